@@ -0,0 +1,177 @@
+//! Alarms shown to the operator
+//!
+//! All alarm conditions are listed in [`ALARMS`], a new source only needs an entry there. The alarm task
+//! evaluates them periodically. The highest-priority alarm that has not been acknowledged is shown as an
+//! overlay on every page, flashes the backlight and drives the optional buzzer or relay output. IncLong
+//! acknowledges all active alarms; an alarm that clears and comes back has to be acknowledged again.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_time::{Duration, Ticker};
+
+use crate::app::config::{AlarmOutput, CONFIG};
+use crate::app::journal::{self, EntryKind};
+use crate::app::maintenance;
+use crate::app::shared::{CardState, Fault, PROCESS_DATA};
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// battery voltage above the absorption voltage of the charge profile that raises an alarm (V)
+const BATTERY_HIGH_MARGIN: f32 = 0.6;
+
+/// bit masks of the active and the acknowledged alarms, one bit per entry of `ALARMS`
+static ACTIVE: AtomicU32 = AtomicU32::new(0);
+static ACKNOWLEDGED: AtomicU32 = AtomicU32::new(0);
+
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum Severity {
+    Info = 0,
+    Warning = 1,
+    Critical = 2,
+}
+
+impl Severity {
+    /// short name for UI
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "INFO",
+            Severity::Warning => "WARNING",
+            Severity::Critical => "ALARM",
+        }
+    }
+
+    /// buzzer pattern, one bit per 100ms step, repeated every 1.6s
+    pub fn pattern(&self) -> u16 {
+        match self {
+            Severity::Info => 0b0000_0000_0000_0001,
+            Severity::Warning => 0b0000_0000_0000_0101,
+            Severity::Critical => 0b0101_0101_0101_0101,
+        }
+    }
+}
+
+/// An entry of the alarm registry
+pub struct AlarmSource {
+    /// reason shown in the overlay, at most 19 characters
+    pub name: &'static str,
+    pub severity: Severity,
+    pub active: fn() -> bool,
+}
+
+fn battery_high() -> bool {
+    let limit = CONFIG.lock(|c| c.borrow().charge_profile.absorption_voltage()) + BATTERY_HIGH_MARGIN;
+    PROCESS_DATA.bat_voltage.load(Ordering::Relaxed) > limit
+}
+
+fn card_failed() -> bool {
+    matches!(PROCESS_DATA.card_state(), CardState::Full | CardState::Error)
+}
+
+/// All alarm conditions, in order of priority within the same severity
+pub static ALARMS: [AlarmSource; 5] = [
+    AlarmSource {
+        name: "Battery high",
+        severity: Severity::Critical,
+        active: battery_high,
+    },
+    AlarmSource {
+        name: "Alt overheated",
+        severity: Severity::Critical,
+        active: || Fault::Overheated.is_active(),
+    },
+    AlarmSource {
+        name: "PPS comm lost",
+        severity: Severity::Warning,
+        active: || Fault::Pps.is_active(),
+    },
+    AlarmSource {
+        name: "SD card failed",
+        severity: Severity::Warning,
+        active: card_failed,
+    },
+    AlarmSource {
+        name: "Maintenance due",
+        severity: Severity::Info,
+        active: || maintenance::first_due().is_some(),
+    },
+];
+
+/// The most severe of the alarms in `mask`, the first in `ALARMS` if several are equally severe
+fn most_severe(mask: u32) -> Option<&'static AlarmSource> {
+    let mut worst: Option<&'static AlarmSource> = None;
+    for (i, alarm) in ALARMS.iter().enumerate() {
+        if mask & (1 << i) != 0 && worst.is_none_or(|w| alarm.severity > w.severity) {
+            worst = Some(alarm);
+        }
+    }
+    worst
+}
+
+/// The alarm to be shown, if any
+pub fn current() -> Option<&'static AlarmSource> {
+    most_severe(ACTIVE.load(Ordering::Relaxed) & !ACKNOWLEDGED.load(Ordering::Relaxed))
+}
+
+/// Acknowledges all active alarms, returns `false` if there was nothing to acknowledge
+pub fn acknowledge() -> bool {
+    let active = ACTIVE.load(Ordering::Relaxed);
+    let previous = ACKNOWLEDGED.fetch_or(active, Ordering::Relaxed);
+    if active & !previous == 0 {
+        return false;
+    }
+    journal::record(EntryKind::Alarm, format_args!("acknowledged"));
+    true
+}
+
+/// Level of the alarm output in 100ms step `step`
+pub fn output(step: u32) -> bool {
+    let Some(alarm) = current() else {
+        return false;
+    };
+    match CONFIG.lock(|c| c.borrow().alarm_output) {
+        AlarmOutput::Off => false,
+        AlarmOutput::Buzzer => alarm.severity.pattern() & (1 << (step % 16)) != 0,
+        AlarmOutput::Relay => alarm.severity >= Severity::Warning,
+    }
+}
+
+/// Evaluates all alarm conditions, journals the ones that were raised
+fn update() {
+    let mask = ALARMS
+        .iter()
+        .enumerate()
+        .filter(|(_, alarm)| (alarm.active)())
+        .fold(0, |mask, (i, _)| mask | 1 << i);
+    let previous = ACTIVE.swap(mask, Ordering::Relaxed);
+    // cleared alarms have to be acknowledged again next time
+    ACKNOWLEDGED.fetch_and(mask, Ordering::Relaxed);
+    for (i, alarm) in ALARMS.iter().enumerate() {
+        if mask & !previous & (1 << i) != 0 {
+            warn!("alarm: {}", alarm.name);
+            journal::record(EntryKind::Alarm, format_args!("{}", alarm.name));
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn alarm_task() -> ! {
+    let mut ticker = Ticker::every(UPDATE_INTERVAL);
+    loop {
+        ticker.next().await;
+        update();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_severe() {
+        assert!(most_severe(0).is_none());
+        assert_eq!(most_severe(0b11000).unwrap().name, "SD card failed");
+        assert_eq!(most_severe(0b10110).unwrap().name, "Alt overheated");
+        assert_eq!(most_severe(0b00011).unwrap().name, "Battery high");
+    }
+}
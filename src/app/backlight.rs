@@ -0,0 +1,86 @@
+//! Backlight brightness, dimmed and finally turned off after a period without button presses
+//!
+//! A button press or an alarm wakes the display. The button press that wakes a dark display is not
+//! passed on, so nothing is changed unseen; the emergency stop (OkShort) is always passed on.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_time::Instant;
+use libm::fminf;
+
+use crate::app::config::{Config, CONFIG};
+
+/// brightness of the dimmed backlight (%), unless the configured brightness is lower
+const DIM_BRIGHTNESS: f32 = 10.;
+
+/// time of the last button press or alarm (s since boot)
+static LAST_ACTIVITY: AtomicU32 = AtomicU32::new(0);
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BacklightState {
+    On,
+    Dimmed,
+    Off,
+}
+
+impl BacklightState {
+    /// State after `idle` seconds without activity
+    fn after(idle: f32, config: &Config) -> Self {
+        let expired = |timeout: f32| timeout > 0. && idle >= timeout;
+        if expired(config.backlight_off_timeout) {
+            BacklightState::Off
+        } else if expired(config.backlight_dim_timeout) {
+            BacklightState::Dimmed
+        } else {
+            BacklightState::On
+        }
+    }
+
+    /// PWM duty (%)
+    pub fn brightness(&self, config: &Config) -> u8 {
+        match self {
+            BacklightState::On => config.backlight_brightness as u8,
+            BacklightState::Dimmed => fminf(config.backlight_brightness, DIM_BRIGHTNESS) as u8,
+            BacklightState::Off => 0,
+        }
+    }
+}
+
+fn now() -> u32 {
+    Instant::now().as_secs() as u32
+}
+
+pub fn state(config: &Config) -> BacklightState {
+    let idle = now().wrapping_sub(LAST_ACTIVITY.load(Ordering::Relaxed));
+    BacklightState::after(idle as f32, config)
+}
+
+/// Restarts the timeouts, returns `true` if the display was off
+pub fn wake() -> bool {
+    let was_off = CONFIG.lock(|c| state(&c.borrow())) == BacklightState::Off;
+    LAST_ACTIVITY.store(now(), Ordering::Relaxed);
+    was_off
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeouts() {
+        let mut config = Config::new();
+        config.backlight_brightness = 80.;
+        config.backlight_dim_timeout = 60.;
+        config.backlight_off_timeout = 300.;
+        assert_eq!(BacklightState::after(59., &config), BacklightState::On);
+        assert_eq!(BacklightState::after(60., &config), BacklightState::Dimmed);
+        assert_eq!(BacklightState::after(300., &config), BacklightState::Off);
+        assert_eq!(BacklightState::On.brightness(&config), 80);
+        assert_eq!(BacklightState::Dimmed.brightness(&config), 10);
+
+        // 0 disables a timeout
+        config.backlight_dim_timeout = 0.;
+        config.backlight_off_timeout = 0.;
+        assert_eq!(BacklightState::after(1e6, &config), BacklightState::On);
+    }
+}
@@ -0,0 +1,206 @@
+//! Wall clock time
+//!
+//! The wall clock is kept as the Unix time at boot, so reading it only needs the embassy uptime. It stays
+//! unknown until a time source has set it: the RTC on the PPS I2C bus at boot, or the clock entries of the
+//! settings menu, which also write the RTC.
+
+use core::cell::Cell;
+use core::fmt;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+
+/// Unix time in ms at boot, `None` while the wall clock is unknown
+static BOOT_TIME_MS: Mutex<CriticalSectionRawMutex, Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+
+/// Wakes up the PPS task to write a new wall clock time to the RTC
+pub static RTC_SET: Signal<CriticalSectionRawMutex, DateTime> = Signal::new();
+
+/// Length of the ISO 8601 representation, e.g. `2024-06-01T12:34:56.789Z`
+pub const ISO_LEN: usize = 24;
+
+const SECONDS_PER_DAY: u64 = 24 * 3600;
+
+/// Days between 0000-03-01 and 1970-01-01 in the proleptic Gregorian calendar
+const UNIX_EPOCH_DAYS: u64 = 719_468;
+const DAYS_PER_ERA: u64 = 146_097;
+
+/// Calendar date and time of day (UTC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub millis: u16,
+}
+
+impl DateTime {
+    /// Converts Unix time in ms to a calendar date
+    pub fn from_unix_ms(unix_ms: u64) -> Self {
+        let secs = unix_ms / 1000;
+        let time = secs % SECONDS_PER_DAY;
+
+        // civil_from_days, see http://howardhinnant.github.io/date_algorithms.html
+        let days = secs / SECONDS_PER_DAY + UNIX_EPOCH_DAYS;
+        let era = days / DAYS_PER_ERA;
+        let doe = days - era * DAYS_PER_ERA;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as u64;
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time % 3600 / 60) as u8,
+            second: (time % 60) as u8,
+            millis: (unix_ms % 1000) as u16,
+        }
+    }
+
+    /// Converts the calendar date to Unix time in ms, the date must be valid and not before 1970
+    pub fn to_unix_ms(&self) -> u64 {
+        // days_from_civil, see http://howardhinnant.github.io/date_algorithms.html
+        let month = self.month as u64;
+        let year = self.year as u64 - (month <= 2) as u64;
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let doy = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + self.day as u64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * DAYS_PER_ERA + doe - UNIX_EPOCH_DAYS;
+
+        let secs = days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        secs * 1000 + self.millis as u64
+    }
+
+    /// Checks the fields, e.g. of a time read from an RTC that might have lost power
+    pub fn is_valid(&self) -> bool {
+        (1970..=2099).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+            && self.millis < 1000
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.millis
+        )
+    }
+}
+
+/// Number of days of `month` (1 to 12)
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Sets the wall clock from a time source
+pub fn set(time: &DateTime) {
+    let boot_ms = time.to_unix_ms().saturating_sub(Instant::now().as_millis());
+    BOOT_TIME_MS.lock(|b| b.set(Some(boot_ms)));
+}
+
+/// Sets the wall clock and the RTC, e.g. from a manual entry
+pub fn set_and_store(time: &DateTime) {
+    set(time);
+    RTC_SET.signal(*time);
+}
+
+/// Unix time in ms at boot, `None` if the wall clock has not been set yet
+pub fn boot_unix_ms() -> Option<u64> {
+    BOOT_TIME_MS.lock(|b| b.get())
+}
+
+/// Current wall clock time, `None` if it has not been set yet
+pub fn now() -> Option<DateTime> {
+    at(Instant::now())
+}
+
+/// Wall clock time of an instant, `None` if the wall clock has not been set yet
+pub fn at(instant: Instant) -> Option<DateTime> {
+    boot_unix_ms().map(|boot_ms| DateTime::from_unix_ms(boot_ms + instant.as_millis()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoch() {
+        let t = DateTime::from_unix_ms(0);
+        assert_eq!(
+            (t.year, t.month, t.day, t.hour, t.minute, t.second),
+            (1970, 1, 1, 0, 0, 0)
+        );
+        assert_eq!(t.to_unix_ms(), 0);
+    }
+
+    #[test]
+    fn test_known_date() {
+        // 2024-02-29T12:34:56.789Z
+        let t = DateTime::from_unix_ms(1_709_210_096_789);
+        assert_eq!((t.year, t.month, t.day), (2024, 2, 29));
+        assert_eq!((t.hour, t.minute, t.second, t.millis), (12, 34, 56, 789));
+        assert!(t.is_valid());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut ms = 0;
+        while ms < 4_102_444_800_000 {
+            // until 2100
+            assert_eq!(DateTime::from_unix_ms(ms).to_unix_ms(), ms);
+            ms += 86_399_123;
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        let t = DateTime::from_unix_ms(1_709_210_096_789);
+        assert!(!DateTime { day: 30, ..t }.is_valid());
+        assert!(!DateTime { month: 0, ..t }.is_valid());
+        assert!(!DateTime {
+            year: 2023,
+            day: 29,
+            ..t
+        }
+        .is_valid());
+        assert!(DateTime {
+            year: 2000,
+            day: 29,
+            ..t
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_iso() {
+        let t = DateTime::from_unix_ms(1_709_210_096_789);
+        let s = heapless::format!({ ISO_LEN }; "{}", t).unwrap();
+        assert_eq!(s, "2024-02-29T12:34:56.789Z");
+        assert_eq!(s.len(), ISO_LEN);
+    }
+}
@@ -0,0 +1,712 @@
+//! Persistent configuration of the regulator
+//!
+//! The active configuration lives in [`CONFIG`]. Changes are made through [`modify`], which validates
+//! the new values and signals the config task to write them to flash.
+
+use binlog::crc::crc32;
+use core::cell::RefCell;
+use core::fmt;
+use core::ops::RangeInclusive;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use libm::fminf;
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::{FromPrimitive, ToPrimitive};
+use thiserror_no_std::Error;
+
+use crate::app::journal::{self, EntryKind};
+use crate::app::maintenance::MAINTENANCE_ITEMS;
+use crate::app::shared::RPM_MIN;
+use crate::app::victron::KNOWN_DEVICE_COUNT;
+
+pub static CONFIG: Mutex<CriticalSectionRawMutex, RefCell<Config>> = Mutex::new(RefCell::new(Config::new()));
+
+/// Wakes up the config task to persist the current configuration
+pub static CONFIG_SAVE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+const DEFAULT_POLE_PAIRS: f32 = 6.;
+const DEFAULT_PULLEY_RATIO: f32 = 53.7 / 128.2;
+
+#[derive(Debug, Error)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    #[error("no configuration found")]
+    NotFound,
+
+    #[error("configuration checksum mismatch")]
+    Checksum,
+
+    #[error("configuration buffer too small")]
+    BufferTooSmall,
+
+    #[error("parameter out of range: {0}")]
+    OutOfRange(&'static str),
+
+    #[error("parameters inconsistent: {0}")]
+    Inconsistent(&'static str),
+}
+
+/// Battery chemistry, determines voltage and charge current limits
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq, Default)]
+pub enum ChargeProfile {
+    #[default]
+    LeadAcid = 0,
+    Agm = 1,
+    LiFePo4 = 2,
+}
+
+impl ChargeProfile {
+    /// short name for UI
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChargeProfile::LeadAcid => "Lead acid",
+            ChargeProfile::Agm => "AGM",
+            ChargeProfile::LiFePo4 => "LiFePO4",
+        }
+    }
+
+    /// maximum charge current relative to the battery capacity (1/h)
+    pub fn max_c_rate(&self) -> f32 {
+        match self {
+            ChargeProfile::LeadAcid => 0.25,
+            ChargeProfile::Agm => 0.3,
+            ChargeProfile::LiFePo4 => 0.5,
+        }
+    }
+
+    /// absorption voltage of a 12V system (V)
+    pub fn absorption_voltage(&self) -> f32 {
+        match self {
+            ChargeProfile::LeadAcid => 14.4,
+            ChargeProfile::Agm => 14.6,
+            ChargeProfile::LiFePo4 => 14.2,
+        }
+    }
+}
+
+/// File format of the data log
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq, Default)]
+pub enum LogFormat {
+    /// human readable, one line per second is about the limit
+    #[default]
+    Csv = 0,
+    /// compact CRC protected records, see the `binlog` crate; allows logging at 10 Hz
+    Binary = 1,
+}
+
+impl LogFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogFormat::Csv => "CSV",
+            LogFormat::Binary => "Binary",
+        }
+    }
+}
+
+/// What the alarm output GPIO is connected to
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq, Default)]
+pub enum AlarmOutput {
+    /// nothing, the output stays low
+    #[default]
+    Off = 0,
+    /// active buzzer, beeps in the pattern of the alarm severity
+    Buzzer = 1,
+    /// relay or lamp, on while a warning or critical alarm is not acknowledged
+    Relay = 2,
+}
+
+impl AlarmOutput {
+    /// short name for UI
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlarmOutput::Off => "Off",
+            AlarmOutput::Buzzer => "Buzzer",
+            AlarmOutput::Relay => "Relay",
+        }
+    }
+}
+
+/// Unit of a maintenance interval
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq, Default)]
+pub enum IntervalUnit {
+    /// engine run hours
+    #[default]
+    Hours = 0,
+    /// charge delivered by the alternator
+    AmpHours = 1,
+}
+
+/// Interval of a maintenance item, see `maintenance::MaintenanceItem`
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MaintenanceInterval {
+    pub unit: IntervalUnit,
+    /// 0 disables the reminder
+    pub threshold: f32,
+}
+
+/// All parameters that can be changed at runtime and survive a reboot
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// alternator W-terminal pulses per engine revolution (pole pairs and pulley ratio combined)
+    pub rpm_pulses_per_rev: f32,
+
+    /// engine RPM below which no charging is possible (lower bound of the high idle band)
+    pub rpm_min: f32,
+
+    /// engine RPM above which normal charging starts (upper bound of the high idle band)
+    pub rpm_normal: f32,
+
+    /// relative hysteresis of the RPM thresholds (0.05 -> dead band of +-5%)
+    pub rpm_hysteresis: f32,
+
+    /// derating factor applied while the engine runs in the high idle band
+    pub high_idle_derating: f32,
+
+    /// engine RPM at which the low-RPM field ramp reaches full field current
+    pub field_ramp_rpm: f32,
+
+    /// field current factor of the low-RPM field ramp at `rpm_min`
+    pub field_ramp_start: f32,
+
+    /// no charging load for this time (s) after the RPM first crossed `rpm_min`
+    pub engine_start_delay: f32,
+
+    /// maximum rate of rise of the field current (A/s)
+    pub field_slew_up: f32,
+
+    /// maximum rate of fall of the field current (A/s), not applied to an emergency stop
+    pub field_slew_down: f32,
+
+    /// alternator output current limit (A), requires an alternator current measurement
+    pub alt_current_limit: f32,
+
+    /// rated output current of the alternator (A)
+    pub alt_rated_current: f32,
+
+    /// small alternator mode - cap the output at `small_alt_percent` of the rated current
+    pub small_alt_mode: bool,
+
+    /// output cap in small alternator mode (% of rated current)
+    pub small_alt_percent: f32,
+
+    /// battery chemistry of the house bank
+    pub charge_profile: ChargeProfile,
+
+    /// capacity of the house bank (Ah)
+    pub battery_capacity: f32,
+
+    /// alternator temperature (°C) above which the field current is derated
+    pub alt_temp_derate: f32,
+
+    /// alternator temperature (°C) at which the field current reaches zero
+    pub alt_temp_max: f32,
+
+    /// a new log file is started once the current one exceeds this size (kB)
+    pub log_file_max_kb: f32,
+
+    /// a new log file is started once the current one is older than this (min)
+    pub log_file_max_minutes: f32,
+
+    /// space the numbered files in the log directory may occupy on the SD card (MB), the oldest are deleted first
+    ///
+    /// Data logs, flight recordings and screenshots count towards it. This is a quota, not the free space of
+    /// the card: the journal and files outside the log directory are not counted and never deleted. The quota
+    /// is capped at 90% of the card size.
+    pub log_quota_mb: f32,
+
+    /// file format of new log files
+    pub log_format: LogFormat,
+
+    /// time between two log records (s)
+    pub log_interval: f32,
+
+    /// logged fields, one bit per field in the order of the log columns, see `LoggerMeta`
+    pub log_columns: u32,
+
+    /// service intervals of belt, brushes and bearings, in the order of `MaintenanceItem`
+    pub maintenance: [MaintenanceInterval; MAINTENANCE_ITEMS],
+
+    /// Victron device measuring the battery, index into `victron::KNOWN_DEVICES`; applied after a restart
+    pub victron_battery: u8,
+
+    /// Victron device measuring the alternator output, index into `victron::KNOWN_DEVICES`
+    pub victron_alternator: u8,
+
+    /// device connected to the alarm output
+    pub alarm_output: AlarmOutput,
+
+    /// backlight brightness while the display is in use (%)
+    pub backlight_brightness: f32,
+
+    /// the backlight is dimmed after this time (s) without a button press, 0 disables dimming
+    pub backlight_dim_timeout: f32,
+
+    /// the backlight is turned off after this time (s) without a button press, 0 keeps it on
+    pub backlight_off_timeout: f32,
+
+    /// red-on-black theme for use at night
+    pub night_theme: bool,
+}
+
+impl Config {
+    pub const RPM_PULSES_PER_REV_RANGE: RangeInclusive<f32> = 1.0..=200.0;
+    pub const RPM_THRESHOLD_RANGE: RangeInclusive<f32> = 200.0..=4000.0;
+    pub const RPM_HYSTERESIS_RANGE: RangeInclusive<f32> = 0.01..=0.3;
+    pub const FACTOR_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+    pub const START_DELAY_RANGE: RangeInclusive<f32> = 0.0..=120.0;
+    pub const FIELD_SLEW_RANGE: RangeInclusive<f32> = 0.05..=30.0;
+    pub const ALT_CURRENT_RANGE: RangeInclusive<f32> = 10.0..=500.0;
+    pub const PERCENT_RANGE: RangeInclusive<f32> = 10.0..=100.0;
+    pub const BATTERY_CAPACITY_RANGE: RangeInclusive<f32> = 10.0..=2000.0;
+    pub const TEMPERATURE_RANGE: RangeInclusive<f32> = 40.0..=150.0;
+    pub const LOG_FILE_SIZE_RANGE: RangeInclusive<f32> = 64.0..=65536.0;
+    pub const LOG_FILE_DURATION_RANGE: RangeInclusive<f32> = 10.0..=10080.0;
+    pub const LOG_QUOTA_RANGE: RangeInclusive<f32> = 16.0..=65536.0;
+    pub const LOG_INTERVAL_RANGE: RangeInclusive<f32> = 0.1..=60.0;
+    pub const MAINTENANCE_INTERVAL_RANGE: RangeInclusive<f32> = 0.0..=1_000_000.0;
+    pub const VICTRON_DEVICE_RANGE: RangeInclusive<f32> = 0.0..=(KNOWN_DEVICE_COUNT - 1) as f32;
+    pub const BACKLIGHT_TIMEOUT_RANGE: RangeInclusive<f32> = 0.0..=3600.0;
+
+    /// size of the serialized configuration, including header and checksum
+    pub const SERIALIZED_LEN: usize = 256;
+    const MAGIC: u32 = 0x4346_4741; // "AGFC"
+
+    pub const fn new() -> Self {
+        Self {
+            // 2 pulses per pole pair and alternator revolution, alternator is driven faster than the engine
+            rpm_pulses_per_rev: 2. * DEFAULT_POLE_PAIRS / DEFAULT_PULLEY_RATIO,
+            rpm_min: RPM_MIN as f32,
+            rpm_normal: 1200.,
+            rpm_hysteresis: 0.05,
+            high_idle_derating: 0.6,
+            field_ramp_rpm: 900.,
+            field_ramp_start: 0.3,
+            engine_start_delay: 10.,
+            field_slew_up: 0.3,
+            field_slew_down: 1.0,
+            alt_current_limit: 120.,
+            alt_rated_current: 150.,
+            small_alt_mode: false,
+            small_alt_percent: 70.,
+            charge_profile: ChargeProfile::LeadAcid,
+            battery_capacity: 200.,
+            alt_temp_derate: 90.,
+            alt_temp_max: 110.,
+            log_file_max_kb: 4096.,
+            log_file_max_minutes: 1440.,
+            log_quota_mb: 2048.,
+            log_format: LogFormat::Csv,
+            log_interval: 1.,
+            log_columns: u32::MAX,
+            maintenance: [
+                // belt check
+                MaintenanceInterval {
+                    unit: IntervalUnit::Hours,
+                    threshold: 250.,
+                },
+                // brush replacement
+                MaintenanceInterval {
+                    unit: IntervalUnit::Hours,
+                    threshold: 2000.,
+                },
+                // bearing service
+                MaintenanceInterval {
+                    unit: IntervalUnit::AmpHours,
+                    threshold: 200_000.,
+                },
+            ],
+            victron_battery: 0,
+            victron_alternator: 1,
+            alarm_output: AlarmOutput::Off,
+            backlight_brightness: 100.,
+            backlight_dim_timeout: 120.,
+            backlight_off_timeout: 0.,
+            night_theme: false,
+        }
+    }
+
+    /// battery charge current limit (A), the maximum C-rate of the charge profile
+    ///
+    /// There is no limit reported by a BMS: the Victron battery monitors read over BLE do not advertise
+    /// one, and the board has no CAN interface to a BMS.
+    pub fn bat_current_limit(&self) -> f32 {
+        self.battery_capacity * self.charge_profile.max_c_rate()
+    }
+
+    /// alternator output current limit (A), including the small alternator cap
+    pub fn effective_alt_current_limit(&self) -> f32 {
+        if self.small_alt_mode {
+            fminf(self.alt_current_limit, self.alt_rated_current * self.small_alt_percent / 100.)
+        } else {
+            self.alt_current_limit
+        }
+    }
+
+    /// Checks all parameters against their valid ranges
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_range("rpm_pulses_per_rev", self.rpm_pulses_per_rev, &Self::RPM_PULSES_PER_REV_RANGE)?;
+        check_range("rpm_min", self.rpm_min, &Self::RPM_THRESHOLD_RANGE)?;
+        check_range("rpm_normal", self.rpm_normal, &Self::RPM_THRESHOLD_RANGE)?;
+        check_range("rpm_hysteresis", self.rpm_hysteresis, &Self::RPM_HYSTERESIS_RANGE)?;
+        check_range("high_idle_derating", self.high_idle_derating, &Self::FACTOR_RANGE)?;
+        check_range("field_ramp_rpm", self.field_ramp_rpm, &Self::RPM_THRESHOLD_RANGE)?;
+        check_range("field_ramp_start", self.field_ramp_start, &Self::FACTOR_RANGE)?;
+        check_range("engine_start_delay", self.engine_start_delay, &Self::START_DELAY_RANGE)?;
+        check_range("field_slew_up", self.field_slew_up, &Self::FIELD_SLEW_RANGE)?;
+        check_range("field_slew_down", self.field_slew_down, &Self::FIELD_SLEW_RANGE)?;
+        check_range("alt_current_limit", self.alt_current_limit, &Self::ALT_CURRENT_RANGE)?;
+        check_range("alt_rated_current", self.alt_rated_current, &Self::ALT_CURRENT_RANGE)?;
+        check_range("small_alt_percent", self.small_alt_percent, &Self::PERCENT_RANGE)?;
+        check_range("battery_capacity", self.battery_capacity, &Self::BATTERY_CAPACITY_RANGE)?;
+        check_range("alt_temp_derate", self.alt_temp_derate, &Self::TEMPERATURE_RANGE)?;
+        check_range("alt_temp_max", self.alt_temp_max, &Self::TEMPERATURE_RANGE)?;
+        check_range("log_file_max_kb", self.log_file_max_kb, &Self::LOG_FILE_SIZE_RANGE)?;
+        check_range("log_file_max_minutes", self.log_file_max_minutes, &Self::LOG_FILE_DURATION_RANGE)?;
+        check_range("log_quota_mb", self.log_quota_mb, &Self::LOG_QUOTA_RANGE)?;
+        check_range("log_interval", self.log_interval, &Self::LOG_INTERVAL_RANGE)?;
+        for interval in &self.maintenance {
+            check_range("maintenance", interval.threshold, &Self::MAINTENANCE_INTERVAL_RANGE)?;
+        }
+        check_range("victron_battery", self.victron_battery as f32, &Self::VICTRON_DEVICE_RANGE)?;
+        check_range("victron_alternator", self.victron_alternator as f32, &Self::VICTRON_DEVICE_RANGE)?;
+        check_range("backlight_brightness", self.backlight_brightness, &Self::PERCENT_RANGE)?;
+        check_range("backlight_dim_timeout", self.backlight_dim_timeout, &Self::BACKLIGHT_TIMEOUT_RANGE)?;
+        check_range("backlight_off_timeout", self.backlight_off_timeout, &Self::BACKLIGHT_TIMEOUT_RANGE)?;
+
+        // the dead bands of both RPM thresholds must not overlap
+        if self.rpm_min * (1. + self.rpm_hysteresis) >= self.rpm_normal * (1. - self.rpm_hysteresis) {
+            return Err(ConfigError::Inconsistent("rpm_min/rpm_normal"));
+        }
+        if self.field_ramp_rpm < self.rpm_min {
+            return Err(ConfigError::Inconsistent("field_ramp_rpm"));
+        }
+        if self.alt_temp_max <= self.alt_temp_derate {
+            return Err(ConfigError::Inconsistent("alt_temp_max"));
+        }
+        Ok(())
+    }
+
+    /// Serializes the configuration into `buf`, returns the number of bytes used
+    ///
+    /// Fields are appended in a fixed order. New fields must only ever be added at the end, so older
+    /// firmware versions and older flash contents remain readable.
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, ConfigError> {
+        let mut w = Writer::new(buf);
+        w.put_f32(self.rpm_pulses_per_rev)?;
+        w.put_f32(self.rpm_min)?;
+        w.put_f32(self.rpm_normal)?;
+        w.put_f32(self.rpm_hysteresis)?;
+        w.put_f32(self.high_idle_derating)?;
+        w.put_f32(self.field_ramp_rpm)?;
+        w.put_f32(self.field_ramp_start)?;
+        w.put_f32(self.engine_start_delay)?;
+        w.put_f32(self.field_slew_up)?;
+        w.put_f32(self.field_slew_down)?;
+        w.put_f32(self.alt_current_limit)?;
+        w.put_f32(self.alt_rated_current)?;
+        w.put_bool(self.small_alt_mode)?;
+        w.put_f32(self.small_alt_percent)?;
+        w.put_enum(self.charge_profile)?;
+        w.put_f32(self.battery_capacity)?;
+        w.put_f32(self.alt_temp_derate)?;
+        w.put_f32(self.alt_temp_max)?;
+        w.put_f32(self.log_file_max_kb)?;
+        w.put_f32(self.log_file_max_minutes)?;
+        w.put_f32(self.log_quota_mb)?;
+        w.put_enum(self.log_format)?;
+        w.put_f32(self.log_interval)?;
+        w.put_u32(self.log_columns)?;
+        for interval in &self.maintenance {
+            w.put_enum(interval.unit)?;
+            w.put_f32(interval.threshold)?;
+        }
+        w.put_enum(self.victron_battery)?;
+        w.put_enum(self.victron_alternator)?;
+        w.put_enum(self.alarm_output)?;
+        w.put_f32(self.backlight_brightness)?;
+        w.put_f32(self.backlight_dim_timeout)?;
+        w.put_f32(self.backlight_off_timeout)?;
+        w.put_bool(self.night_theme)?;
+        w.finish(Self::MAGIC)
+    }
+
+    /// Restores a configuration from `buf`
+    ///
+    /// Fields missing in `buf` (written by an older firmware) keep their default values.
+    pub fn deserialize(buf: &[u8]) -> Result<Self, ConfigError> {
+        let mut r = Reader::new(buf, Self::MAGIC)?;
+        let mut config = Self::new();
+        r.f32(&mut config.rpm_pulses_per_rev);
+        r.f32(&mut config.rpm_min);
+        r.f32(&mut config.rpm_normal);
+        r.f32(&mut config.rpm_hysteresis);
+        r.f32(&mut config.high_idle_derating);
+        r.f32(&mut config.field_ramp_rpm);
+        r.f32(&mut config.field_ramp_start);
+        r.f32(&mut config.engine_start_delay);
+        r.f32(&mut config.field_slew_up);
+        r.f32(&mut config.field_slew_down);
+        r.f32(&mut config.alt_current_limit);
+        r.f32(&mut config.alt_rated_current);
+        r.bool(&mut config.small_alt_mode);
+        r.f32(&mut config.small_alt_percent);
+        r.enumeration(&mut config.charge_profile);
+        r.f32(&mut config.battery_capacity);
+        r.f32(&mut config.alt_temp_derate);
+        r.f32(&mut config.alt_temp_max);
+        r.f32(&mut config.log_file_max_kb);
+        r.f32(&mut config.log_file_max_minutes);
+        r.f32(&mut config.log_quota_mb);
+        r.enumeration(&mut config.log_format);
+        r.f32(&mut config.log_interval);
+        r.u32(&mut config.log_columns);
+        for interval in &mut config.maintenance {
+            r.enumeration(&mut interval.unit);
+            r.f32(&mut interval.threshold);
+        }
+        r.enumeration(&mut config.victron_battery);
+        r.enumeration(&mut config.victron_alternator);
+        r.enumeration(&mut config.alarm_output);
+        r.f32(&mut config.backlight_brightness);
+        r.f32(&mut config.backlight_dim_timeout);
+        r.f32(&mut config.backlight_off_timeout);
+        r.bool(&mut config.night_theme);
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Applies `f` to a copy of the active configuration and activates it if it passes validation
+///
+/// `f` changes the parameter `name`, read by `get` to journal the old and the new value. The new
+/// configuration is persisted asynchronously by the config task.
+pub fn modify<T: fmt::Display>(
+    name: &str,
+    get: impl Fn(&Config) -> T,
+    f: impl FnOnce(&mut Config),
+) -> Result<(), ConfigError> {
+    let (old, new) = CONFIG.lock(|c| {
+        let mut config = c.borrow().clone();
+        f(&mut config);
+        config.validate()?;
+        let old = get(&c.replace(config));
+        Ok::<_, ConfigError>((old, get(&c.borrow())))
+    })?;
+    journal::record(EntryKind::ConfigChanged, format_args!("{}: {} -> {}", name, old, new));
+    CONFIG_SAVE.signal(());
+    Ok(())
+}
+
+fn check_range(name: &'static str, value: f32, range: &RangeInclusive<f32>) -> Result<(), ConfigError> {
+    if range.contains(&value) {
+        Ok(())
+    } else {
+        warn!("config parameter {} out of range: {}", name, value);
+        Err(ConfigError::OutOfRange(name))
+    }
+}
+
+/// Header of a serialized record: magic, payload length
+const HEADER_LEN: usize = 4 + 2;
+const CRC_LEN: usize = 4;
+
+/// Serializes a record for flash: header, fields, CRC
+pub(crate) struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: HEADER_LEN }
+    }
+
+    /// Writes header and checksum, returns the length of the record
+    pub(crate) fn finish(self, magic: u32) -> Result<usize, ConfigError> {
+        let payload_len = self.pos - HEADER_LEN;
+        let crc_pos = self.pos;
+        let buf = self.buf;
+        buf[0..4].copy_from_slice(&magic.to_le_bytes());
+        buf[4..6].copy_from_slice(&(payload_len as u16).to_le_bytes());
+        let crc = crc32(&buf[..crc_pos]);
+        buf.get_mut(crc_pos..crc_pos + CRC_LEN)
+            .ok_or(ConfigError::BufferTooSmall)?
+            .copy_from_slice(&crc.to_le_bytes());
+        Ok(crc_pos + CRC_LEN)
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), ConfigError> {
+        self.buf
+            .get_mut(self.pos..self.pos + bytes.len())
+            .ok_or(ConfigError::BufferTooSmall)?
+            .copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Ok(())
+    }
+
+    pub(crate) fn put_f32(&mut self, value: f32) -> Result<(), ConfigError> {
+        self.put(&value.to_le_bytes())
+    }
+
+    pub(crate) fn put_f64(&mut self, value: f64) -> Result<(), ConfigError> {
+        self.put(&value.to_le_bytes())
+    }
+
+    pub(crate) fn put_u32(&mut self, value: u32) -> Result<(), ConfigError> {
+        self.put(&value.to_le_bytes())
+    }
+
+    pub(crate) fn put_bool(&mut self, value: bool) -> Result<(), ConfigError> {
+        self.put(&[value as u8])
+    }
+
+    pub(crate) fn put_enum<T: ToPrimitive>(&mut self, value: T) -> Result<(), ConfigError> {
+        self.put(&[value.to_u8().unwrap_or_default()])
+    }
+}
+
+/// Reads the fields of a record written by [`Writer`]
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Checks header and checksum of the record in `buf`
+    pub(crate) fn new(buf: &'a [u8], magic: u32) -> Result<Self, ConfigError> {
+        let header = buf.get(..HEADER_LEN).ok_or(ConfigError::NotFound)?;
+        if u32::from_le_bytes([header[0], header[1], header[2], header[3]]) != magic {
+            return Err(ConfigError::NotFound);
+        }
+        let payload_len = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let crc_pos = HEADER_LEN + payload_len;
+        let crc_bytes = buf.get(crc_pos..crc_pos + CRC_LEN).ok_or(ConfigError::BufferTooSmall)?;
+        if crc32(&buf[..crc_pos]) != u32::from_le_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]) {
+            return Err(ConfigError::Checksum);
+        }
+        Ok(Self {
+            buf: &buf[HEADER_LEN..crc_pos],
+        })
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (head, tail) = self.buf.split_at_checked(N)?;
+        self.buf = tail;
+        head.try_into().ok()
+    }
+
+    /// overwrites `value` if the field is present
+    pub(crate) fn f32(&mut self, value: &mut f32) {
+        if let Some(bytes) = self.take::<4>() {
+            *value = f32::from_le_bytes(bytes);
+        }
+    }
+
+    /// overwrites `value` if the field is present
+    pub(crate) fn f64(&mut self, value: &mut f64) {
+        if let Some(bytes) = self.take::<8>() {
+            *value = f64::from_le_bytes(bytes);
+        }
+    }
+
+    /// overwrites `value` if the field is present
+    pub(crate) fn u32(&mut self, value: &mut u32) {
+        if let Some(bytes) = self.take::<4>() {
+            *value = u32::from_le_bytes(bytes);
+        }
+    }
+
+    /// overwrites `value` if the field is present
+    pub(crate) fn bool(&mut self, value: &mut bool) {
+        if let Some([byte]) = self.take::<1>() {
+            *value = byte != 0;
+        }
+    }
+
+    /// overwrites `value` if the field is present and a valid variant
+    pub(crate) fn enumeration<T: FromPrimitive>(&mut self, value: &mut T) {
+        if let Some(v) = self.take::<1>().and_then(|[byte]| T::from_u8(byte)) {
+            *value = v;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut config = Config::new();
+        config.rpm_pulses_per_rev = 42.5;
+        config.small_alt_mode = true;
+        config.charge_profile = ChargeProfile::LiFePo4;
+        config.log_format = LogFormat::Binary;
+        config.log_columns = 0b1010_0101;
+        config.victron_battery = 2;
+        config.alarm_output = AlarmOutput::Relay;
+        config.backlight_off_timeout = 600.;
+        config.night_theme = true;
+        config.maintenance[1] = MaintenanceInterval {
+            unit: IntervalUnit::AmpHours,
+            threshold: 5000.,
+        };
+        let mut buf = [0xff_u8; Config::SERIALIZED_LEN];
+        config.serialize(&mut buf).unwrap();
+        assert_eq!(Config::deserialize(&buf).unwrap(), config);
+    }
+
+    #[test]
+    fn test_erased_flash() {
+        let buf = [0xff_u8; Config::SERIALIZED_LEN];
+        assert!(matches!(Config::deserialize(&buf), Err(ConfigError::NotFound)));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let mut buf = [0xff_u8; Config::SERIALIZED_LEN];
+        Config::new().serialize(&mut buf).unwrap();
+        buf[HEADER_LEN] ^= 0x01;
+        assert!(matches!(Config::deserialize(&buf), Err(ConfigError::Checksum)));
+    }
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        let mut buf = [0xff_u8; Config::SERIALIZED_LEN];
+        buf[0..4].copy_from_slice(&Config::MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&0_u16.to_le_bytes());
+        let crc = crc32(&buf[..HEADER_LEN]);
+        buf[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(Config::deserialize(&buf).unwrap(), Config::new());
+    }
+
+    #[test]
+    fn test_validate_overlapping_rpm_bands() {
+        let mut config = Config::new();
+        config.rpm_normal = config.rpm_min * 1.05;
+        assert!(matches!(config.validate(), Err(ConfigError::Inconsistent(_))));
+    }
+
+    #[test]
+    fn test_validate_out_of_range() {
+        let mut config = Config::new();
+        config.rpm_pulses_per_rev = 0.;
+        assert!(config.validate().is_err());
+    }
+}
@@ -1,10 +1,14 @@
 use core::cmp::min;
 use core::sync::atomic::Ordering;
-use embassy_time::{Duration, Ticker};
-use libm::{floorf, fmaxf};
+use embassy_time::{Duration, Instant, Ticker};
+use libm::{floorf, fmaxf, fminf};
 
+use crate::app::config::CONFIG;
+use crate::app::limit::Limiter;
+use crate::app::recorder;
 use crate::app::shared::{
-    PpsSetMode, CONTROLLER, MAX_FIELD_CURRENT, MAX_FIELD_VOLTAGE, PROCESS_DATA, RPM_MAX, RPM_MIN, SETPOINT,
+    ActiveLimit, PpsSetMode, RpmEvent, CONTROLLER, MAX_FIELD_CURRENT, MAX_FIELD_VOLTAGE, PROCESS_DATA, RPM_MAX, RPM_MIN,
+    SETPOINT,
 };
 
 #[derive(Debug)]
@@ -20,6 +24,24 @@ pub struct Controller {
 
     /// charging in progress
     charge: bool,
+
+    /// engine runs in the high idle band, charging is derated
+    high_idle: bool,
+
+    /// time the RPM crossed the minimum RPM, `None` while the engine is not running
+    engine_start: Option<Instant>,
+
+    /// slew rate limited field current, as sent to the PPS
+    field_current: f32,
+
+    /// outer loop holding the battery voltage at the absorption voltage of the charge profile
+    voltage_limiter: Limiter,
+
+    /// outer loop holding the battery charge current at the C-rate limit
+    bat_current_limiter: Limiter,
+
+    /// outer loop holding the alternator output current at its limit
+    alt_current_limiter: Limiter,
 }
 
 #[allow(dead_code)]
@@ -28,7 +50,9 @@ impl Controller {
     const RPM_STEP: usize = 100;
     const RPM_ARRAY_SIZE: usize = RPM_MAX / Controller::RPM_STEP;
     const RPM_FACTOR: [f32; Controller::RPM_ARRAY_SIZE] = Controller::const_rpm_factor();
-    const LOOP_INTERVAL_MS: u64 = 100;
+    pub const LOOP_INTERVAL_MS: u64 = 100;
+    const CURRENT_LIMIT_GAIN: f32 = 0.5; // 1/s, relative to the current limit
+    const VOLTAGE_LIMIT_GAIN: f32 = 20.; // 1/s, relative to the voltage limit (0.1V error at 14.4V -> 0.14/s)
 
     pub const fn new() -> Self {
         Self {
@@ -36,6 +60,12 @@ impl Controller {
             target: 0.,
             idle: false,
             charge: false,
+            high_idle: false,
+            engine_start: None,
+            field_current: 0.,
+            voltage_limiter: Limiter::new(Controller::VOLTAGE_LIMIT_GAIN),
+            bat_current_limiter: Limiter::new(Controller::CURRENT_LIMIT_GAIN),
+            alt_current_limiter: Limiter::new(Controller::CURRENT_LIMIT_GAIN),
         }
     }
 
@@ -57,6 +87,23 @@ impl Controller {
         self.derating = derating;
     }
 
+    pub fn set_rpm_band(&mut self, band: RpmEvent) {
+        let high_idle = band == RpmEvent::HighIdle;
+        if high_idle != self.high_idle {
+            info!("high idle: {}", high_idle);
+        }
+        self.high_idle = high_idle;
+
+        match (band, self.engine_start) {
+            (RpmEvent::Low, _) => self.engine_start = None,
+            (_, None) => {
+                info!("engine started");
+                self.engine_start = Some(Instant::now());
+            }
+            _ => (),
+        }
+    }
+
     pub fn start_idle(&mut self) {
         debug!("starting idle");
         SETPOINT.field_voltage_limit.store(MAX_FIELD_VOLTAGE, Ordering::Relaxed);
@@ -72,10 +119,12 @@ impl Controller {
         self.charge = true;
     }
 
+    /// emergency stop - the field current is switched off immediately, without ramp
     pub fn stop(&mut self) {
         debug!("stopping");
         self.idle = false;
         self.charge = false;
+        self.field_current = 0.;
         SETPOINT.field_voltage_limit.store(0., Ordering::Relaxed);
         SETPOINT.pps_enabled.store(PpsSetMode::Off as u8, Ordering::Relaxed);
     }
@@ -96,6 +145,49 @@ impl Controller {
         f
     }
 
+    /// Low-RPM field ramp, avoids stalling a small engine by the alternator torque right after start
+    ///
+    /// Returns `start` at or below `rpm_min` (or without valid RPM), rising linearly to 1.0 at `ramp_rpm`.
+    fn field_ramp_factor(rpm: f32, rpm_min: f32, ramp_rpm: f32, start: f32) -> f32 {
+        if rpm >= ramp_rpm {
+            1.
+        } else if rpm.is_nan() || rpm <= rpm_min {
+            start
+        } else {
+            start + (1. - start) * (rpm - rpm_min) / (ramp_rpm - rpm_min)
+        }
+    }
+
+    /// Linear derating of the field current between `derate` and `max` alternator temperature
+    ///
+    /// No derating without a temperature sensor.
+    fn temperature_factor(temperature: f32, derate: f32, max: f32) -> f32 {
+        if temperature.is_nan() || temperature <= derate {
+            1.
+        } else if temperature >= max {
+            0.
+        } else {
+            (max - temperature) / (max - derate)
+        }
+    }
+
+    /// Limits the change of the field current per controller cycle
+    fn slew_limit(current: f32, target: f32, max_rise: f32, max_fall: f32) -> f32 {
+        if target > current {
+            fminf(target, current + max_rise)
+        } else {
+            fmaxf(target, current - max_fall)
+        }
+    }
+
+    /// no charging load shortly after engine start
+    fn start_delay_active(&self, now: Instant, delay: f32) -> bool {
+        match self.engine_start {
+            Some(start) => now < start + Duration::from_millis((delay * 1000.) as u64),
+            None => false, // manual start without RPM
+        }
+    }
+
     const fn const_rpm_factor<const SIZE: usize>() -> [f32; SIZE] {
         // first guess: double RPM, double current
         const fn calc_rpm_factor(array_index: usize) -> f32 {
@@ -114,18 +206,80 @@ impl Controller {
         tmp
     }
 
-    fn update(&self) {
+    fn update(&mut self) {
         PROCESS_DATA.target_factor.store(self.target, Ordering::Relaxed);
+        let config = CONFIG.lock(|c| c.borrow().clone());
+        let dt = Self::LOOP_INTERVAL_MS as f32 / 1000.;
+        let bat_voltage = PROCESS_DATA.bat_voltage.load(Ordering::Relaxed);
+        let bat_current = PROCESS_DATA.bat_current.load(Ordering::Relaxed);
+        let alt_current = PROCESS_DATA.alt_current.load(Ordering::Relaxed);
+        let voltage_limit = self
+            .voltage_limiter
+            .update(bat_voltage, config.charge_profile.absorption_voltage(), dt);
+        let bat_current_limit = self
+            .bat_current_limiter
+            .update(bat_current, config.bat_current_limit(), dt);
+        let alt_current_limit = self
+            .alt_current_limiter
+            .update(alt_current, config.effective_alt_current_limit(), dt);
+
         let mut field_current = 0.;
+        let mut active_limit = ActiveLimit::Off;
+        let mut selecting = false;
         if self.idle {
             field_current += Self::IF0;
-            if self.charge {
-                let _rpm = PROCESS_DATA.rpm.load(Ordering::Relaxed) as f32;
-                let rpm_factor = 1.0 /* * Self::lookup_rpm_factor(rpm)*/;
-                field_current += MAX_FIELD_CURRENT * rpm_factor * self.target * self.derating;
+            active_limit = ActiveLimit::Idle;
+            if self.charge && self.start_delay_active(Instant::now(), config.engine_start_delay) {
+                active_limit = ActiveLimit::StartDelay;
+            } else if self.charge {
+                let rpm = PROCESS_DATA.rpm.load(Ordering::Relaxed);
+                let rpm_limit =
+                    Self::field_ramp_factor(rpm, config.rpm_min, config.field_ramp_rpm, config.field_ramp_start);
+                let temperature = PROCESS_DATA.temperature.load(Ordering::Relaxed);
+                let temperature_limit =
+                    Self::temperature_factor(temperature, config.alt_temp_derate, config.alt_temp_max);
+                let band_derating = if self.high_idle { config.high_idle_derating } else { 1. };
+                let demand = self.target * self.derating * band_derating;
+
+                // minimum selection - all limits clamp the field current, the most restrictive wins
+                let (limit, factor) = [
+                    (ActiveLimit::Setpoint, demand),
+                    (ActiveLimit::Voltage, voltage_limit),
+                    (ActiveLimit::BatCurrent, bat_current_limit),
+                    (ActiveLimit::AltCurrent, alt_current_limit),
+                    (ActiveLimit::Temperature, temperature_limit),
+                    (ActiveLimit::Rpm, rpm_limit),
+                ]
+                .into_iter()
+                .fold((ActiveLimit::Setpoint, 1.), |min, l| if l.1 < min.1 { l } else { min });
+                active_limit = limit;
+                selecting = true;
+                field_current += MAX_FIELD_CURRENT * factor;
             }
         }
-        SETPOINT.field_current_limit.store(field_current, Ordering::Relaxed);
+
+        // soft start and ramp-down, saves the belt and avoids torque jumps at low RPM
+        self.field_current = Self::slew_limit(
+            self.field_current,
+            field_current,
+            config.field_slew_up * dt,
+            config.field_slew_down * dt,
+        );
+        // the slew rate limit holds the field current back, up or down
+        if self.field_current != field_current {
+            active_limit = ActiveLimit::Ramp;
+        }
+
+        // anti-windup: the limiters track the field current actually applied, including the slew rate limit,
+        // so a limiter that is not active takes over within a few cycles after a load step
+        if selecting {
+            let applied = (self.field_current - Self::IF0) / MAX_FIELD_CURRENT;
+            self.voltage_limiter.track(applied);
+            self.bat_current_limiter.track(applied);
+            self.alt_current_limiter.track(applied);
+        }
+        SETPOINT.field_current_limit.store(self.field_current, Ordering::Relaxed);
+        SETPOINT.active_limit.store(active_limit as u8, Ordering::Relaxed);
     }
 }
 
@@ -134,8 +288,9 @@ pub async fn controller_task() -> ! {
     let mut ticker = Ticker::every(Duration::from_millis(Controller::LOOP_INTERVAL_MS));
     loop {
         CONTROLLER.lock(move |c| {
-            c.borrow().update();
+            c.borrow_mut().update();
         });
+        recorder::sample();
         ticker.next().await;
     }
 }
@@ -180,4 +335,34 @@ mod tests {
             Controller::RPM_FACTOR[Controller::RPM_ARRAY_SIZE - 1]
         );
     }
+
+    #[test]
+    fn test_field_ramp_factor() {
+        assert_eq!(Controller::field_ramp_factor(0., 500., 900., 0.3), 0.3);
+        assert_eq!(Controller::field_ramp_factor(f32::NAN, 500., 900., 0.3), 0.3);
+        assert!((Controller::field_ramp_factor(700., 500., 900., 0.3) - 0.65).abs() < 1e-6);
+        assert_eq!(Controller::field_ramp_factor(900., 500., 900., 0.3), 1.);
+        assert_eq!(Controller::field_ramp_factor(3000., 500., 900., 0.3), 1.);
+    }
+
+    #[test]
+    fn test_field_ramp_factor_disabled() {
+        assert_eq!(Controller::field_ramp_factor(500., 500., 500., 0.3), 1.);
+    }
+
+    #[test]
+    fn test_temperature_factor() {
+        assert_eq!(Controller::temperature_factor(f32::NAN, 90., 110.), 1.);
+        assert_eq!(Controller::temperature_factor(60., 90., 110.), 1.);
+        assert_eq!(Controller::temperature_factor(100., 90., 110.), 0.5);
+        assert_eq!(Controller::temperature_factor(120., 90., 110.), 0.);
+    }
+
+    #[test]
+    fn test_slew_limit() {
+        assert_eq!(Controller::slew_limit(1.0, 3.0, 0.1, 0.2), 1.1);
+        assert_eq!(Controller::slew_limit(1.0, 1.05, 0.1, 0.2), 1.05);
+        assert_eq!(Controller::slew_limit(3.0, 1.0, 0.1, 0.2), 2.8);
+        assert_eq!(Controller::slew_limit(1.1, 1.0, 0.1, 0.2), 1.0);
+    }
 }
@@ -0,0 +1,225 @@
+//! Trend history of battery and alternator values for the trends page
+//!
+//! The values are sampled once per second and kept in one ring buffer per time window. Each point of a
+//! window is the mean of the samples in its interval, so longer windows show the average rather than
+//! aliasing the faster changes.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Ticker};
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive;
+
+use crate::app::shared::PROCESS_DATA;
+
+/// points per window, about one per pixel of the chart
+pub const POINTS: usize = 120;
+pub const TREND_COUNT: usize = 4;
+const WINDOW_COUNT: usize = 3;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub static HISTORY: Mutex<CriticalSectionRawMutex, RefCell<History>> = Mutex::new(RefCell::new(History::new()));
+
+/// window shown on the trends page
+static WINDOW: AtomicU8 = AtomicU8::new(TrendWindow::TwoMinutes as u8);
+
+/// Recorded values
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
+pub enum Trend {
+    BatVoltage = 0,
+    BatCurrent = 1,
+    FieldCurrent = 2,
+    Rpm = 3,
+}
+
+/// Time span shown on the trends page
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, Default, PartialEq)]
+pub enum TrendWindow {
+    #[default]
+    TwoMinutes = 0,
+    TenMinutes = 1,
+    OneHour = 2,
+}
+
+impl TrendWindow {
+    /// samples averaged into one point
+    pub fn samples_per_point(&self) -> u32 {
+        match self {
+            TrendWindow::TwoMinutes => 1,
+            TrendWindow::TenMinutes => 5,
+            TrendWindow::OneHour => 30,
+        }
+    }
+
+    /// short name for UI
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrendWindow::TwoMinutes => "2 min",
+            TrendWindow::TenMinutes => "10 min",
+            TrendWindow::OneHour => "1 h",
+        }
+    }
+}
+
+pub fn window() -> TrendWindow {
+    TrendWindow::from_u8(WINDOW.load(Ordering::Relaxed)).unwrap_or_default()
+}
+
+/// Selects the next longer window, wrapping around to the shortest
+pub fn next_window() {
+    WINDOW.store((window() as u8 + 1) % WINDOW_COUNT as u8, Ordering::Relaxed);
+}
+
+fn sample_now() -> [f32; TREND_COUNT] {
+    [
+        PROCESS_DATA.bat_voltage.load(Ordering::Relaxed),
+        PROCESS_DATA.bat_current.load(Ordering::Relaxed),
+        PROCESS_DATA.field_current.load(Ordering::Relaxed),
+        PROCESS_DATA.rpm.load(Ordering::Relaxed),
+    ]
+}
+
+/// Points of one window, each the mean of `samples_per_point` samples
+#[derive(Debug)]
+struct Ring {
+    points: [[f32; TREND_COUNT]; POINTS],
+    /// index of the next point to be written
+    next: usize,
+    len: usize,
+    /// sums and number of the finite samples of the point in progress
+    sum: [f32; TREND_COUNT],
+    finite: [u32; TREND_COUNT],
+    samples: u32,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self {
+            points: [[f32::NAN; TREND_COUNT]; POINTS],
+            next: 0,
+            len: 0,
+            sum: [0.; TREND_COUNT],
+            finite: [0; TREND_COUNT],
+            samples: 0,
+        }
+    }
+
+    /// Adds a sample, returns `true` if a point was completed
+    fn add(&mut self, sample: &[f32; TREND_COUNT], samples_per_point: u32) -> bool {
+        for (i, value) in sample.iter().enumerate() {
+            if value.is_finite() {
+                self.sum[i] += value;
+                self.finite[i] += 1;
+            }
+        }
+        self.samples += 1;
+        if self.samples < samples_per_point {
+            return false;
+        }
+
+        let point = &mut self.points[self.next];
+        for i in 0..TREND_COUNT {
+            // NaN if the value was not available during the whole interval
+            point[i] = self.sum[i] / self.finite[i] as f32;
+        }
+        self.next = (self.next + 1) % POINTS;
+        self.len = (self.len + 1).min(POINTS);
+        self.sum = [0.; TREND_COUNT];
+        self.finite = [0; TREND_COUNT];
+        self.samples = 0;
+        true
+    }
+
+    /// Copies the points of `trend` oldest first, the newest point is always at the end
+    fn series(&self, trend: Trend, out: &mut [f32; POINTS]) {
+        let missing = POINTS - self.len;
+        out[..missing].fill(f32::NAN);
+        for (k, value) in out[missing..].iter_mut().enumerate() {
+            *value = self.points[(self.next + POINTS - self.len + k) % POINTS][trend as usize];
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct History {
+    rings: [Ring; WINDOW_COUNT],
+    /// incremented whenever a point is completed, so the UI only redraws on changes
+    revision: u32,
+}
+
+impl History {
+    pub const fn new() -> Self {
+        Self {
+            rings: [Ring::new(), Ring::new(), Ring::new()],
+            revision: 0,
+        }
+    }
+
+    pub fn add(&mut self, sample: &[f32; TREND_COUNT]) {
+        for (i, ring) in self.rings.iter_mut().enumerate() {
+            let window = TrendWindow::from_usize(i).unwrap_or_default();
+            if ring.add(sample, window.samples_per_point()) {
+                self.revision = self.revision.wrapping_add(1);
+            }
+        }
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    /// Copies the points of `trend` in `window` oldest first, NaN where no data is available
+    pub fn series(&self, window: TrendWindow, trend: Trend, out: &mut [f32; POINTS]) {
+        self.rings[window as usize].series(trend, out);
+    }
+}
+
+#[embassy_executor::task]
+pub async fn history_task() -> ! {
+    let mut ticker = Ticker::every(SAMPLE_INTERVAL);
+    loop {
+        ticker.next().await;
+        let sample = sample_now();
+        HISTORY.lock(|h| h.borrow_mut().add(&sample));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newest_point_last() {
+        let mut history = History::new();
+        for i in 0..3 {
+            history.add(&[12. + i as f32, 0., 0., 0.]);
+        }
+        let mut out = [0.; POINTS];
+        history.series(TrendWindow::TwoMinutes, Trend::BatVoltage, &mut out);
+        assert!(out[POINTS - 4].is_nan());
+        assert_eq!(&out[POINTS - 3..], &[12., 13., 14.]);
+        assert_eq!(history.revision(), 3);
+    }
+
+    #[test]
+    fn test_points_averaged_and_wrapped() {
+        let mut history = History::new();
+        for i in 0..(POINTS + 2) * 5 {
+            history.add(&[0., i as f32, f32::NAN, 0.]);
+        }
+        let mut out = [0.; POINTS];
+        history.series(TrendWindow::TenMinutes, Trend::BatCurrent, &mut out);
+        // mean of the last 5 samples
+        assert_eq!(out[POINTS - 1], ((POINTS + 2) * 5 - 3) as f32);
+        assert_eq!(out[0], 12.);
+        history.series(TrendWindow::TenMinutes, Trend::FieldCurrent, &mut out);
+        assert!(out.iter().all(|v| v.is_nan()));
+    }
+}
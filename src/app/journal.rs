@@ -0,0 +1,87 @@
+//! Event journal
+//!
+//! Append-only log of discrete events (boot, received regulator events, state changes, faults, config
+//! changes, maintenance, alarms), written to `EVENTS.LOG` next to the data logs. Entries are queued in a channel
+//! and written by a loop of their own in `logger`, so recording an entry never blocks on SD I/O and the entries
+//! reach the card independent of the log interval. The loop shares the card and its volume with the data logger,
+//! both only access it with `BUS_LOCK` held.
+//! If the queue is full, e.g. while the card is missing, entries are dropped and the number of dropped
+//! entries is written to the journal later.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Instant;
+use heapless::String;
+
+const QUEUE_LEN: usize = 32;
+pub const PAYLOAD_LEN: usize = 48;
+
+static JOURNAL: Channel<CriticalSectionRawMutex, JournalEntry, QUEUE_LEN> = Channel::new();
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EntryKind {
+    Boot,
+    Event,
+    StateEntered,
+    FaultRaised,
+    FaultCleared,
+    ConfigChanged,
+    Maintenance,
+    Alarm,
+    Dropped,
+}
+
+#[derive(Debug)]
+pub struct JournalEntry {
+    pub time: Instant,
+    pub kind: EntryKind,
+    /// truncated to `PAYLOAD_LEN`
+    pub payload: String<PAYLOAD_LEN>,
+}
+
+/// Queues a journal entry, never blocks
+pub fn record(kind: EntryKind, payload: fmt::Arguments) {
+    let mut entry = JournalEntry {
+        time: Instant::now(),
+        kind,
+        payload: String::new(),
+    };
+    entry.payload.write_fmt(payload).ok(); // keeps what fits
+    if JOURNAL.try_send(entry).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records a fault being raised or cleared
+pub fn fault(name: &str, active: bool) {
+    let kind = if active {
+        EntryKind::FaultRaised
+    } else {
+        EntryKind::FaultCleared
+    };
+    record(kind, format_args!("{}", name));
+}
+
+/// Waits until there is an entry to be written
+pub async fn pending() {
+    JOURNAL.ready_to_receive().await
+}
+
+/// Next entry to be written, reports dropped entries first
+pub fn next() -> Option<JournalEntry> {
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        let mut payload = String::new();
+        write!(payload, "{}", dropped).ok();
+        return Some(JournalEntry {
+            time: Instant::now(),
+            kind: EntryKind::Dropped,
+            payload,
+        });
+    }
+    JOURNAL.try_receive().ok()
+}
@@ -0,0 +1,129 @@
+use libm::{fmaxf, fminf};
+
+/// Integrating limiter of an outer control loop (e.g. alternator output current)
+///
+/// The output is a factor (0.0 to 1.0) of the maximum field current. It winds down while the measured
+/// value exceeds the limit and recovers while it stays below, so the field current settles at the level
+/// that holds the measured value at the limit.
+#[derive(Debug)]
+pub struct Limiter {
+    /// integrator state, factor of the maximum field current
+    factor: f32,
+
+    /// integral gain (1/s), relative to the limit
+    gain: f32,
+
+    /// the last update had a valid measurement and limit
+    valid: bool,
+}
+
+impl Limiter {
+    /// headroom of an inactive limiter above the selected output
+    const TRACK_MARGIN: f32 = 0.1;
+
+    pub const fn new(gain: f32) -> Self {
+        Self {
+            factor: 1.,
+            gain,
+            valid: false,
+        }
+    }
+
+    /// Advances the limiter by `dt` seconds and returns the new field current factor
+    ///
+    /// Without a valid measurement or limit, the last factor is kept: losing the sensor while limiting
+    /// must not release the full field.
+    pub fn update(&mut self, measured: f32, limit: f32, dt: f32) -> f32 {
+        self.valid = measured.is_finite() && limit.is_finite() && limit > 0.;
+        if self.valid {
+            let error = (limit - measured) / limit;
+            self.factor = fminf(fmaxf(self.factor + self.gain * error * dt, 0.), 1.);
+        }
+        self.factor
+    }
+
+    /// Anti-windup: an inactive limiter stays close to the applied output, so it takes over without delay
+    /// once its measured value reaches the limit
+    ///
+    /// A limiter without a valid measurement keeps its factor, see `update`.
+    pub fn track(&mut self, output: f32) {
+        if self.valid {
+            self.factor = fminf(self.factor, fmaxf(output, 0.) + Self::TRACK_MARGIN);
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn factor(&self) -> f32 {
+        self.factor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_winds_down_above_limit() {
+        let mut l = Limiter::new(0.5);
+        let f = l.update(150., 100., 0.1);
+        assert!((f - 0.975).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_saturates() {
+        let mut l = Limiter::new(0.5);
+        assert_eq!(l.update(50., 100., 0.1), 1.);
+        for _ in 0..1000 {
+            l.update(1000., 100., 0.1);
+        }
+        assert_eq!(l.factor(), 0.);
+    }
+
+    #[test]
+    fn test_holds_without_measurement() {
+        let mut l = Limiter::new(0.5);
+        l.update(150., 100., 1.);
+        let f = l.factor();
+        assert_eq!(l.update(f32::NAN, 100., 1.), f);
+    }
+
+    #[test]
+    fn test_track() {
+        let mut l = Limiter::new(0.5);
+        l.track(0.3);
+        assert_eq!(l.factor(), 1., "no tracking without a measurement");
+        l.update(50., 100., 0.);
+        l.track(0.3);
+        assert!((l.factor() - 0.4).abs() < 1e-6);
+        l.track(0.9);
+        assert!((l.factor() - 0.4).abs() < 1e-6);
+    }
+
+    /// A load step above the limit while the output runs well below it: the limiter has tracked the output,
+    /// so the limit holds within a few updates instead of winding down from 1.0 first
+    #[test]
+    fn test_step_above_limit() {
+        let limit = 100.;
+        let dt = 0.1;
+        let mut l = Limiter::new(0.5);
+        // alternator current per field current factor
+        let mut gain = 100.;
+        let mut demand = 0.3;
+        let mut output = 0.;
+        let mut above = 0;
+        for step in 0..100 {
+            if step == 50 {
+                demand = 1.;
+                gain = 250.;
+            }
+            let measured = gain * output;
+            if step >= 50 && measured > limit * 1.05 {
+                above += 1;
+            }
+            output = fminf(demand, l.update(measured, limit, dt));
+            l.track(output);
+        }
+        assert!(above <= 3, "{} updates above the limit", above);
+        assert!((gain * output - limit).abs() < limit * 0.05);
+    }
+}
@@ -1,14 +1,26 @@
-use alloc::boxed::Box;
-use embassy_time::{Duration, Ticker};
-use embedded_sdmmc::{Error, File, Mode, SdCardError, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::sync::atomic::Ordering;
+use embassy_futures::join::join;
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use embedded_sdmmc::{
+    Error, Mode, RawDirectory, RawFile, SdCardError, ShortFileName, TimeSource, Timestamp, VolumeIdx, VolumeManager,
+};
 use heapless::{format, String};
 use thiserror_no_std::Error;
 
-use crate::app::shared::{PROCESS_DATA, REGULATOR_MODE, RM_LEN, SETPOINT};
-use crate::board::io::spi2::SdCardType;
+use crate::app::clock::{self, DateTime, ISO_LEN};
+use crate::app::config::{LogFormat, CONFIG};
+use crate::app::journal::{self, JournalEntry, PAYLOAD_LEN};
+use crate::app::recorder::{self, Trigger, RECORDER};
+use crate::app::screenshot::{self, Band};
+use crate::app::shared::{CardState, ProcessData, Setpoint, PROCESS_DATA, REGULATOR_MODE, RM_LEN, SETPOINT};
+use crate::board::io::spi2::{SdCardType, BUS_LOCK};
 use crate::fmt::Debug2Format;
 
-type FileType<'a> = File<'a, SdCardType, EmbassyTimeSource, 4, 4, 1>;
 type VolumeManagerType = VolumeManager<SdCardType, EmbassyTimeSource>;
 
 #[derive(Default)]
@@ -27,102 +39,732 @@ pub enum LoggerError {
 
     #[error("SD card not present")]
     NoCardError(#[from] SdCardError),
+
+    #[error("Log file not open")]
+    NotOpen,
+
+    #[error("Binary log error: {0}")]
+    Binary(#[from] binlog::Error),
+
+    #[error("Journal write failed, card state {0:?}")]
+    Journal(CardState),
+}
+
+impl LoggerError {
+    /// What the error tells about the card
+    fn card_state(&self) -> CardState {
+        match self {
+            LoggerError::NoCardError(SdCardError::CardNotFound | SdCardError::TimeoutCommand(_))
+            | LoggerError::SdCard(Error::DeviceError(SdCardError::CardNotFound | SdCardError::TimeoutCommand(_))) => {
+                CardState::Absent
+            }
+            LoggerError::SdCard(Error::DiskFull) => CardState::Full,
+            LoggerError::Journal(state) => *state,
+            _ => CardState::Error,
+        }
+    }
+}
+
+/// The log file currently written to
+struct LogFile {
+    file: RawFile,
+    format: LogFormat,
+    columns: u32,
+    opened: Instant,
+    flushed: Instant,
+    size: u32,
+}
+
+/// Summary of the numbered files in the log directory
+#[derive(Default)]
+struct LogDirInfo {
+    /// oldest file, data log, recording or screenshot
+    oldest: Option<(u32, ShortFileName)>,
+    /// highest number of all files, recordings and screenshots share the numbering
+    newest: u32,
+    /// size of all numbered files, the log quota applies to them
+    total_bytes: u64,
+}
+
+/// Size and age limits and format of the log files, taken from the persistent configuration
+struct LogLimits {
+    file_bytes: u32,
+    file_age: Duration,
+    quota_bytes: u64,
+    format: LogFormat,
+    /// bit mask of the logged fields
+    columns: u32,
 }
 
 struct DataLogger {
     volume_mgr: VolumeManagerType,
+    card_bytes: u64,
+    dir: Option<RawDirectory>,
+    file: Option<LogFile>,
+    /// screenshot being written, see `app::screenshot`
+    screenshot: Option<RawFile>,
 }
 
 pub const LINE_LEN: usize = 800;
 
+/// State of the card after a failed journal write, `log_loop` resets the card
+static JOURNAL_FAILED: Signal<CriticalSectionRawMutex, CardState> = Signal::new();
+/// Signalled by `log_loop` after opening the card, `journal_loop` waits for it while the card is not open
+static CARD_OPENED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// number of values in a binary log record
+pub const RECORD_FIELDS: usize = ProcessData::FIELDS.len() + Setpoint::FIELDS.len();
+const _: () = assert!(RECORD_FIELDS <= u32::BITS as usize, "log_columns has one bit per field");
+/// flight recordings have an additional column marking the trigger
+const RECORD_LEN: usize = binlog::record_len(RECORD_FIELDS + 1);
+
+/// Current values of a binary log record
+pub fn record_values() -> [f32; RECORD_FIELDS] {
+    let mut values = [f32::NAN; RECORD_FIELDS];
+    for (v, value) in values.iter_mut().zip(field_values(&PROCESS_DATA).chain(field_values(&SETPOINT))) {
+        *v = value;
+    }
+    values
+}
+
 impl DataLogger {
     const FN_LEN: usize = 5 + 1 + 3;
+    const LOG_DIR: &'static str = "logs";
+    const CARD_POLL_S: u64 = 2;
+    const RETRY_DELAY_S: u64 = 10;
+    const FULL_RETRY_DELAY_S: u64 = 60;
+    /// at high log rates, flushing every record would cost more than writing it
+    const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+    /// flight recordings, in the binary log format
+    const RECORDING_EXTENSION: &'static str = "REC";
+    const SCREENSHOT_EXTENSION: &'static str = "BMP";
+    /// upper bound of a recording file, the header fits into a line
+    const RECORDING_BYTES: u64 = (LINE_LEN + recorder::SAMPLES * RECORD_LEN) as u64;
+    /// event journal, not numbered, so the log quota does not delete it
+    const JOURNAL_FILE: &'static str = "EVENTS.LOG";
+    const JOURNAL_LINE_LEN: usize = ISO_LEN + 24 + PAYLOAD_LEN;
 
-    pub async fn new(card: SdCardType) -> Result<Self, LoggerError> {
-        let size = card.num_bytes()?;
-        info!("SD card size: {:?}", size);
+    pub fn new(card: SdCardType) -> Self {
         let volume_mgr = VolumeManager::new(card, EmbassyTimeSource::default());
-        Ok(Self { volume_mgr })
+        Self {
+            volume_mgr,
+            card_bytes: 0,
+            dir: None,
+            file: None,
+            screenshot: None,
+        }
     }
 
-    pub async fn open(&self) -> Result<Box<FileType<'_>>, LoggerError> {
-        let volume0 = self.volume_mgr.open_volume(VolumeIdx(0))?;
-        let volume0 = Box::leak(Box::new(volume0));
-        debug!("Volume 0: {:?}", Debug2Format(&volume0));
+    /// Drops all open handles and forces a re-initialization of the card on next access
+    ///
+    /// Closing handles is not possible after a card error, so the volume manager is rebuilt instead.
+    pub fn reset(self) -> Self {
+        let (card, time_source) = self.volume_mgr.free();
+        card.mark_card_uninit();
+        Self {
+            volume_mgr: VolumeManager::new(card, time_source),
+            card_bytes: 0,
+            dir: None,
+            file: None,
+            screenshot: None,
+        }
+    }
 
-        let mut dir = volume0.open_root_dir()?;
-        dir.make_dir_in_dir("logs").ok(); // create the directory if it doesn't exist
-        dir.change_dir("logs")?;
-        let dir = Box::leak(Box::new(dir));
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
 
-        let mut index = 0;
-        dir.iterate_dir(|f| {
-            let fname = f.name.base_name();
-            let num_or_not = u32::from_ascii(fname);
-            if let Ok(num) = num_or_not {
-                debug!("Found file: {:?}", Debug2Format(&f.name));
-                index = num.max(index);
-            }
-        })?;
+    /// Opens the log directory and starts a new log file
+    pub async fn open(&mut self) -> Result<(), LoggerError> {
+        self.card_bytes = self.volume_mgr.device(|card| card.num_bytes())?;
+        info!("SD card size: {:?}", self.card_bytes);
 
-        let fname: String<{ Self::FN_LEN }> = format!("{:05}.CSV", index + 1)?;
-        let file = dir.open_file_in_dir(fname.as_str(), Mode::ReadWriteCreateOrAppend)?;
+        let volume = self.volume_mgr.open_raw_volume(VolumeIdx(0))?;
 
-        let line =
-            format!({ LINE_LEN }; "{};{};{};;{}\n", "Timestamp", "Mode", PROCESS_DATA.get_meta(), SETPOINT.get_meta())?;
-        debug!("{:?}", Debug2Format(&line));
-        file.write(line.as_bytes())?;
-        file.flush()?;
+        let root = self.volume_mgr.open_root_dir(volume)?;
+        self.volume_mgr.make_dir_in_dir(root, Self::LOG_DIR).ok(); // create the directory if it doesn't exist
+        let dir = self.volume_mgr.open_dir(root, Self::LOG_DIR);
+        self.volume_mgr.close_dir(root)?;
+        self.dir = Some(dir?);
 
-        Ok(Box::new(file))
+        self.start_file()
     }
 
-    pub async fn log<'a>(&self, file: &'a FileType<'a>) -> Result<(), LoggerError> {
+    pub async fn log(&mut self) -> Result<(), LoggerError> {
+        let limits = self.limits();
+        let rotate = self.file.as_ref().is_some_and(|f| {
+            f.size >= limits.file_bytes
+                || f.opened.elapsed() >= limits.file_age
+                || f.format != limits.format
+                || f.columns != limits.columns
+        });
+        if rotate {
+            self.rotate()?;
+        }
+
         let now = embassy_time::Instant::now();
-        let mut mode: String<RM_LEN> = String::new();
-        REGULATOR_MODE.lock(|rm| mode.push_str(rm.borrow().as_str()))?;
-        let line = format!({ LINE_LEN }; "{};{};{};;{}\n", now.as_millis() as u64, mode, PROCESS_DATA, SETPOINT)?;
-        debug!("{:?}", Debug2Format(&line));
-        file.write(line.as_bytes())?;
-        file.flush()?;
+        match limits.format {
+            LogFormat::Csv => {
+                let mut mode: String<RM_LEN> = String::new();
+                REGULATOR_MODE.lock(|rm| mode.push_str(rm.borrow().as_str()))?;
+                let time: String<ISO_LEN> = match clock::at(now) {
+                    Some(time) => format!("{}", time)?,
+                    None => String::new(), // wall clock not known yet
+                };
+                let mut line: String<LINE_LEN> = format!("{};{};{}", time, now.as_millis() as u64, mode)?;
+                write_csv_fields(&mut line, &PROCESS_DATA, limits.columns, 0)?;
+                write_csv_fields(&mut line, &SETPOINT, limits.columns, ProcessData::FIELDS.len())?;
+                line.push('\n')?;
+                debug!("{:?}", Debug2Format(&line));
+                self.write(line.as_bytes())
+            }
+            LogFormat::Binary => {
+                let mut values = [0.; RECORD_FIELDS];
+                let mut count = 0;
+                for (i, value) in record_values().into_iter().enumerate() {
+                    if is_selected(limits.columns, i) {
+                        values[count] = value;
+                        count += 1;
+                    }
+                }
+                let mut record = [0_u8; RECORD_LEN];
+                let len = binlog::write_record(&mut record, now.as_millis() as u32, &values[..count])?;
+                self.write(&record[..len])
+            }
+        }
+    }
+
+    /// Closes the current log file and continues in a new one
+    fn rotate(&mut self) -> Result<(), LoggerError> {
+        if let Some(log_file) = self.file.take() {
+            info!("rotating log file after {} bytes", log_file.size);
+            self.volume_mgr.close_file(log_file.file)?;
+        }
+        self.start_file()
+    }
+
+    fn start_file(&mut self) -> Result<(), LoggerError> {
+        let dir = self.dir.ok_or(LoggerError::NotOpen)?;
+        let limits = self.limits();
+        self.enforce_quota(dir, limits.quota_bytes.saturating_sub(limits.file_bytes as u64))?;
+
+        let index = self.scan_dir(dir)?.newest + 1;
+        let fname = Self::file_name(index, Self::extension(limits.format))?;
+        info!("starting log file {}", fname.as_str());
+        let file = self
+            .volume_mgr
+            .open_file_in_dir(dir, fname.as_str(), Mode::ReadWriteCreateOrAppend)?;
+        self.file = Some(LogFile {
+            file,
+            format: limits.format,
+            columns: limits.columns,
+            opened: Instant::now(),
+            flushed: Instant::now(),
+            size: 0,
+        });
+
+        match limits.format {
+            LogFormat::Csv => {
+                let mut line: String<LINE_LEN> = String::try_from("Timestamp;Uptime;Mode")?;
+                for name in column_names(limits.columns) {
+                    write!(line, ";{}", name)?;
+                }
+                line.push('\n')?;
+                self.write(line.as_bytes())?;
+            }
+            LogFormat::Binary => {
+                let names = column_names(limits.columns);
+                let mut header = [0_u8; LINE_LEN];
+                let len = binlog::write_header(&mut header, clock::boot_unix_ms(), names)?;
+                self.write(&header[..len])?;
+            }
+        }
+        self.flush()
+    }
+
+    /// Saves a complete flight recording to its own file and restarts the flight recorder
+    ///
+    /// The recording is numbered like the log files and counts towards the log quota. New triggers are
+    /// ignored until the recording is saved, except a fault.
+    pub fn save_recording(&mut self) -> Result<(), LoggerError> {
+        let Some((trigger, trigger_ms)) = RECORDER.lock(|r| r.borrow().complete()) else {
+            return Ok(());
+        };
+        let dir = self.dir.ok_or(LoggerError::NotOpen)?;
+        self.make_room(dir, Self::RECORDING_BYTES)?;
+        let index = self.scan_dir(dir)?.newest + 1;
+        let fname = Self::file_name(index, Self::RECORDING_EXTENSION)?;
+        info!("saving flight recording ({:?}) to {}", trigger, fname.as_str());
+        let file = self.volume_mgr.open_file_in_dir(dir, fname.as_str(), Mode::ReadWriteCreate)?;
+
+        let result = self.write_recording(file, trigger, trigger_ms);
+        let closed = self.volume_mgr.close_file(file);
+        result?;
+        closed?;
+        // kept after an error, so it is saved again once the card is back
+        RECORDER.lock(|r| {
+            let mut r = r.borrow_mut();
+            // unless a fault has overwritten it in the meantime
+            if r.complete() == Some((trigger, trigger_ms)) {
+                r.release();
+            }
+        });
+        Ok(())
+    }
+
+    fn write_recording(&mut self, file: RawFile, trigger: Trigger, trigger_ms: u32) -> Result<(), LoggerError> {
+        let names = column_names(u32::MAX).chain(["Trigger"]);
+        let mut buf = [0_u8; LINE_LEN];
+        let len = binlog::write_header(&mut buf, clock::boot_unix_ms(), names)?;
+        self.volume_mgr.write(file, &buf[..len])?;
+
+        // one sample at a time, so the controller is not blocked while writing
+        let mut marked = false;
+        for index in 0.. {
+            let sample = RECORDER.lock(|r| {
+                let r = r.borrow();
+                // a fault overwriting the recording ends it early
+                r.complete()
+                    .filter(|&complete| complete == (trigger, trigger_ms))
+                    .and_then(|_| r.get(index).copied())
+            });
+            let Some(sample) = sample else {
+                break;
+            };
+            let mut values = [0.; RECORD_FIELDS + 1];
+            values[..RECORD_FIELDS].copy_from_slice(&sample.values);
+            if !marked && sample.uptime_ms >= trigger_ms {
+                values[RECORD_FIELDS] = trigger as u8 as f32;
+                marked = true;
+            }
+            let len = binlog::write_record(&mut buf, sample.uptime_ms, &values)?;
+            self.volume_mgr.write(file, &buf[..len])?;
+        }
+        Ok(())
+    }
+
+    /// Writes a band of a screenshot, the first band starts a new file
+    ///
+    /// Screenshots are numbered like the log files and count towards the log quota.
+    pub fn save_screenshot(&mut self, band: &Band) -> Result<(), LoggerError> {
+        let result = self.write_screenshot(band);
+        if band.is_last() || result.is_err() {
+            if let Some(file) = self.screenshot.take() {
+                self.volume_mgr.close_file(file)?;
+            }
+        }
+        result
+    }
+
+    fn write_screenshot(&mut self, band: &Band) -> Result<(), LoggerError> {
+        if band.y == 0 {
+            // left over from an aborted screenshot
+            if let Some(file) = self.screenshot.take() {
+                self.volume_mgr.close_file(file)?;
+            }
+            let dir = self.dir.ok_or(LoggerError::NotOpen)?;
+            self.make_room(dir, screenshot::FILE_LEN as u64)?;
+            let index = self.scan_dir(dir)?.newest + 1;
+            let fname = Self::file_name(index, Self::SCREENSHOT_EXTENSION)?;
+            info!("saving screenshot to {}", fname.as_str());
+            let file = self.volume_mgr.open_file_in_dir(dir, fname.as_str(), Mode::ReadWriteCreate)?;
+            self.screenshot = Some(file);
+            self.volume_mgr.write(file, &screenshot::bmp_header())?;
+        }
+        let file = self.screenshot.ok_or(LoggerError::NotOpen)?;
+        let mut bmp_row = [0_u8; screenshot::ROW_LEN];
+        for row in 0..band.rows {
+            screenshot::to_bmp_row(band.row(row), &mut bmp_row);
+            self.volume_mgr.write(file, &bmp_row)?;
+        }
+        Ok(())
+    }
+
+    /// Appends all queued journal entries to the event journal
+    ///
+    /// The file is only opened while writing, as entries are rare compared to log records.
+    pub fn write_journal(&mut self) -> Result<(), LoggerError> {
+        let Some(first) = journal::next() else {
+            return Ok(());
+        };
+        let dir = self.dir.ok_or(LoggerError::NotOpen)?;
+        let file = self
+            .volume_mgr
+            .open_file_in_dir(dir, Self::JOURNAL_FILE, Mode::ReadWriteCreateOrAppend)?;
+
+        let result = self.write_journal_entries(file, first);
+        let closed = self.volume_mgr.close_file(file);
+        result?;
+        Ok(closed?)
+    }
+
+    fn write_journal_entries(&mut self, file: RawFile, first: JournalEntry) -> Result<(), LoggerError> {
+        if self.volume_mgr.file_length(file)? == 0 {
+            self.volume_mgr.write(file, b"Timestamp;Uptime;Type;Payload\n")?;
+        }
+        let mut entry = Some(first);
+        while let Some(e) = entry {
+            let time: String<ISO_LEN> = match clock::at(e.time) {
+                Some(time) => format!("{}", time)?,
+                None => String::new(),
+            };
+            let line = format!({ Self::JOURNAL_LINE_LEN }; "{};{};{:?};{}\n",
+                time, e.time.as_millis(), e.kind, e.payload)?;
+            self.volume_mgr.write(file, line.as_bytes())?;
+            entry = journal::next();
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), LoggerError> {
+        let log_file = self.file.as_mut().ok_or(LoggerError::NotOpen)?;
+        self.volume_mgr.write(log_file.file, data)?;
+        log_file.size += data.len() as u32;
+        if log_file.flushed.elapsed() >= Self::FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), LoggerError> {
+        let log_file = self.file.as_mut().ok_or(LoggerError::NotOpen)?;
+        self.volume_mgr.flush_file(log_file.file)?;
+        log_file.flushed = Instant::now();
         Ok(())
     }
+
+    /// Deletes the oldest files until they fit into `quota_bytes`
+    ///
+    /// Recordings and screenshots are deleted along with the logs of their time, otherwise they would fill
+    /// the card over time, as embedded-sdmmc does not report the free space. Deleting stops at a file that
+    /// is still open, all older ones are gone then.
+    fn enforce_quota(&mut self, dir: RawDirectory, quota_bytes: u64) -> Result<(), LoggerError> {
+        loop {
+            let info = self.scan_dir(dir)?;
+            match info.oldest {
+                Some((_, fname)) if info.total_bytes > quota_bytes => {
+                    info!("log quota exceeded ({} bytes), deleting {}", info.total_bytes, Debug2Format(&fname));
+                    match self.volume_mgr.delete_file_in_dir(dir, fname) {
+                        Err(Error::FileAlreadyOpen) => return Ok(()),
+                        result => result?,
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Makes room for a file of `bytes` written in between the log records, next to a full log file
+    fn make_room(&mut self, dir: RawDirectory, bytes: u64) -> Result<(), LoggerError> {
+        let limits = self.limits();
+        self.enforce_quota(dir, limits.quota_bytes.saturating_sub(limits.file_bytes as u64 + bytes))
+    }
+
+    fn scan_dir(&self, dir: RawDirectory) -> Result<LogDirInfo, LoggerError> {
+        let mut info = LogDirInfo::default();
+        self.volume_mgr.iterate_dir(dir, |f| {
+            if let Ok(num) = u32::from_ascii(f.name.base_name()) {
+                debug!("Found file: {:?}", Debug2Format(&f.name));
+                info.newest = num.max(info.newest);
+                if info.oldest.as_ref().is_none_or(|(oldest, _)| num < *oldest) {
+                    info.oldest = Some((num, f.name.clone()));
+                }
+                info.total_bytes += f.size as u64;
+            }
+        })?;
+        Ok(info)
+    }
+
+    fn file_name(index: u32, extension: &str) -> Result<String<{ Self::FN_LEN }>, LoggerError> {
+        Ok(format!("{:05}.{}", index, extension)?)
+    }
+
+    fn extension(format: LogFormat) -> &'static str {
+        match format {
+            LogFormat::Csv => "CSV",
+            LogFormat::Binary => "BIN",
+        }
+    }
+
+    fn limits(&self) -> LogLimits {
+        // embedded-sdmmc does not report the free space, so the quota is also capped by the card size, leaving
+        // room for the journal and files outside the log directory
+        let card_quota = self.card_bytes / 10 * 9;
+        CONFIG.lock(|c| {
+            let c = c.borrow();
+            LogLimits {
+                file_bytes: (c.log_file_max_kb * 1024.) as u32,
+                file_age: Duration::from_secs((c.log_file_max_minutes * 60.) as u64),
+                quota_bytes: ((c.log_quota_mb * 1024. * 1024.) as u64).min(card_quota),
+                format: c.log_format,
+                columns: c.log_columns,
+            }
+        })
+    }
 }
 
+/// Logger state machine, driven by the state of the SD card
+///
+/// A missing card is polled by periodic re-initialization, so a card can be pulled to copy the logs and
+/// put back while the engine is running. Logging continues in a new file after the card is back.
+/// Screenshots are written as soon as they are queued, in between the log records, the journal has a loop
+/// of its own sharing the card.
+/// The card is only accessed with `BUS_LOCK` held, as the display keeps the SPI bus during its transfers.
 pub async fn logger_loop(card: SdCardType) -> () {
-    let Ok(logger) = DataLogger::new(card).await else {
-        warn!("Could not init SD card, disabling CSV logger");
-        return;
-    };
-    let Ok(file) = logger.open().await else {
-        warn!("Could not log file, disabling CSV logger");
-        return;
-    };
+    // only borrowed with `BUS_LOCK` held, `None` only while the card is reset
+    let logger = RefCell::new(Some(DataLogger::new(card)));
+    join(log_loop(&logger), journal_loop(&logger)).await;
+}
 
-    let mut ticker = Ticker::every(Duration::from_secs(1));
+async fn log_loop(shared: &RefCell<Option<DataLogger>>) {
+    let mut interval = log_interval();
+    let mut ticker = Ticker::every(interval);
     loop {
-        logger.log(&file).await.unwrap_or_else(|err| {
-            error!("Could not log to SD card: {:?}", Debug2Format(&err));
-        });
-        ticker.next().await;
+        if log_interval() != interval {
+            interval = log_interval();
+            ticker = Ticker::every(interval);
+        }
+
+        let event = if shared.borrow().as_ref().is_some_and(|l| l.is_open()) {
+            Some(select3(ticker.next(), screenshot::next_band(), JOURNAL_FAILED.wait()).await)
+        } else {
+            None
+        };
+        let bus = BUS_LOCK.lock().await;
+        let mut guard = shared.borrow_mut();
+        let logger = guard.as_mut().expect("the logger is only taken while resetting");
+        let result = match event {
+            Some(Either3::First(())) => match logger.log().await {
+                Ok(()) => logger.save_recording(),
+                err => err,
+            },
+            Some(Either3::Second(band)) => {
+                let result = logger.save_screenshot(&band);
+                screenshot::saved(result.is_ok());
+                result
+            }
+            Some(Either3::Third(state)) => Err(LoggerError::Journal(state)),
+            None => logger.open().await.inspect(|()| {
+                // a journal failure before the reset is no reason to reset again
+                JOURNAL_FAILED.reset();
+                CARD_OPENED.signal(());
+            }),
+        };
+        let state = match &result {
+            Ok(()) => CardState::Ok,
+            Err(err) => err.card_state(),
+        };
+
+        let previous = PROCESS_DATA.card_state();
+        if state != previous {
+            info!("SD card state: {:?} -> {:?}", previous, state);
+            PROCESS_DATA.card_state.store(state as u8, Ordering::Relaxed);
+        }
+
+        let retry_delay = match state {
+            CardState::Ok => continue,
+            CardState::Absent => {
+                // keeps the recorder sampling for a fault
+                RECORDER.lock(|r| r.borrow_mut().discard_unsaved());
+                DataLogger::CARD_POLL_S
+            }
+            CardState::Full => DataLogger::FULL_RETRY_DELAY_S,
+            CardState::Error | CardState::Unknown => DataLogger::RETRY_DELAY_S,
+        };
+        if let Err(err) = result {
+            if state != previous {
+                error!("data logger failed, retrying in {} s: {:?}", retry_delay, Debug2Format(&err));
+            }
+        }
+        *guard = guard.take().map(DataLogger::reset);
+        drop(guard);
+        drop(bus);
+        Timer::after(Duration::from_secs(retry_delay)).await;
+        ticker.reset();
+    }
+}
+
+/// Writes the journal entries as soon as they are queued, independent of the log interval
+///
+/// While the card is not open, the entries stay queued until `log_loop` has opened it again. A failed write
+/// is left to `log_loop`, which resets the card and keeps the card state.
+async fn journal_loop(shared: &RefCell<Option<DataLogger>>) {
+    loop {
+        journal::pending().await;
+        let written = {
+            let _bus = BUS_LOCK.lock().await;
+            match shared.borrow_mut().as_mut().filter(|l| l.is_open()) {
+                Some(logger) => logger
+                    .write_journal()
+                    .inspect_err(|err| {
+                        error!("journal write failed: {:?}", Debug2Format(err));
+                        JOURNAL_FAILED.signal(err.card_state());
+                    })
+                    .is_ok(),
+                None => false,
+            }
+        };
+        if !written {
+            // the card cannot have been opened since the bus was released
+            CARD_OPENED.reset();
+            CARD_OPENED.wait().await;
+        }
     }
 }
 
+fn log_interval() -> Duration {
+    Duration::from_millis(CONFIG.lock(|c| (c.borrow().log_interval * 1000.) as u64))
+}
+
+impl EmbassyTimeSource {
+    /// 2000-01-01, file dates count from here while the wall clock is not known
+    const FALLBACK_EPOCH_MS: u64 = 946_684_800_000;
+}
+
 impl TimeSource for EmbassyTimeSource {
     fn get_timestamp(&self) -> Timestamp {
         let now = embassy_time::Instant::now();
-        let secs = now.as_secs();
+        let time = clock::at(now).unwrap_or_else(|| DateTime::from_unix_ms(Self::FALLBACK_EPOCH_MS + now.as_millis()));
         Timestamp {
-            year_since_1970: (secs / (365 * 24 * 3600)) as u8,
-            zero_indexed_month: 0,
-            zero_indexed_day: 0,
-            hours: ((secs % (24 * 3600)) / 3600) as u8,
-            minutes: ((secs % 3600) / 60) as u8,
-            seconds: (secs % 60) as u8,
+            year_since_1970: (time.year - 1970) as u8,
+            zero_indexed_month: time.month - 1,
+            zero_indexed_day: time.day - 1,
+            hours: time.hour,
+            minutes: time.minute,
+            seconds: time.second,
+        }
+    }
+}
+
+/// Value of a log field
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Float(f32),
+    Int(u32),
+    Bool(bool),
+    /// number and name, the CSV log shows the name
+    Enum(u8, &'static str),
+}
+
+impl FieldValue {
+    /// value in a binary log record
+    pub fn as_f32(&self) -> f32 {
+        match *self {
+            FieldValue::Float(v) => v,
+            FieldValue::Int(v) => v as f32,
+            FieldValue::Bool(v) => v as u8 as f32,
+            FieldValue::Enum(v, _) => v as f32,
+        }
+    }
+}
+
+/// Description of a logged field, see [`log_field!`]
+#[allow(dead_code)] // name and unit are for displays of the logged data
+pub struct LogField<T: 'static> {
+    pub name: &'static str,
+    pub unit: &'static str,
+    /// column header, the name followed by the unit
+    pub column: &'static str,
+    /// decimals of a `FieldValue::Float` in the CSV log
+    pub precision: usize,
+    pub value: fn(&T) -> FieldValue,
+}
+
+impl<T> LogField<T> {
+    /// CSV representation of the current value
+    pub fn display<'a>(&self, data: &'a T) -> impl fmt::Display + 'a {
+        DisplayValue((self.value)(data), self.precision)
+    }
+}
+
+struct DisplayValue(FieldValue, usize);
+
+impl fmt::Display for DisplayValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            FieldValue::Float(v) => write!(f, "{:.*}", self.1, v),
+            FieldValue::Int(v) => write!(f, "{}", v),
+            FieldValue::Bool(v) => write!(f, "{}", v),
+            FieldValue::Enum(_, name) => f.write_str(name),
+        }
+    }
+}
+
+/// Builds a [`LogField`]
+///
+/// `log_field!(name, unit, precision, field)` describes an `AtomicF32` field, `unit` may be omitted.
+/// `log_field!(name, accessor)` describes any other field without unit, `accessor` converts the data
+/// into a [`FieldValue`].
+macro_rules! log_field {
+    ($name:literal, $unit:literal, $precision:literal, $field:ident) => {
+        $crate::app::logger::LogField {
+            name: $name,
+            unit: $unit,
+            column: concat!($name, " [", $unit, "]"),
+            precision: $precision,
+            value: |data| $crate::app::logger::FieldValue::Float(data.$field.load(core::sync::atomic::Ordering::Relaxed)),
+        }
+    };
+    ($name:literal, $precision:literal, $field:ident) => {
+        $crate::app::logger::LogField {
+            name: $name,
+            unit: "",
+            column: $name,
+            precision: $precision,
+            value: |data| $crate::app::logger::FieldValue::Float(data.$field.load(core::sync::atomic::Ordering::Relaxed)),
+        }
+    };
+    ($name:literal, $value:expr) => {
+        $crate::app::logger::LogField {
+            name: $name,
+            unit: "",
+            column: $name,
+            precision: 0,
+            value: $value,
+        }
+    };
+}
+pub(crate) use log_field;
+
+/// Columns of the data log
+///
+/// The single source of the column headers and values of the CSV and binary logs. The regulator mode
+/// is text and only part of the CSV log.
+pub trait LoggerMeta: Sized + 'static {
+    const FIELDS: &'static [LogField<Self>];
+}
+
+/// Column headers of the fields selected by `columns`, a bit mask over all fields
+fn column_names(columns: u32) -> impl Iterator<Item = &'static str> {
+    ProcessData::FIELDS
+        .iter()
+        .map(|f| f.column)
+        .chain(Setpoint::FIELDS.iter().map(|f| f.column))
+        .enumerate()
+        .filter(move |(i, _)| is_selected(columns, *i))
+        .map(|(_, name)| name)
+}
+
+fn is_selected(columns: u32, index: usize) -> bool {
+    columns & (1 << index) != 0
+}
+
+/// Writes the CSV values of the fields of `data` selected by `columns`, starting at field `offset`
+fn write_csv_fields<T: LoggerMeta>(
+    line: &mut String<LINE_LEN>,
+    data: &T,
+    columns: u32,
+    offset: usize,
+) -> Result<(), LoggerError> {
+    for (i, field) in T::FIELDS.iter().enumerate() {
+        if is_selected(columns, offset + i) {
+            write!(line, ";{}", field.display(data))?;
         }
     }
+    Ok(())
 }
 
-pub trait LoggerMeta {
-    fn get_meta(&self) -> String<LINE_LEN>;
+/// Values of the fields of `data`, as logged to binary logs
+fn field_values<T: LoggerMeta>(data: &T) -> impl Iterator<Item = f32> + '_ {
+    T::FIELDS.iter().map(move |f| (f.value)(data).as_f32())
 }
@@ -0,0 +1,166 @@
+//! Maintenance reminders
+//!
+//! Each maintenance item counts engine hours or alternator Ah from the lifetime statistics since its
+//! last service. Once the configured interval is reached, the item is due and shown in the UI until the
+//! service is confirmed by DecLong in the off state, which starts the next interval.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive;
+
+use crate::app::config::{IntervalUnit, MaintenanceInterval, CONFIG};
+use crate::app::journal::{self, EntryKind};
+use crate::app::stats::{Statistics, Totals, STATS, STATS_SAVE};
+
+pub const MAINTENANCE_ITEMS: usize = 3;
+
+/// bit mask of the due items, updated by the stats task
+static DUE: AtomicU8 = AtomicU8::new(0);
+
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
+pub enum MaintenanceItem {
+    Belt = 0,
+    Brushes = 1,
+    Bearings = 2,
+}
+
+impl MaintenanceItem {
+    /// short name for UI and journal
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaintenanceItem::Belt => "Belt",
+            MaintenanceItem::Brushes => "Brushes",
+            MaintenanceItem::Bearings => "Bearings",
+        }
+    }
+}
+
+/// Lifetime counters at the last service of an item
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ServiceMark {
+    pub engine_s: f64,
+    pub alt_ah: f64,
+}
+
+impl ServiceMark {
+    pub const fn new() -> Self {
+        Self {
+            engine_s: 0.,
+            alt_ah: 0.,
+        }
+    }
+
+    pub fn at(totals: &Totals) -> Self {
+        Self {
+            engine_s: totals.engine_s,
+            alt_ah: totals.alt_ah,
+        }
+    }
+
+    /// hours or Ah since the service
+    pub fn usage(&self, lifetime: &Totals, unit: IntervalUnit) -> f64 {
+        match unit {
+            IntervalUnit::Hours => (lifetime.engine_s - self.engine_s) / 3600.,
+            IntervalUnit::AmpHours => lifetime.alt_ah - self.alt_ah,
+        }
+    }
+}
+
+/// Bit mask of the due items
+pub fn due_mask(stats: &Statistics, intervals: &[MaintenanceInterval; MAINTENANCE_ITEMS]) -> u8 {
+    let mut mask = 0;
+    for (i, (mark, interval)) in stats.service.iter().zip(intervals).enumerate() {
+        if interval.threshold > 0. && mark.usage(&stats.lifetime, interval.unit) >= interval.threshold as f64 {
+            mask |= 1 << i;
+        }
+    }
+    mask
+}
+
+fn first_of(mask: u8) -> Option<MaintenanceItem> {
+    (0..MAINTENANCE_ITEMS as u8)
+        .find(|i| mask & (1 << i) != 0)
+        .and_then(MaintenanceItem::from_u8)
+}
+
+/// The first due item, shown in the UI and reset by the operator
+pub fn first_due() -> Option<MaintenanceItem> {
+    first_of(DUE.load(Ordering::Relaxed))
+}
+
+fn current_mask() -> u8 {
+    let intervals = CONFIG.lock(|c| c.borrow().maintenance);
+    STATS.lock(|s| due_mask(&s.borrow(), &intervals))
+}
+
+/// Records that `item` has been serviced, the next interval starts now
+pub fn reset(item: MaintenanceItem) {
+    STATS.lock(|s| {
+        let mut s = s.borrow_mut();
+        s.service[item as usize] = ServiceMark::at(&s.lifetime);
+    });
+    DUE.store(current_mask(), Ordering::Relaxed);
+    STATS_SAVE.signal(());
+    info!("maintenance done: {}", item.name());
+    journal::record(EntryKind::Maintenance, format_args!("{} done", item.name()));
+}
+
+/// Updates the due items and journals the ones that became due, called by the stats task
+pub fn check() {
+    let mask = current_mask();
+    let previous = DUE.swap(mask, Ordering::Relaxed);
+    for i in 0..MAINTENANCE_ITEMS as u8 {
+        if mask & !previous & (1 << i) != 0 {
+            if let Some(item) = MaintenanceItem::from_u8(i) {
+                warn!("maintenance due: {}", item.name());
+                journal::record(EntryKind::Maintenance, format_args!("{} due", item.name()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVALS: [MaintenanceInterval; MAINTENANCE_ITEMS] = [
+        MaintenanceInterval {
+            unit: IntervalUnit::Hours,
+            threshold: 10.,
+        },
+        MaintenanceInterval {
+            unit: IntervalUnit::AmpHours,
+            threshold: 1000.,
+        },
+        MaintenanceInterval {
+            unit: IntervalUnit::Hours,
+            threshold: 0.,
+        },
+    ];
+
+    #[test]
+    fn test_due() {
+        let mut stats = Statistics::new();
+        stats.lifetime.engine_s = 9. * 3600.;
+        stats.lifetime.alt_ah = 1200.;
+        assert_eq!(due_mask(&stats, &INTERVALS), 0b010);
+        assert_eq!(first_of(0b010), Some(MaintenanceItem::Brushes));
+
+        stats.lifetime.engine_s = 10. * 3600.;
+        assert_eq!(due_mask(&stats, &INTERVALS), 0b011);
+        assert_eq!(first_of(0b011), Some(MaintenanceItem::Belt));
+        assert_eq!(first_of(0), None);
+    }
+
+    #[test]
+    fn test_reset_starts_next_interval() {
+        let mut stats = Statistics::new();
+        stats.lifetime.engine_s = 12. * 3600.;
+        stats.service[0] = ServiceMark::at(&stats.lifetime);
+        assert_eq!(due_mask(&stats, &INTERVALS), 0);
+        stats.lifetime.engine_s = 22. * 3600.;
+        assert_eq!(due_mask(&stats, &INTERVALS), 0b001);
+    }
+}
@@ -1,5 +1,18 @@
+pub mod alarm;
+pub mod backlight;
+pub mod clock;
+pub mod config;
 pub mod control;
+pub mod history;
+pub mod journal;
+pub mod limit;
 pub mod shared;
+pub mod stats;
 pub mod mode;
+pub mod recorder;
+pub mod rpm;
+pub mod screenshot;
 pub mod victron;
 pub mod logger;
+pub mod maintenance;
+pub mod settings;
@@ -1,15 +1,30 @@
+use core::sync::atomic::Ordering;
 use heapless::{format, String};
 use libm::{fmaxf, fminf};
 use static_cell::make_static;
 use statig::prelude::*;
 
+use crate::app::alarm;
+use crate::app::backlight;
+use crate::app::config::{self, ConfigError};
 use crate::app::control::Controller;
-use crate::app::shared::{ButtonEvent, ReceiverType, RegulatorEvent, RpmEvent, CONTROLLER, REGULATOR_MODE, RM_LEN};
+use crate::app::history;
+use crate::app::journal::{self, EntryKind};
+use crate::app::maintenance;
+use crate::app::recorder::{self, Trigger};
+use crate::app::settings;
+use crate::app::shared::{
+    page, set_fault, set_page, ButtonEvent, Fault, Page, ReceiverType, RegulatorEvent, RpmEvent, TemperatureEvent,
+    CONTROLLER, PROCESS_DATA, REGULATOR_MODE, RM_LEN, RPM_MAX, RPM_MIN,
+};
 
 /// Operating mode of the regulator
 ///
 /// This central state machine processes all events generated by sensor data and user input.
 /// It triggers the controller to act according to the current operating mode.
+///
+/// Button events a state does not use are passed to the `navigation` superstate, which switches the UI
+/// pages. OkShort is always handled by the states, so the emergency stop never depends on the page.
 #[derive(Default, Debug)]
 pub struct RegulatorMode;
 
@@ -21,6 +36,8 @@ pub struct RegulatorMode;
 )]
 impl RegulatorMode {
     const DUMMY_STR: String<RM_LEN> = String::new();
+    const CALIBRATION_DEFAULT_RPM: f32 = 1000.;
+    const CALIBRATION_RPM_STEP: f32 = 50.;
 
     /// Startup state - no-op until the startup is complete
     #[state]
@@ -31,13 +48,54 @@ impl RegulatorMode {
         }
     }
 
+    /// Superstate of all operating states - Inc/Dec switch the UI pages unless a state uses them,
+    /// IncLong selects the time window of the trends page
+    ///
+    /// A double press always switches the page, so the pages can be reached while Inc/Dec adjust the
+    /// setpoint on the overview page.
+    #[superstate]
+    async fn navigation(event: &RegulatorEvent) -> Outcome<State> {
+        match event {
+            RegulatorEvent::Button(ButtonEvent::IncShort(_)) => {
+                settings::cancel();
+                set_page(page().next());
+                Handled
+            }
+            RegulatorEvent::Button(ButtonEvent::DecShort(_)) => {
+                settings::cancel();
+                set_page(page().previous());
+                Handled
+            }
+            RegulatorEvent::Button(ButtonEvent::IncLong) if page() == Page::Trends => {
+                history::next_window();
+                Handled
+            }
+            _ => Handled,
+        }
+    }
+
     /// Off state - no field current, no RPM measurement possible
-    #[state(entry_action = "enter_off")]
+    ///
+    /// The settings menu can only be operated in this state.
+    #[state(superstate = "navigation", entry_action = "enter_off")]
     async fn off(event: &RegulatorEvent) -> Outcome<State> {
         match event {
+            RegulatorEvent::Rpm(rpm) => {
+                Self::set_rpm_band(*rpm);
+                Handled
+            }
+            RegulatorEvent::Button(button) if page() == Page::Settings && settings::handle(*button) => Handled,
             RegulatorEvent::Button(button) => match button {
                 ButtonEvent::OkLong => Transition(State::idle()),
-                _ => Handled,
+
+                // confirm the service of the maintenance item shown in the UI
+                ButtonEvent::DecLong => {
+                    if let Some(item) = maintenance::first_due() {
+                        maintenance::reset(item);
+                    }
+                    Handled
+                }
+                _ => Super,
             },
             _ => Handled,
         }
@@ -45,50 +103,105 @@ impl RegulatorMode {
 
     /// Idle state - field current is set to 1.0A to allow for RPM measurement is possible
     /// field current is not controlled, no significant charging current
-    #[state(entry_action = "enter_idle")]
+    #[state(superstate = "navigation", entry_action = "enter_idle")]
     async fn idle(event: &RegulatorEvent) -> Outcome<State> {
         match event {
-            RegulatorEvent::Rpm(rpm) => match rpm {
-                // automatic transition by exceeding RPM_MIN
-                RpmEvent::Normal => Transition(State::charging()),
-                _ => Handled,
-            },
+            RegulatorEvent::Rpm(rpm) => {
+                Self::set_rpm_band(*rpm);
+                match rpm {
+                    // automatic transition by exceeding the configured minimum RPM
+                    RpmEvent::HighIdle | RpmEvent::Normal => Transition(State::charging()),
+                    _ => Handled,
+                }
+            }
             RegulatorEvent::Button(button) => match button {
                 // manual transition to charging by IncLong
                 ButtonEvent::IncLong => Transition(State::charging()),
 
-                // manual emergency stop by DecLong
+                // start RPM calibration wizard by OkLong
+                ButtonEvent::OkLong => Transition(State::calibrating(Self::CALIBRATION_DEFAULT_RPM)),
+
+                // manual emergency stop by OkShort
                 ButtonEvent::OkShort(_) => Transition(State::off()),
-                _ => Handled,
+                _ => Super,
+            },
+            _ => Handled,
+        }
+    }
+
+    /// RPM calibration wizard - idle field current, no charging
+    ///
+    /// The operator holds the engine at a known RPM and sets the same value as `reference_rpm` with
+    /// the Inc/Dec buttons on the overview page. OkLong stores the measured pulses per engine revolution,
+    /// DecLong cancels.
+    #[state(superstate = "navigation", entry_action = "enter_calibrating")]
+    async fn calibrating(reference_rpm: &mut f32, event: &RegulatorEvent) -> Outcome<State> {
+        match event {
+            RegulatorEvent::Rpm(rpm) => {
+                Self::set_rpm_band(*rpm);
+                Handled
+            }
+            RegulatorEvent::Button(button) => match button {
+                ButtonEvent::IncShort(1) if page() == Page::Overview => {
+                    *reference_rpm = fminf(*reference_rpm + Self::CALIBRATION_RPM_STEP, RPM_MAX as f32);
+                    Self::show_calibration(*reference_rpm);
+                    Handled
+                }
+
+                ButtonEvent::DecShort(1) if page() == Page::Overview => {
+                    *reference_rpm = fmaxf(*reference_rpm - Self::CALIBRATION_RPM_STEP, RPM_MIN as f32);
+                    Self::show_calibration(*reference_rpm);
+                    Handled
+                }
+
+                // store the calibration, stay in the wizard if the measurement is not plausible
+                ButtonEvent::OkLong => match Self::calibrate(*reference_rpm) {
+                    Ok(()) => Transition(State::idle()),
+                    Err(err) => {
+                        warn!("RPM calibration failed: {:?}", err);
+                        Handled
+                    }
+                },
+
+                // cancel calibration by DecLong
+                ButtonEvent::DecLong => Transition(State::idle()),
+
+                // manual emergency stop by OkShort
+                ButtonEvent::OkShort(_) => Transition(State::off()),
+                _ => Super,
             },
             _ => Handled,
         }
     }
 
     /// Charging is active
-    #[state(entry_action = "enter_charging")]
+    #[state(superstate = "navigation", entry_action = "enter_charging")]
     async fn charging(event: &RegulatorEvent) -> Outcome<State> {
         match event {
-            RegulatorEvent::Rpm(rpm) => match rpm {
-                // automatic transition by falling below RPM_MIN
-                RpmEvent::Low => Transition(State::idle()),
-                _ => Handled,
-            },
+            RegulatorEvent::Rpm(rpm) => {
+                // reduced charging while the engine is idled up
+                Self::set_rpm_band(*rpm);
+                match rpm {
+                    // automatic transition by falling below the configured minimum RPM
+                    RpmEvent::Low => Transition(State::idle()),
+                    _ => Handled,
+                }
+            }
             RegulatorEvent::Button(button) => match button {
-                // manual setpoint control
-                ButtonEvent::IncShort(count) => {
+                // manual setpoint control on the overview page
+                ButtonEvent::IncShort(1) if page() == Page::Overview => {
                     CONTROLLER.lock(|c| {
                         let c: &mut Controller = &mut c.borrow_mut();
-                        c.adjust_target_factor_inc(fminf(0.05 * (*count as f32), 1.));
+                        c.adjust_target_factor_inc(0.05);
                     });
                     Handled
                 }
 
-                // manual setpoint control
-                ButtonEvent::DecShort(count) => {
+                // manual setpoint control on the overview page
+                ButtonEvent::DecShort(1) if page() == Page::Overview => {
                     CONTROLLER.lock(|c| {
                         let c: &mut Controller = &mut c.borrow_mut();
-                        c.adjust_target_factor_inc(fmaxf(-0.05 * (*count as f32), -1.));
+                        c.adjust_target_factor_inc(-0.05);
                     });
                     Handled
                 }
@@ -96,9 +209,9 @@ impl RegulatorMode {
                 // manual transition to idle by DecLong
                 ButtonEvent::DecLong => Transition(State::idle()),
 
-                // manual emergency stop by DecLong
+                // manual emergency stop by OkShort
                 ButtonEvent::OkShort(_) => Transition(State::off()),
-                _ => Handled,
+                _ => Super,
             },
             _ => Handled,
         }
@@ -122,6 +235,17 @@ impl RegulatorMode {
         });
     }
 
+    #[action]
+    async fn enter_calibrating(&mut self) {
+        info!("entering calibration state");
+        // the wizard is shown and operated on the overview page
+        set_page(Page::Overview);
+        CONTROLLER.lock(|c| {
+            let c: &mut Controller = &mut c.borrow_mut();
+            c.start_idle();
+        });
+    }
+
     #[action]
     async fn enter_off(&mut self) {
         info!("entering off state");
@@ -133,16 +257,57 @@ impl RegulatorMode {
 }
 
 impl RegulatorMode {
-    /// update state name in UI
+    /// update state name in UI, capture the transition in the flight recorder and the journal
     async fn after_transition(&mut self, source: &State, target: &State, _context: &mut ()) {
-        let state_name = format!(RM_LEN; "{:?}", target).unwrap_or_else(|_| Self::DUMMY_STR);
         trace!("after_transition: {:?} -> {:?}", source, target);
+        journal::record(EntryKind::StateEntered, format_args!("{:?}", target));
+        if !matches!(source, State::Startup {}) {
+            recorder::trigger(Trigger::Transition);
+        }
+        match target {
+            State::Calibrating { reference_rpm } => Self::show_calibration(*reference_rpm),
+            _ => {
+                let state_name = format!(RM_LEN; "{:?}", target).unwrap_or_else(|_| Self::DUMMY_STR);
+                Self::set_mode_text(&state_name);
+            }
+        }
+    }
+
+    fn set_mode_text(text: &String<RM_LEN>) {
         REGULATOR_MODE.lock(|rm| {
             let rm: &mut String<RM_LEN> = &mut rm.borrow_mut();
             rm.clear();
-            rm.push_str(&state_name).unwrap_or_else(|_| ()); // should never fail, as both strings are of RM_LEN
+            rm.push_str(text).unwrap_or_else(|_| ()); // should never fail, as both strings are of RM_LEN
         });
     }
+
+    /// show the reference RPM of the calibration wizard in the UI
+    fn show_calibration(reference_rpm: f32) {
+        let text = format!(RM_LEN; "Cal {:.0}", reference_rpm).unwrap_or_else(|_| Self::DUMMY_STR);
+        Self::set_mode_text(&text);
+    }
+
+    /// keep the controller informed about the RPM band (high idle, engine start), even if it does not
+    /// cause a transition
+    fn set_rpm_band(band: RpmEvent) {
+        CONTROLLER.lock(|c| {
+            let c: &mut Controller = &mut c.borrow_mut();
+            c.set_rpm_band(band);
+        });
+    }
+
+    /// stores the pulses per engine revolution measured at `reference_rpm` in the persistent config
+    fn calibrate(reference_rpm: f32) -> Result<(), ConfigError> {
+        let pulse_rate = PROCESS_DATA.pulse_rate.load(Ordering::Relaxed);
+        let pulses_per_rev = pulse_rate * 60. / reference_rpm;
+        info!(
+            "RPM calibration: {} Hz at {} rpm -> {} pulses/rev",
+            pulse_rate, reference_rpm, pulses_per_rev
+        );
+        config::modify("rpm_pulses_per_rev", |c| c.rpm_pulses_per_rev, |c| {
+            c.rpm_pulses_per_rev = pulses_per_rev
+        })
+    }
 }
 
 #[embassy_executor::task]
@@ -151,6 +316,26 @@ pub async fn regulator_mode_task(receiver: ReceiverType) -> ! {
     loop {
         let evt = receiver.receive().await;
         debug!("received event: {:?}", evt);
+        // RPM band changes can be frequent around a threshold, the transitions they cause are journaled
+        if !matches!(evt, RegulatorEvent::Rpm(_)) {
+            journal::record(EntryKind::Event, format_args!("{:?}", evt));
+        }
+        // a button press on a dark display only wakes it up
+        if let RegulatorEvent::Button(button) = evt {
+            if backlight::wake() && !matches!(button, ButtonEvent::OkShort(_)) {
+                continue;
+            }
+        }
+        // acknowledging an alarm takes precedence over the other uses of IncLong
+        if matches!(evt, RegulatorEvent::Button(ButtonEvent::IncLong)) && alarm::acknowledge() {
+            continue;
+        }
+        if let RegulatorEvent::Temperature(temperature) = evt {
+            let overheated = matches!(temperature, TemperatureEvent::Overheated);
+            if set_fault(Fault::Overheated, overheated) && overheated {
+                recorder::trigger(Trigger::Fault);
+            }
+        }
         state_machine.handle(&evt).await;
         // intentionally no timer/ticker here, loop is inhibited by receive() and handle()
     }
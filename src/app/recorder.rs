@@ -0,0 +1,278 @@
+//! Flight recorder
+//!
+//! Samples all logged values at the controller rate into a ring buffer. A trigger (fault, mode
+//! transition or manual) freezes the buffer after the post-trigger time, then the data logger writes it
+//! to a separate file on the SD card and releases the buffer for the next trigger.
+//!
+//! A fault is never lost to a manual or transition recording: it overwrites one still in progress or
+//! waiting to be saved, and the data logger discards those while there is no SD card, so the buffer keeps
+//! the pre-trigger window of the next fault. The buffer is in internal RAM, 150 samples of 80 bytes.
+
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+use num_derive::{FromPrimitive, ToPrimitive};
+
+use crate::app::control::Controller;
+use crate::app::logger::{record_values, RECORD_FIELDS};
+
+const PRE_TRIGGER_S: u64 = 10;
+const POST_TRIGGER_S: u64 = 5;
+const SAMPLES_PER_S: u64 = 1000 / Controller::LOOP_INTERVAL_MS;
+const PRE_TRIGGER_SAMPLES: usize = (PRE_TRIGGER_S * SAMPLES_PER_S) as usize;
+const POST_TRIGGER_SAMPLES: usize = (POST_TRIGGER_S * SAMPLES_PER_S) as usize;
+
+/// samples of a recording
+pub const SAMPLES: usize = PRE_TRIGGER_SAMPLES + POST_TRIGGER_SAMPLES;
+
+pub type RecorderType = FlightRecorder<SAMPLES>;
+
+pub static RECORDER: Mutex<CriticalSectionRawMutex, RefCell<RecorderType>> =
+    Mutex::new(RefCell::new(FlightRecorder::new(POST_TRIGGER_SAMPLES)));
+
+/// Cause of a recording, logged in the `Trigger` column of the recording file
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
+pub enum Trigger {
+    Manual = 1,
+    Transition = 2,
+    Fault = 3,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Sample {
+    pub uptime_ms: u32,
+    pub values: [f32; RECORD_FIELDS],
+}
+
+impl Sample {
+    const EMPTY: Self = Self {
+        uptime_ms: 0,
+        values: [f32::NAN; RECORD_FIELDS],
+    };
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum RecorderState {
+    Recording,
+    Triggered { trigger: Trigger, trigger_ms: u32, remaining: usize },
+    Complete { trigger: Trigger, trigger_ms: u32 },
+}
+
+/// Ring buffer of the last `N` samples, frozen after a trigger until the recording has been saved
+#[derive(Debug)]
+pub struct FlightRecorder<const N: usize> {
+    samples: [Sample; N],
+    /// index of the next sample to be written
+    head: usize,
+    len: usize,
+    post_trigger_samples: usize,
+    state: RecorderState,
+}
+
+impl<const N: usize> FlightRecorder<N> {
+    pub const fn new(post_trigger_samples: usize) -> Self {
+        Self {
+            samples: [Sample::EMPTY; N],
+            head: 0,
+            len: 0,
+            post_trigger_samples,
+            state: RecorderState::Recording,
+        }
+    }
+
+    pub fn sample(&mut self, sample: Sample) {
+        let remaining = match &mut self.state {
+            RecorderState::Complete { .. } => return,
+            RecorderState::Recording => None,
+            RecorderState::Triggered { remaining, .. } => {
+                *remaining -= 1;
+                Some(*remaining)
+            }
+        };
+
+        self.samples[self.head] = sample;
+        self.head = (self.head + 1) % N;
+        self.len = (self.len + 1).min(N);
+
+        if let (Some(0), RecorderState::Triggered { trigger, trigger_ms, .. }) = (remaining, self.state) {
+            self.state = RecorderState::Complete { trigger, trigger_ms };
+        }
+    }
+
+    /// Starts the post-trigger phase, returns `false` if a recording is already in progress
+    ///
+    /// A fault overwrites a recording of another trigger, also a complete one, whose samples are kept as
+    /// the pre-trigger window.
+    pub fn trigger(&mut self, trigger: Trigger, now_ms: u32) -> bool {
+        match self.state {
+            RecorderState::Recording => {}
+            RecorderState::Triggered { trigger: current, .. } | RecorderState::Complete { trigger: current, .. }
+                if trigger == Trigger::Fault && current != Trigger::Fault => {}
+            _ => return false,
+        }
+        self.state = if self.post_trigger_samples == 0 {
+            RecorderState::Complete {
+                trigger,
+                trigger_ms: now_ms,
+            }
+        } else {
+            RecorderState::Triggered {
+                trigger,
+                trigger_ms: now_ms,
+                remaining: self.post_trigger_samples,
+            }
+        };
+        true
+    }
+
+    /// Trigger and trigger time of a complete recording waiting to be saved
+    pub fn complete(&self) -> Option<(Trigger, u32)> {
+        match self.state {
+            RecorderState::Complete { trigger, trigger_ms } => Some((trigger, trigger_ms)),
+            _ => None,
+        }
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.len
+    }
+
+    /// Sample `index`, counted from the oldest one
+    pub fn get(&self, index: usize) -> Option<&Sample> {
+        if index >= self.len {
+            return None;
+        }
+        Some(&self.samples[(self.head + N - self.len + index) % N])
+    }
+
+    /// Discards the samples and starts recording again
+    pub fn release(&mut self) {
+        self.len = 0;
+        self.state = RecorderState::Recording;
+    }
+
+    /// Starts recording again without saving a complete recording, unless it is a fault
+    pub fn discard_unsaved(&mut self) {
+        if matches!(self.state, RecorderState::Complete { trigger, .. } if trigger != Trigger::Fault) {
+            self.state = RecorderState::Recording;
+        }
+    }
+}
+
+/// Records the current values, called by the controller task
+pub fn sample() {
+    let sample = Sample {
+        uptime_ms: Instant::now().as_millis() as u32,
+        values: record_values(),
+    };
+    RECORDER.lock(|r| r.borrow_mut().sample(sample));
+}
+
+/// Starts a recording, ignored while the previous one is not saved yet unless it is a fault
+pub fn trigger(trigger: Trigger) {
+    let now_ms = Instant::now().as_millis() as u32;
+    if RECORDER.lock(|r| r.borrow_mut().trigger(trigger, now_ms)) {
+        info!("flight recorder triggered: {:?}", trigger);
+    } else {
+        debug!("flight recorder busy, trigger ignored: {:?}", trigger);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(uptime_ms: u32) -> Sample {
+        Sample {
+            uptime_ms,
+            ..Sample::EMPTY
+        }
+    }
+
+    #[test]
+    fn test_ring_order() {
+        let mut r = FlightRecorder::<4>::new(2);
+        for t in 0..6 {
+            r.sample(sample(t));
+        }
+        assert_eq!(r.sample_count(), 4);
+        assert_eq!(r.get(0).unwrap().uptime_ms, 2);
+        assert_eq!(r.get(3).unwrap().uptime_ms, 5);
+        assert!(r.get(4).is_none());
+    }
+
+    #[test]
+    fn test_post_trigger() {
+        let mut r = FlightRecorder::<4>::new(2);
+        r.sample(sample(0));
+        r.sample(sample(1));
+        assert!(r.trigger(Trigger::Manual, 1));
+        r.sample(sample(2));
+        assert_eq!(r.complete(), None);
+        r.sample(sample(3));
+        assert_eq!(r.complete(), Some((Trigger::Manual, 1)));
+
+        // frozen until released
+        r.sample(sample(4));
+        assert_eq!(r.get(3).unwrap().uptime_ms, 3);
+        r.release();
+        assert_eq!(r.sample_count(), 0);
+        assert_eq!(r.complete(), None);
+    }
+
+    #[test]
+    fn test_retrigger_ignored() {
+        let mut r = FlightRecorder::<4>::new(2);
+        assert!(r.trigger(Trigger::Fault, 0));
+        assert!(!r.trigger(Trigger::Transition, 0));
+        assert!(!r.trigger(Trigger::Fault, 0));
+        r.sample(sample(0));
+        r.sample(sample(1));
+        assert_eq!(r.complete(), Some((Trigger::Fault, 0)));
+        assert!(!r.trigger(Trigger::Manual, 2));
+        assert!(!r.trigger(Trigger::Fault, 2));
+        r.discard_unsaved();
+        assert_eq!(r.complete(), Some((Trigger::Fault, 0)));
+    }
+
+    #[test]
+    fn test_fault_overwrites() {
+        let mut r = FlightRecorder::<4>::new(2);
+        assert!(r.trigger(Trigger::Transition, 0));
+        r.sample(sample(0));
+        // restarts the post-trigger phase
+        assert!(r.trigger(Trigger::Fault, 1));
+        r.sample(sample(1));
+        assert_eq!(r.complete(), None);
+        r.sample(sample(2));
+        assert_eq!(r.complete(), Some((Trigger::Fault, 1)));
+
+        r.release();
+        assert!(r.trigger(Trigger::Manual, 3));
+        r.sample(sample(3));
+        r.sample(sample(4));
+        assert_eq!(r.complete(), Some((Trigger::Manual, 3)));
+        // not saved, sampling continues after the fault
+        assert!(r.trigger(Trigger::Fault, 5));
+        r.sample(sample(5));
+        r.sample(sample(6));
+        assert_eq!(r.complete(), Some((Trigger::Fault, 5)));
+        assert_eq!(r.get(0).unwrap().uptime_ms, 3);
+        assert_eq!(r.get(3).unwrap().uptime_ms, 6);
+    }
+
+    #[test]
+    fn test_discard_unsaved() {
+        let mut r = FlightRecorder::<4>::new(1);
+        assert!(r.trigger(Trigger::Manual, 0));
+        r.sample(sample(0));
+        assert_eq!(r.complete(), Some((Trigger::Manual, 0)));
+        r.discard_unsaved();
+        assert_eq!(r.complete(), None);
+        r.sample(sample(1));
+        assert_eq!(r.sample_count(), 2);
+    }
+}
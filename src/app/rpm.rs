@@ -0,0 +1,94 @@
+use crate::app::shared::RpmEvent;
+use crate::util::zc::detect_zero_crossing_with_hysteresis;
+
+/// Classifies the engine RPM into the bands low, high idle and normal
+///
+/// Each band boundary is a zero-crossing detector with its own hysteresis, so noise around one threshold
+/// does not toggle the band. An event is only emitted if the band changes.
+#[derive(Debug)]
+pub struct RpmClassifier {
+    band: RpmEvent,
+    above_min: bool,
+    above_normal: bool,
+}
+
+impl RpmClassifier {
+    pub const fn new() -> Self {
+        Self {
+            band: RpmEvent::Low,
+            above_min: false,
+            above_normal: false,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn band(&self) -> RpmEvent {
+        self.band
+    }
+
+    /// Feeds a new RPM reading, returns the new band if it has changed
+    pub fn update(&mut self, rpm: f32, rpm_min: f32, rpm_normal: f32, hysteresis: f32) -> Option<RpmEvent> {
+        (self.above_min, _) = detect_zero_crossing_with_hysteresis(rpm, rpm_min, hysteresis, self.above_min);
+        (self.above_normal, _) = detect_zero_crossing_with_hysteresis(rpm, rpm_normal, hysteresis, self.above_normal);
+
+        let band = if !self.above_min {
+            RpmEvent::Low
+        } else if self.above_normal {
+            RpmEvent::Normal
+        } else {
+            RpmEvent::HighIdle
+        };
+
+        if band != self.band {
+            self.band = band;
+            Some(band)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: f32 = 500.;
+    const NORMAL: f32 = 1200.;
+    const HYST: f32 = 0.05;
+
+    #[test]
+    fn test_startup_sequence() {
+        let mut c = RpmClassifier::new();
+        assert_eq!(c.update(0., MIN, NORMAL, HYST), None);
+        assert_eq!(c.update(510., MIN, NORMAL, HYST), None); // inside dead band
+        assert_eq!(c.update(800., MIN, NORMAL, HYST), Some(RpmEvent::HighIdle));
+        assert_eq!(c.update(1220., MIN, NORMAL, HYST), None); // inside dead band
+        assert_eq!(c.update(1300., MIN, NORMAL, HYST), Some(RpmEvent::Normal));
+        assert_eq!(c.band(), RpmEvent::Normal);
+    }
+
+    #[test]
+    fn test_hysteresis_on_way_down() {
+        let mut c = RpmClassifier::new();
+        c.update(2000., MIN, NORMAL, HYST);
+        assert_eq!(c.update(1180., MIN, NORMAL, HYST), None);
+        assert_eq!(c.update(1100., MIN, NORMAL, HYST), Some(RpmEvent::HighIdle));
+        assert_eq!(c.update(490., MIN, NORMAL, HYST), None);
+        assert_eq!(c.update(450., MIN, NORMAL, HYST), Some(RpmEvent::Low));
+    }
+
+    #[test]
+    fn test_jump_across_bands() {
+        let mut c = RpmClassifier::new();
+        assert_eq!(c.update(2000., MIN, NORMAL, HYST), Some(RpmEvent::Normal));
+        assert_eq!(c.update(0., MIN, NORMAL, HYST), Some(RpmEvent::Low));
+    }
+
+    #[test]
+    fn test_no_repeated_events() {
+        let mut c = RpmClassifier::new();
+        assert_eq!(c.update(800., MIN, NORMAL, HYST), Some(RpmEvent::HighIdle));
+        assert_eq!(c.update(850., MIN, NORMAL, HYST), None);
+        assert_eq!(c.update(790., MIN, NORMAL, HYST), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Screenshots of the display, saved to the SD card for support tickets and documentation
+//!
+//! A double press of Dec and Inc together requests a screenshot, see `button::COMBO_WINDOW`. The UI
+//! renders the whole screen once more and hands each band to the data logger before it is sent to the
+//! display; the logger writes the bands to a BMP file. Both run in the SPI2 task on the PRO core, so the
+//! regulator is not affected.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
+
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 240;
+
+/// bytes of an RGB565 row, a multiple of 4 as BMP requires
+pub const ROW_LEN: usize = WIDTH * 2;
+
+/// file header, info header and the RGB565 bit masks
+pub const HEADER_LEN: usize = 14 + 40 + 12;
+
+/// size of a screenshot file
+pub const FILE_LEN: usize = HEADER_LEN + ROW_LEN * HEIGHT;
+
+/// a data logger busy with the card does not take the band in time
+const BAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static BAND: Signal<CriticalSectionRawMutex, Band> = Signal::new();
+static SAVED: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Full-width rows of the screen rendered by LVGL, big-endian RGB565
+pub struct Band {
+    /// first row
+    pub y: usize,
+    pub rows: usize,
+    pixels: *const u8,
+}
+
+// the pixels are only read by the data logger while the UI waits in `save`
+unsafe impl Send for Band {}
+
+impl Band {
+    /// # Safety
+    /// `pixels` must point to `rows` rows and stay valid until `save` returns.
+    pub unsafe fn new(y: usize, rows: usize, pixels: *const u8) -> Self {
+        Self { y, rows, pixels }
+    }
+
+    pub fn row(&self, row: usize) -> &[u8] {
+        assert!(row < self.rows);
+        unsafe { core::slice::from_raw_parts(self.pixels.add(row * ROW_LEN), ROW_LEN) }
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.y + self.rows >= HEIGHT
+    }
+}
+
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Returns `true` once per request
+pub fn take_request() -> bool {
+    REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Hands `band` to the data logger and waits until it is written, returns `false` if it was not
+pub async fn save(band: Band) -> bool {
+    SAVED.reset();
+    BAND.signal(band);
+    let saved = with_timeout(BAND_TIMEOUT, SAVED.wait()).await;
+    // not taken by the logger, the pixels are about to become invalid
+    BAND.reset();
+    saved.unwrap_or(false)
+}
+
+/// The next band to be written by the data logger, followed by `saved`
+pub async fn next_band() -> Band {
+    BAND.wait().await
+}
+
+pub fn saved(ok: bool) {
+    SAVED.signal(ok);
+}
+
+/// BMP header of a top-down RGB565 image of the screen
+pub fn bmp_header() -> [u8; HEADER_LEN] {
+    let mut header = [0_u8; HEADER_LEN];
+    let fields: [&[u8]; 15] = [
+        // file header
+        b"BM",
+        &(FILE_LEN as u32).to_le_bytes(),
+        &0_u32.to_le_bytes(),
+        &(HEADER_LEN as u32).to_le_bytes(),
+        // info header, the negative height stores the rows top-down
+        &40_u32.to_le_bytes(),
+        &(WIDTH as i32).to_le_bytes(),
+        &(-(HEIGHT as i32)).to_le_bytes(),
+        &1_u16.to_le_bytes(),
+        &16_u16.to_le_bytes(),
+        // BI_BITFIELDS
+        &3_u32.to_le_bytes(),
+        &image_len.to_le_bytes(),
+        // 72 dpi, no palette
+        &[0x13, 0x0b, 0, 0, 0x13, 0x0b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        // red, green and blue masks
+        &0xf800_u32.to_le_bytes(),
+        &0x07e0_u32.to_le_bytes(),
+        &0x001f_u32.to_le_bytes(),
+    ];
+    let mut pos = 0;
+    for field in fields {
+        header[pos..pos + field.len()].copy_from_slice(field);
+        pos += field.len();
+    }
+    header
+}
+
+/// Converts a big-endian row of the display to the little-endian pixels of BMP
+pub fn to_bmp_row(row: &[u8], bmp_row: &mut [u8]) {
+    for (src, dst) in row.chunks_exact(2).zip(bmp_row.chunks_exact_mut(2)) {
+        dst[0] = src[1];
+        dst[1] = src[0];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bmp_header() {
+        let header = bmp_header();
+        assert_eq!(&header[..2], b"BM");
+        assert_eq!(u32::from_le_bytes(header[2..6].try_into().unwrap()), 66 + 320 * 240 * 2);
+        assert_eq!(u32::from_le_bytes(header[10..14].try_into().unwrap()), 66);
+        assert_eq!(i32::from_le_bytes(header[22..26].try_into().unwrap()), -240);
+        assert_eq!(u16::from_le_bytes(header[28..30].try_into().unwrap()), 16);
+        assert_eq!(u32::from_le_bytes(header[62..66].try_into().unwrap()), 0x001f);
+
+        let mut bmp_row = [0_u8; 4];
+        to_bmp_row(&[0xf8, 0x00, 0x00, 0x1f], &mut bmp_row);
+        assert_eq!(bmp_row, [0x00, 0xf8, 0x1f, 0x00]);
+    }
+}
@@ -0,0 +1,568 @@
+//! Settings menu for editing the persistent configuration with the buttons
+//!
+//! The menu is a list of [`Setting`]s shown on the settings page. In the off state, Inc/Dec select a
+//! setting and OkLong starts editing it. While editing, Inc/Dec change the value by one step (IncLong and
+//! DecLong by ten steps), OkLong applies it through [`config::modify`] and OkShort cancels. Values are
+//! limited to the ranges `Config::validate` checks, which also checks the consistency of the result.
+//!
+//! The last entries set the wall clock and the RTC instead of the configuration, one date or time field
+//! per entry. While the clock is unknown they start from [`UNKNOWN_CLOCK_MS`].
+
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::ops::RangeInclusive;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::String;
+use libm::{fmaxf, fminf, roundf};
+use num_traits::FromPrimitive;
+
+use crate::app::clock::{self, DateTime};
+use crate::app::config::{self, AlarmOutput, ChargeProfile, Config, ConfigError, LogFormat, CONFIG};
+use crate::app::journal::{self, EntryKind};
+use crate::app::logger::RECORD_FIELDS;
+use crate::app::maintenance::MaintenanceItem;
+use crate::app::shared::ButtonEvent;
+use crate::app::victron;
+
+pub static MENU: Mutex<CriticalSectionRawMutex, RefCell<SettingsMenu>> =
+    Mutex::new(RefCell::new(SettingsMenu::new()));
+
+/// steps applied by IncLong/DecLong
+const LONG_STEPS: f32 = 10.;
+
+/// 2025-01-01T00:00:00Z, shown by the clock entries while the wall clock is unknown
+const UNKNOWN_CLOCK_MS: u64 = 1_735_689_600_000;
+
+/// How a setting is shown
+pub enum Kind {
+    /// number with `precision` decimals
+    Number(usize),
+    /// one of several options, the value is the index of the option
+    Choice(fn(u8) -> &'static str),
+}
+
+/// A config parameter editable in the menu, all values are handled as `f32`
+pub struct Setting {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub range: RangeInclusive<f32>,
+    pub step: f32,
+    pub get: fn(&Config) -> f32,
+    pub set: Set,
+}
+
+/// Where an edited value is written
+pub enum Set {
+    /// a config parameter, saved to flash
+    Config(fn(&mut Config, f32)),
+    /// a field of the wall clock, stored in the RTC
+    Clock(fn(&mut DateTime, f32)),
+}
+
+impl Setting {
+    /// `value` for the UI
+    pub fn display(&self, value: f32) -> String<20> {
+        let mut s = String::new();
+        match self.kind {
+            Kind::Number(precision) => write!(s, "{:.*}", precision, value),
+            Kind::Choice(name) => write!(s, "{}", name(value as u8)),
+        }
+        .ok(); // keeps what fits
+        s
+    }
+
+    /// `value` changed by `steps`, rounded to the step and limited to the range
+    fn adjust(&self, value: f32, steps: f32) -> f32 {
+        let value = roundf((value + steps * self.step) / self.step) * self.step;
+        fminf(fmaxf(value, *self.range.start()), *self.range.end())
+    }
+}
+
+fn on_off(index: u8) -> &'static str {
+    if index == 0 {
+        "Off"
+    } else {
+        "On"
+    }
+}
+
+fn profile_name(index: u8) -> &'static str {
+    ChargeProfile::from_u8(index).map_or("?", |p| p.name())
+}
+
+fn alarm_output_name(index: u8) -> &'static str {
+    AlarmOutput::from_u8(index).map_or("?", |o| o.name())
+}
+
+fn log_format_name(index: u8) -> &'static str {
+    LogFormat::from_u8(index).map_or("?", |f| f.name())
+}
+
+/// Current wall clock time to start editing from
+fn clock_now() -> DateTime {
+    clock::now().unwrap_or_else(|| DateTime::from_unix_ms(UNKNOWN_CLOCK_MS))
+}
+
+/// `time` with one field changed, the day limited to the month, e.g. 31 March changed to February
+fn edit_clock(mut time: DateTime, set: fn(&mut DateTime, f32), value: f32) -> DateTime {
+    set(&mut time, value);
+    time.day = time.day.min(clock::days_in_month(time.year, time.month));
+    time
+}
+
+/// Sets the wall clock and the RTC to the current time with one field changed
+fn set_clock(set: fn(&mut DateTime, f32), value: f32) {
+    let time = edit_clock(clock_now(), set, value);
+    clock::set_and_store(&time);
+    journal::record(EntryKind::Event, format_args!("clock set to {}", time));
+}
+
+/// On/Off entry of a column of the data log, `bit` of `Config::log_columns`
+macro_rules! log_column {
+    ($name:literal, $bit:literal) => {
+        Setting {
+            name: concat!("Log ", $name),
+            kind: Kind::Choice(on_off),
+            range: 0.0..=1.0,
+            step: 1.,
+            get: |c| ((c.log_columns >> $bit) & 1) as f32,
+            set: Set::Config(|c, v| c.log_columns = (c.log_columns & !(1 << $bit)) | ((v != 0.) as u32) << $bit),
+        }
+    };
+}
+
+pub static SETTINGS: [Setting; 28 + RECORD_FIELDS] = [
+    Setting {
+        name: "Profile",
+        kind: Kind::Choice(profile_name),
+        range: 0.0..=2.0,
+        step: 1.,
+        get: |c| c.charge_profile as u8 as f32,
+        set: Set::Config(|c, v| c.charge_profile = ChargeProfile::from_u8(v as u8).unwrap_or_default()),
+    },
+    Setting {
+        name: "Capacity Ah",
+        kind: Kind::Number(0),
+        range: Config::BATTERY_CAPACITY_RANGE,
+        step: 10.,
+        get: |c| c.battery_capacity,
+        set: Set::Config(|c, v| c.battery_capacity = v),
+    },
+    Setting {
+        name: "Alt limit A",
+        kind: Kind::Number(0),
+        range: Config::ALT_CURRENT_RANGE,
+        step: 5.,
+        get: |c| c.alt_current_limit,
+        set: Set::Config(|c, v| c.alt_current_limit = v),
+    },
+    Setting {
+        name: "Alt rated A",
+        kind: Kind::Number(0),
+        range: Config::ALT_CURRENT_RANGE,
+        step: 5.,
+        get: |c| c.alt_rated_current,
+        set: Set::Config(|c, v| c.alt_rated_current = v),
+    },
+    Setting {
+        name: "Small alt",
+        kind: Kind::Choice(on_off),
+        range: 0.0..=1.0,
+        step: 1.,
+        get: |c| c.small_alt_mode as u8 as f32,
+        set: Set::Config(|c, v| c.small_alt_mode = v != 0.),
+    },
+    Setting {
+        name: "Small alt %",
+        kind: Kind::Number(0),
+        range: Config::PERCENT_RANGE,
+        step: 5.,
+        get: |c| c.small_alt_percent,
+        set: Set::Config(|c, v| c.small_alt_percent = v),
+    },
+    Setting {
+        name: "RPM min",
+        kind: Kind::Number(0),
+        range: Config::RPM_THRESHOLD_RANGE,
+        step: 50.,
+        get: |c| c.rpm_min,
+        set: Set::Config(|c, v| c.rpm_min = v),
+    },
+    Setting {
+        name: "RPM normal",
+        kind: Kind::Number(0),
+        range: Config::RPM_THRESHOLD_RANGE,
+        step: 50.,
+        get: |c| c.rpm_normal,
+        set: Set::Config(|c, v| c.rpm_normal = v),
+    },
+    // pole pairs and pulley ratio combined, also measured by the calibration wizard
+    Setting {
+        name: "Pulses/rev",
+        kind: Kind::Number(2),
+        range: Config::RPM_PULSES_PER_REV_RANGE,
+        step: 0.05,
+        get: |c| c.rpm_pulses_per_rev,
+        set: Set::Config(|c, v| c.rpm_pulses_per_rev = v),
+    },
+    Setting {
+        name: "Derate C",
+        kind: Kind::Number(0),
+        range: Config::TEMPERATURE_RANGE,
+        step: 1.,
+        get: |c| c.alt_temp_derate,
+        set: Set::Config(|c, v| c.alt_temp_derate = v),
+    },
+    Setting {
+        name: "Max temp C",
+        kind: Kind::Number(0),
+        range: Config::TEMPERATURE_RANGE,
+        step: 1.,
+        get: |c| c.alt_temp_max,
+        set: Set::Config(|c, v| c.alt_temp_max = v),
+    },
+    Setting {
+        name: "Bat shunt",
+        kind: Kind::Choice(victron::device_name),
+        range: Config::VICTRON_DEVICE_RANGE,
+        step: 1.,
+        get: |c| c.victron_battery as f32,
+        set: Set::Config(|c, v| c.victron_battery = v as u8),
+    },
+    Setting {
+        name: "Alt shunt",
+        kind: Kind::Choice(victron::device_name),
+        range: Config::VICTRON_DEVICE_RANGE,
+        step: 1.,
+        get: |c| c.victron_alternator as f32,
+        set: Set::Config(|c, v| c.victron_alternator = v as u8),
+    },
+    Setting {
+        name: "Alarm out",
+        kind: Kind::Choice(alarm_output_name),
+        range: 0.0..=2.0,
+        step: 1.,
+        get: |c| c.alarm_output as u8 as f32,
+        set: Set::Config(|c, v| c.alarm_output = AlarmOutput::from_u8(v as u8).unwrap_or_default()),
+    },
+    Setting {
+        name: "Brightness %",
+        kind: Kind::Number(0),
+        range: Config::PERCENT_RANGE,
+        step: 10.,
+        get: |c| c.backlight_brightness,
+        set: Set::Config(|c, v| c.backlight_brightness = v),
+    },
+    // 0 disables the timeout
+    Setting {
+        name: "Dim after s",
+        kind: Kind::Number(0),
+        range: Config::BACKLIGHT_TIMEOUT_RANGE,
+        step: 30.,
+        get: |c| c.backlight_dim_timeout,
+        set: Set::Config(|c, v| c.backlight_dim_timeout = v),
+    },
+    Setting {
+        name: "Off after s",
+        kind: Kind::Number(0),
+        range: Config::BACKLIGHT_TIMEOUT_RANGE,
+        step: 30.,
+        get: |c| c.backlight_off_timeout,
+        set: Set::Config(|c, v| c.backlight_off_timeout = v),
+    },
+    Setting {
+        name: "Night theme",
+        kind: Kind::Choice(on_off),
+        range: 0.0..=1.0,
+        step: 1.,
+        get: |c| c.night_theme as u8 as f32,
+        set: Set::Config(|c, v| c.night_theme = v != 0.),
+    },
+    // a change starts a new log file
+    Setting {
+        name: "Log format",
+        kind: Kind::Choice(log_format_name),
+        range: 0.0..=1.0,
+        step: 1.,
+        get: |c| c.log_format as u8 as f32,
+        set: Set::Config(|c, v| c.log_format = LogFormat::from_u8(v as u8).unwrap_or_default()),
+    },
+    // 0.1 s needs the binary format
+    Setting {
+        name: "Log every s",
+        kind: Kind::Number(1),
+        range: Config::LOG_INTERVAL_RANGE,
+        step: 0.1,
+        get: |c| c.log_interval,
+        set: Set::Config(|c, v| c.log_interval = v),
+    },
+    // in the order of the log columns, see `logger::column_names`
+    log_column!("RPM", 0),
+    log_column!("Pulses", 1),
+    log_column!("Target", 2),
+    log_column!("Field A", 3),
+    log_column!("Field V", 4),
+    log_column!("Alt A", 5),
+    log_column!("Bat A", 6),
+    log_column!("Bat SoC", 7),
+    log_column!("Bat V", 8),
+    log_column!("Input V", 9),
+    log_column!("Alt C", 10),
+    log_column!("PPS C", 11),
+    log_column!("PPS mode", 12),
+    log_column!("BLE rate", 13),
+    log_column!("Field lim A", 14),
+    log_column!("Field lim V", 15),
+    log_column!("PPS on", 16),
+    log_column!("Contactor", 17),
+    log_column!("Limit", 18),
+    // service intervals in the units of the defaults, 0 disables the reminder
+    Setting {
+        name: "Belt h",
+        kind: Kind::Number(0),
+        range: Config::MAINTENANCE_INTERVAL_RANGE,
+        step: 50.,
+        get: |c| c.maintenance[MaintenanceItem::Belt as usize].threshold,
+        set: Set::Config(|c, v| c.maintenance[MaintenanceItem::Belt as usize].threshold = v),
+    },
+    Setting {
+        name: "Brushes h",
+        kind: Kind::Number(0),
+        range: Config::MAINTENANCE_INTERVAL_RANGE,
+        step: 100.,
+        get: |c| c.maintenance[MaintenanceItem::Brushes as usize].threshold,
+        set: Set::Config(|c, v| c.maintenance[MaintenanceItem::Brushes as usize].threshold = v),
+    },
+    Setting {
+        name: "Bearings Ah",
+        kind: Kind::Number(0),
+        range: Config::MAINTENANCE_INTERVAL_RANGE,
+        step: 10000.,
+        get: |c| c.maintenance[MaintenanceItem::Bearings as usize].threshold,
+        set: Set::Config(|c, v| c.maintenance[MaintenanceItem::Bearings as usize].threshold = v),
+    },
+    Setting {
+        name: "Year",
+        kind: Kind::Number(0),
+        range: 2024.0..=2099.0,
+        step: 1.,
+        get: |_| clock_now().year as f32,
+        set: Set::Clock(|t, v| t.year = v as u16),
+    },
+    Setting {
+        name: "Month",
+        kind: Kind::Number(0),
+        range: 1.0..=12.0,
+        step: 1.,
+        get: |_| clock_now().month as f32,
+        set: Set::Clock(|t, v| t.month = v as u8),
+    },
+    Setting {
+        name: "Day",
+        kind: Kind::Number(0),
+        range: 1.0..=31.0,
+        step: 1.,
+        get: |_| clock_now().day as f32,
+        set: Set::Clock(|t, v| t.day = v as u8),
+    },
+    Setting {
+        name: "Hour UTC",
+        kind: Kind::Number(0),
+        range: 0.0..=23.0,
+        step: 1.,
+        get: |_| clock_now().hour as f32,
+        set: Set::Clock(|t, v| t.hour = v as u8),
+    },
+    // applied at the start of the minute, so OkLong is pressed when the minute begins
+    Setting {
+        name: "Minute",
+        kind: Kind::Number(0),
+        range: 0.0..=59.0,
+        step: 1.,
+        get: |_| clock_now().minute as f32,
+        set: Set::Clock(|t, v| {
+            t.minute = v as u8;
+            t.second = 0;
+            t.millis = 0;
+        }),
+    },
+];
+
+/// What the menu wants done after a button press
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Response {
+    Ignored,
+    Handled,
+    /// write `value` to setting `index`
+    Apply(usize, f32),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SettingsMenu {
+    /// index of the selected setting
+    pub cursor: usize,
+    /// value being edited, not applied yet
+    pub edit: Option<f32>,
+    /// outcome of the last edit, shown until the next one
+    pub message: &'static str,
+}
+
+impl SettingsMenu {
+    pub const fn new() -> Self {
+        Self {
+            cursor: 0,
+            edit: None,
+            message: "",
+        }
+    }
+
+    fn handle(&mut self, button: ButtonEvent, config: &Config) -> Response {
+        let setting = &SETTINGS[self.cursor];
+        match (self.edit, button) {
+            (None, ButtonEvent::IncShort(1)) => self.cursor = (self.cursor + 1) % SETTINGS.len(),
+            (None, ButtonEvent::DecShort(1)) => self.cursor = (self.cursor + SETTINGS.len() - 1) % SETTINGS.len(),
+            (None, ButtonEvent::OkLong) => {
+                self.edit = Some((setting.get)(config));
+                self.message = "";
+            }
+            (Some(value), ButtonEvent::IncShort(1)) => self.edit = Some(setting.adjust(value, 1.)),
+            (Some(value), ButtonEvent::DecShort(1)) => self.edit = Some(setting.adjust(value, -1.)),
+            (Some(value), ButtonEvent::IncLong) => self.edit = Some(setting.adjust(value, LONG_STEPS)),
+            (Some(value), ButtonEvent::DecLong) => self.edit = Some(setting.adjust(value, -LONG_STEPS)),
+            (Some(value), ButtonEvent::OkLong) => {
+                self.edit = None;
+                return Response::Apply(self.cursor, value);
+            }
+            (Some(_), ButtonEvent::OkShort(_)) => {
+                self.edit = None;
+                self.message = "Cancelled";
+            }
+            _ => return Response::Ignored,
+        }
+        Response::Handled
+    }
+
+    fn applied(&mut self, result: Result<(), ConfigError>) {
+        self.message = match result {
+            Ok(()) => "Saved",
+            Err(ConfigError::Inconsistent(_)) => "Inconsistent",
+            Err(_) => "Not saved",
+        };
+    }
+}
+
+/// Passes a button event to the menu, returns `false` if the menu does not use it
+///
+/// Only called in the off state, so the configuration and the clock never change while charging.
+pub fn handle(button: ButtonEvent) -> bool {
+    let config = CONFIG.lock(|c| c.borrow().clone());
+    let response = MENU.lock(|m| m.borrow_mut().handle(button, &config));
+    if let Response::Apply(index, value) = response {
+        let result = match SETTINGS[index].set {
+            Set::Config(set) => {
+                let setting = &SETTINGS[index];
+                config::modify(setting.name, |c| setting.display((setting.get)(c)), |c| set(c, value))
+            }
+            Set::Clock(set) => {
+                set_clock(set, value);
+                Ok(())
+            }
+        };
+        if let Err(e) = &result {
+            warn!("setting {} not applied: {:?}", SETTINGS[index].name, e);
+        }
+        MENU.lock(|m| m.borrow_mut().applied(result));
+    }
+    response != Response::Ignored
+}
+
+/// Abandons an edit in progress, e.g. when leaving the settings page
+pub fn cancel() {
+    MENU.lock(|m| m.borrow_mut().edit = None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_in_range() {
+        let config = Config::new();
+        for setting in &SETTINGS {
+            let Set::Config(set) = setting.set else {
+                continue;
+            };
+            let value = (setting.get)(&config);
+            assert!(setting.range.contains(&value), "{}", setting.name);
+            let mut modified = Config::new();
+            set(&mut modified, value);
+            assert_eq!(modified, config, "{}", setting.name);
+        }
+    }
+
+    #[test]
+    fn test_log_columns() {
+        let config = Config::new();
+        let mut cleared = 0;
+        for setting in &SETTINGS {
+            let Set::Config(set) = setting.set else {
+                continue;
+            };
+            let mut modified = Config::new();
+            set(&mut modified, 0.);
+            let bits = config.log_columns & !modified.log_columns;
+            assert_eq!(bits & cleared, 0, "{}", setting.name);
+            cleared |= bits;
+            set(&mut modified, 1.);
+            assert_eq!(modified.log_columns, config.log_columns, "{}", setting.name);
+        }
+        assert_eq!(cleared, (1 << RECORD_FIELDS) - 1);
+    }
+
+    #[test]
+    fn test_clock_edits_valid() {
+        let unknown = DateTime::from_unix_ms(UNKNOWN_CLOCK_MS);
+        for setting in &SETTINGS {
+            let Set::Clock(set) = setting.set else {
+                continue;
+            };
+            for value in [*setting.range.start(), *setting.range.end()] {
+                assert!(edit_clock(unknown, set, value).is_valid(), "{} {}", setting.name, value);
+            }
+        }
+        // 31 January changed to February of a leap year
+        let time = DateTime { day: 31, ..unknown };
+        let time = edit_clock(DateTime { year: 2028, ..time }, |t, v| t.month = v as u8, 2.);
+        assert_eq!((time.month, time.day), (2, 29));
+    }
+
+    #[test]
+    fn test_edit_and_apply() {
+        let config = Config::new();
+        let mut menu = SettingsMenu::new();
+        assert_eq!(menu.handle(ButtonEvent::DecShort(1), &config), Response::Handled);
+        assert_eq!(menu.cursor, SETTINGS.len() - 1);
+        menu.cursor = 1; // capacity
+        menu.handle(ButtonEvent::OkLong, &config);
+        assert_eq!(menu.edit, Some(200.));
+        menu.handle(ButtonEvent::IncShort(1), &config);
+        menu.handle(ButtonEvent::IncLong, &config);
+        assert_eq!(menu.handle(ButtonEvent::OkLong, &config), Response::Apply(1, 310.));
+        assert_eq!(menu.edit, None);
+    }
+
+    #[test]
+    fn test_edit_limited_and_cancelled() {
+        let config = Config::new();
+        let mut menu = SettingsMenu::new();
+        menu.handle(ButtonEvent::OkLong, &config);
+        menu.handle(ButtonEvent::IncLong, &config);
+        assert_eq!(menu.edit, Some(2.));
+        assert_eq!(SETTINGS[0].display(2.).as_str(), "LiFePO4");
+        menu.handle(ButtonEvent::OkShort(1), &config);
+        assert_eq!(menu.edit, None);
+        // a double press is left to the page navigation
+        assert_eq!(menu.handle(ButtonEvent::IncShort(2), &config), Response::Ignored);
+    }
+}
@@ -1,15 +1,16 @@
 use atomic_float::AtomicF32;
 use core::cell::RefCell;
-use core::fmt;
 use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
-use heapless::{format, String};
+use heapless::String;
 use num_derive::{FromPrimitive, ToPrimitive};
+use num_traits::FromPrimitive;
 use embassy_sync::channel::{Channel, Receiver, Sender};
 use static_cell::StaticCell;
 use super::control::Controller;
-use crate::app::logger::{LoggerMeta, LINE_LEN};
+use crate::app::journal;
+use crate::app::logger::{log_field, FieldValue, LogField, LoggerMeta};
 
 pub static CONTROLLER: Mutex<CriticalSectionRawMutex, RefCell<Controller>> =
     Mutex::new(RefCell::new(Controller::new()));
@@ -45,6 +46,167 @@ pub enum PpsSetMode {
     DontTouch = 2,
 }
 
+/// State of the SD card as seen by the data logger
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, Default, PartialEq)]
+pub enum CardState {
+    Absent = 0,
+    Ok = 1,
+    Full = 2,
+    Error = 3,
+    #[default]
+    Unknown = 4,
+}
+
+impl CardState {
+    /// short name for UI and log
+    pub fn name(&self) -> &'static str {
+        match self {
+            CardState::Absent => "SD -",
+            CardState::Ok => "SD ok",
+            CardState::Full => "SD full",
+            CardState::Error => "SD err",
+            CardState::Unknown => "SD ?",
+        }
+    }
+}
+
+/// Page shown on the display, navigated with the Inc/Dec buttons
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, Default, PartialEq)]
+pub enum Page {
+    #[default]
+    Overview = 0,
+    Battery = 1,
+    Alternator = 2,
+    Trends = 3,
+    Statistics = 4,
+    Faults = 5,
+    Settings = 6,
+}
+
+impl Page {
+    pub const COUNT: u8 = Page::Settings as u8 + 1;
+
+    pub fn next(&self) -> Self {
+        Page::from_u8((*self as u8 + 1) % Self::COUNT).unwrap_or_default()
+    }
+
+    pub fn previous(&self) -> Self {
+        Page::from_u8((*self as u8 + Self::COUNT - 1) % Self::COUNT).unwrap_or_default()
+    }
+
+    /// title shown on top of the page
+    pub fn title(&self) -> &'static str {
+        match self {
+            Page::Overview => "Overview",
+            Page::Battery => "Battery",
+            Page::Alternator => "Alternator",
+            Page::Trends => "Trends",
+            Page::Statistics => "Statistics",
+            Page::Faults => "Faults",
+            Page::Settings => "Settings",
+        }
+    }
+}
+
+pub static PAGE: AtomicU8 = AtomicU8::new(Page::Overview as u8);
+
+pub fn page() -> Page {
+    Page::from_u8(PAGE.load(Ordering::Relaxed)).unwrap_or_default()
+}
+
+pub fn set_page(page: Page) {
+    PAGE.store(page as u8, Ordering::Relaxed);
+}
+
+/// Conditions that prevent or degrade charging, shown on the faults page
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// communication with the power supply failed
+    Pps = 0,
+    Overheated = 1,
+}
+
+impl Fault {
+    pub const COUNT: u8 = Fault::Overheated as u8 + 1;
+
+    pub fn is_active(&self) -> bool {
+        FAULTS.load(Ordering::Relaxed) & (1 << *self as u8) != 0
+    }
+
+    /// short name for UI and journal
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fault::Pps => "PPS comm",
+            Fault::Overheated => "Overheated",
+        }
+    }
+}
+
+/// bit mask of the active faults
+static FAULTS: AtomicU8 = AtomicU8::new(0);
+
+/// Raises or clears `fault`, returns `true` if its state changed
+///
+/// Changes are journaled, so this may be called on every cycle.
+pub fn set_fault(fault: Fault, active: bool) -> bool {
+    let bit = 1 << fault as u8;
+    let previous = if active {
+        FAULTS.fetch_or(bit, Ordering::Relaxed)
+    } else {
+        FAULTS.fetch_and(!bit, Ordering::Relaxed)
+    };
+    let changed = (previous & bit != 0) != active;
+    if changed {
+        journal::fault(fault.name(), active);
+    }
+    changed
+}
+
+/// The limit that currently determines the field current
+///
+/// Computed by the controller on every cycle, so any charge current can be explained afterwards.
+#[repr(u8)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(FromPrimitive, ToPrimitive, Copy, Clone, Debug, Default, PartialEq)]
+pub enum ActiveLimit {
+    #[default]
+    Off = 0,
+    Idle = 1,
+    StartDelay = 2,
+    /// slew rate limit of the field current, ramping up or down
+    Ramp = 3,
+    Setpoint = 4,
+    Voltage = 5,
+    BatCurrent = 6,
+    AltCurrent = 7,
+    Temperature = 8,
+    Rpm = 9,
+}
+
+impl ActiveLimit {
+    /// short name for UI and log
+    pub fn name(&self) -> &'static str {
+        match self {
+            ActiveLimit::Off => "Off",
+            ActiveLimit::Idle => "Idle",
+            ActiveLimit::StartDelay => "Start delay",
+            ActiveLimit::Ramp => "Ramp",
+            ActiveLimit::Setpoint => "Setpoint",
+            ActiveLimit::Voltage => "Voltage",
+            ActiveLimit::BatCurrent => "Bat current",
+            ActiveLimit::AltCurrent => "Alt current",
+            ActiveLimit::Temperature => "Temperature",
+            ActiveLimit::Rpm => "RPM",
+        }
+    }
+}
+
 /// All external data that are observed by the regulator
 ///
 /// This struct is filled by various sources, mostly drivers. The data are logged to SD card if
@@ -53,7 +215,10 @@ pub enum PpsSetMode {
 #[derive(Debug)]
 pub struct ProcessData {
     pub rpm: AtomicF32,
+    /// filtered alternator pulse rate (Hz), used for RPM calibration
+    pub pulse_rate: AtomicF32,
     pub temperature: AtomicF32,
+    pub alt_current: AtomicF32,
     pub bat_current: AtomicF32,
     pub bat_soc: AtomicF32,
     pub bat_voltage: AtomicF32,
@@ -64,11 +229,21 @@ pub struct ProcessData {
     pub pps_mode: AtomicU8,
     pub ble_rate: AtomicF32,
     pub target_factor: AtomicF32,
+    /// not logged, as only `CardState::Ok` could ever be written to the card
+    pub card_state: AtomicU8,
+}
+
+impl ProcessData {
+    pub fn card_state(&self) -> CardState {
+        CardState::from_u8(self.card_state.load(Ordering::Relaxed)).unwrap_or_default()
+    }
 }
 
 pub static PROCESS_DATA: ProcessData = ProcessData {
     rpm: AtomicF32::new(f32::NAN),
+    pulse_rate: AtomicF32::new(f32::NAN),
     temperature: AtomicF32::new(f32::NAN),
+    alt_current: AtomicF32::new(f32::NAN),
     bat_current: AtomicF32::new(f32::NAN),
     bat_soc: AtomicF32::new(f32::NAN),
     bat_voltage: AtomicF32::new(f32::NAN),
@@ -79,6 +254,7 @@ pub static PROCESS_DATA: ProcessData = ProcessData {
     pps_mode: AtomicU8::new(PpsRunningMode::Unknown as u8),
     ble_rate: AtomicF32::new(0.),
     target_factor: AtomicF32::new(0.),
+    card_state: AtomicU8::new(CardState::Unknown as u8),
 };
 
 /// Output state of the regulator
@@ -89,6 +265,13 @@ pub struct Setpoint {
     pub field_voltage_limit: AtomicF32,
     pub pps_enabled: AtomicU8,
     pub contactor_state: AtomicBool,
+    pub active_limit: AtomicU8,
+}
+
+impl Setpoint {
+    pub fn active_limit(&self) -> ActiveLimit {
+        ActiveLimit::from_u8(self.active_limit.load(Ordering::Relaxed)).unwrap_or_default()
+    }
 }
 
 #[allow(unused)]
@@ -97,70 +280,39 @@ pub static SETPOINT: Setpoint = Setpoint {
     field_voltage_limit: AtomicF32::new(f32::NAN),
     pps_enabled: AtomicU8::new(PpsSetMode::DontTouch as u8),
     contactor_state: AtomicBool::new(false),
+    active_limit: AtomicU8::new(ActiveLimit::Off as u8),
 };
 
-impl fmt::Display for ProcessData {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{};{};{};{};{};{};{};{};{};{};{};{}",
-            self.rpm.load(Ordering::Relaxed),
-            self.target_factor.load(Ordering::Relaxed),
-            self.field_current.load(Ordering::Relaxed),
-            self.field_voltage.load(Ordering::Relaxed),
-            self.bat_current.load(Ordering::Relaxed),
-            self.bat_soc.load(Ordering::Relaxed),
-            self.bat_voltage.load(Ordering::Relaxed),
-            self.input_voltage.load(Ordering::Relaxed),
-            self.temperature.load(Ordering::Relaxed),
-            self.pps_temperature.load(Ordering::Relaxed),
-            self.pps_mode.load(Ordering::Relaxed),
-            self.ble_rate.load(Ordering::Relaxed),
-        )
-    }
-}
-
 impl LoggerMeta for ProcessData {
-    fn get_meta(&self) -> String<{ LINE_LEN }> {
-        format!(
-            LINE_LEN; "{};{};{};{};{};{};{};{};{};{};{};{}",
-            "RPM",
-            "Target",
-            "Field Current",
-            "Field Voltage",
-            "Bat Current",
-            "Bat SoC",
-            "Bat Voltage",
-            "Input Voltage",
-            "Temperature",
-            "PPS Temperature",
-            "PPS Mode",
-            "BLE Rate",
-        )
-        .unwrap()
-    }
-}
-
-impl fmt::Display for Setpoint {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{};{};{};{}",
-            self.field_current_limit.load(Ordering::Relaxed),
-            self.field_voltage_limit.load(Ordering::Relaxed),
-            self.pps_enabled.load(Ordering::Relaxed),
-            self.contactor_state.load(Ordering::Relaxed),
-        )
-    }
+    const FIELDS: &'static [LogField<Self>] = &[
+        log_field!("RPM", "rpm", 0, rpm),
+        log_field!("Pulse Rate", "Hz", 1, pulse_rate),
+        log_field!("Target", 3, target_factor),
+        log_field!("Field Current", "A", 3, field_current),
+        log_field!("Field Voltage", "V", 2, field_voltage),
+        log_field!("Alt Current", "A", 1, alt_current),
+        log_field!("Bat Current", "A", 1, bat_current),
+        log_field!("Bat SoC", "%", 1, bat_soc),
+        log_field!("Bat Voltage", "V", 2, bat_voltage),
+        log_field!("Input Voltage", "V", 2, input_voltage),
+        log_field!("Temperature", "°C", 1, temperature),
+        log_field!("PPS Temperature", "°C", 1, pps_temperature),
+        log_field!("PPS Mode", |d| FieldValue::Int(d.pps_mode.load(Ordering::Relaxed) as u32)),
+        log_field!("BLE Rate", "1/s", 1, ble_rate),
+    ];
 }
 
 impl LoggerMeta for Setpoint {
-    fn get_meta(&self) -> String<{ LINE_LEN }> {
-        format!(
-            LINE_LEN; "{};{};{};{}", "Field Current Limit", "Field Voltage Limit", "PPS Enabled", "Contactor State"
-        )
-        .unwrap()
-    }
+    const FIELDS: &'static [LogField<Self>] = &[
+        log_field!("Field Current Limit", "A", 3, field_current_limit),
+        log_field!("Field Voltage Limit", "V", 2, field_voltage_limit),
+        log_field!("PPS Enabled", |d| FieldValue::Int(d.pps_enabled.load(Ordering::Relaxed) as u32)),
+        log_field!("Contactor State", |d| FieldValue::Bool(d.contactor_state.load(Ordering::Relaxed))),
+        log_field!("Active Limit", |d| {
+            let limit = d.active_limit();
+            FieldValue::Enum(limit as u8, limit.name())
+        }),
+    ];
 }
 
 #[allow(unused)]
@@ -173,7 +325,7 @@ pub enum TemperatureEvent {
 }
 
 #[allow(unused)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RpmEvent {
     Low,
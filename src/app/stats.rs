@@ -0,0 +1,386 @@
+//! Charge statistics
+//!
+//! Integrates alternator and battery charge (Ah) and energy (Wh), engine run time and the time spent in
+//! each active limit, and keeps the peak temperatures and currents. Totals are kept for the current day
+//! and for the lifetime of the regulator and are persisted to flash by the config task, together with
+//! the service marks of the maintenance reminders.
+//!
+//! The day comes from the wall clock. While it is unknown, e.g. without an RTC and before the clock is
+//! set in the settings menu, the daily totals count since boot and are shown as such.
+//!
+//! The regulator has no explicit charge stages, the active limit takes their place: `BatCurrent` and
+//! `AltCurrent` correspond to bulk charging, `Voltage` to absorption.
+
+use core::cell::RefCell;
+use core::sync::atomic::Ordering;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker};
+use libm::fmaxf;
+
+use crate::app::clock;
+use crate::app::config::{ConfigError, Reader, Writer, CONFIG};
+use crate::app::maintenance::{self, ServiceMark, MAINTENANCE_ITEMS};
+use crate::app::shared::{ActiveLimit, PROCESS_DATA, SETPOINT};
+
+pub static STATS: Mutex<CriticalSectionRawMutex, RefCell<Statistics>> =
+    Mutex::new(RefCell::new(Statistics::new()));
+
+/// Wakes up the config task to persist the statistics
+pub static STATS_SAVE: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+/// flash sectors endure about 100k erase cycles, saving only while the engine runs keeps well below
+const SAVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub const LIMIT_COUNT: usize = ActiveLimit::Rpm as usize + 1;
+const MS_PER_DAY: u64 = 24 * 3600 * 1000;
+
+/// One set of measurements, taken once per update interval
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StatsSample {
+    pub rpm: f32,
+    pub rpm_min: f32,
+    pub alt_current: f32,
+    pub bat_current: f32,
+    pub bat_voltage: f32,
+    pub field_current: f32,
+    pub alt_temperature: f32,
+    pub pps_temperature: f32,
+    pub active_limit: ActiveLimit,
+}
+
+impl StatsSample {
+    /// current values of the process data
+    fn now() -> Self {
+        Self {
+            rpm: PROCESS_DATA.rpm.load(Ordering::Relaxed),
+            rpm_min: CONFIG.lock(|c| c.borrow().rpm_min),
+            alt_current: PROCESS_DATA.alt_current.load(Ordering::Relaxed),
+            bat_current: PROCESS_DATA.bat_current.load(Ordering::Relaxed),
+            bat_voltage: PROCESS_DATA.bat_voltage.load(Ordering::Relaxed),
+            field_current: PROCESS_DATA.field_current.load(Ordering::Relaxed),
+            alt_temperature: PROCESS_DATA.temperature.load(Ordering::Relaxed),
+            pps_temperature: PROCESS_DATA.pps_temperature.load(Ordering::Relaxed),
+            active_limit: SETPOINT.active_limit(),
+        }
+    }
+
+    fn engine_running(&self) -> bool {
+        self.rpm >= self.rpm_min
+    }
+}
+
+/// Aggregated values over a period, all times in seconds
+#[derive(Clone, Debug, PartialEq)]
+pub struct Totals {
+    pub alt_ah: f64,
+    pub alt_wh: f64,
+    /// charge into the battery, discharge is not counted
+    pub bat_ah: f64,
+    pub bat_wh: f64,
+    pub engine_s: f64,
+    /// time in each `ActiveLimit` while the engine was running
+    pub limit_s: [f64; LIMIT_COUNT],
+    pub max_alt_temperature: f32,
+    pub max_pps_temperature: f32,
+    pub max_alt_current: f32,
+    pub max_bat_current: f32,
+    pub max_field_current: f32,
+}
+
+impl Totals {
+    pub const fn new() -> Self {
+        Self {
+            alt_ah: 0.,
+            alt_wh: 0.,
+            bat_ah: 0.,
+            bat_wh: 0.,
+            engine_s: 0.,
+            limit_s: [0.; LIMIT_COUNT],
+            max_alt_temperature: f32::NAN,
+            max_pps_temperature: f32::NAN,
+            max_alt_current: f32::NAN,
+            max_bat_current: f32::NAN,
+            max_field_current: f32::NAN,
+        }
+    }
+
+    /// Adds a sample taken `dt` seconds after the previous one, missing measurements (NaN) are skipped
+    pub fn add(&mut self, sample: &StatsSample, dt: f32) {
+        let hours = dt as f64 / 3600.;
+        if sample.alt_current.is_finite() && sample.alt_current > 0. {
+            self.alt_ah += sample.alt_current as f64 * hours;
+            if sample.bat_voltage.is_finite() {
+                self.alt_wh += (sample.alt_current * sample.bat_voltage) as f64 * hours;
+            }
+        }
+        if sample.bat_current.is_finite() && sample.bat_current > 0. {
+            self.bat_ah += sample.bat_current as f64 * hours;
+            if sample.bat_voltage.is_finite() {
+                self.bat_wh += (sample.bat_current * sample.bat_voltage) as f64 * hours;
+            }
+        }
+        if sample.engine_running() {
+            self.engine_s += dt as f64;
+            self.limit_s[sample.active_limit as usize] += dt as f64;
+        }
+        // fmaxf ignores NaN, so the first valid value becomes the peak
+        self.max_alt_temperature = fmaxf(self.max_alt_temperature, sample.alt_temperature);
+        self.max_pps_temperature = fmaxf(self.max_pps_temperature, sample.pps_temperature);
+        self.max_alt_current = fmaxf(self.max_alt_current, sample.alt_current);
+        self.max_bat_current = fmaxf(self.max_bat_current, sample.bat_current);
+        self.max_field_current = fmaxf(self.max_field_current, sample.field_current);
+    }
+
+    fn serialize(&self, w: &mut Writer) -> Result<(), ConfigError> {
+        w.put_f64(self.alt_ah)?;
+        w.put_f64(self.alt_wh)?;
+        w.put_f64(self.bat_ah)?;
+        w.put_f64(self.bat_wh)?;
+        w.put_f64(self.engine_s)?;
+        for s in &self.limit_s {
+            w.put_f64(*s)?;
+        }
+        w.put_f32(self.max_alt_temperature)?;
+        w.put_f32(self.max_pps_temperature)?;
+        w.put_f32(self.max_alt_current)?;
+        w.put_f32(self.max_bat_current)?;
+        w.put_f32(self.max_field_current)
+    }
+
+    fn deserialize(&mut self, r: &mut Reader) {
+        r.f64(&mut self.alt_ah);
+        r.f64(&mut self.alt_wh);
+        r.f64(&mut self.bat_ah);
+        r.f64(&mut self.bat_wh);
+        r.f64(&mut self.engine_s);
+        for s in &mut self.limit_s {
+            r.f64(s);
+        }
+        r.f32(&mut self.max_alt_temperature);
+        r.f32(&mut self.max_pps_temperature);
+        r.f32(&mut self.max_alt_current);
+        r.f32(&mut self.max_bat_current);
+        r.f32(&mut self.max_field_current);
+    }
+}
+
+impl Default for Totals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Daily and lifetime totals
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statistics {
+    /// day of `today` in days since 1970-01-01, 0 while the wall clock is not known and `today` counts
+    /// since boot
+    pub day: u32,
+    pub today: Totals,
+    pub lifetime: Totals,
+    /// lifetime counters at the last service of each `MaintenanceItem`
+    pub service: [ServiceMark; MAINTENANCE_ITEMS],
+    /// counts the saves, the newer of the two copies in flash has the higher one
+    pub sequence: u32,
+}
+
+impl Statistics {
+    pub const SERIALIZED_LEN: usize = 512;
+    const MAGIC: u32 = 0x5453_4741; // "AGST"
+
+    pub const fn new() -> Self {
+        Self {
+            day: 0,
+            today: Totals::new(),
+            lifetime: Totals::new(),
+            service: [ServiceMark::new(); MAINTENANCE_ITEMS],
+            sequence: 0,
+        }
+    }
+
+    pub fn add(&mut self, sample: &StatsSample, dt: f32) {
+        self.today.add(sample, dt);
+        self.lifetime.add(sample, dt);
+    }
+
+    /// Called once with the statistics restored at boot, the totals of an unknown day are not continued
+    pub fn on_boot(&mut self) {
+        if self.day == 0 {
+            self.today = Totals::new();
+        }
+    }
+
+    /// Starts a new day if `day` differs from the current one
+    ///
+    /// Totals collected before the wall clock was known are counted to the first known day.
+    pub fn set_day(&mut self, day: u32) {
+        if day == self.day {
+            return;
+        }
+        if self.day != 0 {
+            self.today = Totals::new();
+        }
+        self.day = day;
+    }
+
+    /// Serializes the statistics into `buf`, returns the number of bytes used
+    ///
+    /// Like the configuration, new fields must only be added at the end.
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, ConfigError> {
+        let mut w = Writer::new(buf);
+        w.put_u32(self.day)?;
+        self.today.serialize(&mut w)?;
+        self.lifetime.serialize(&mut w)?;
+        for mark in &self.service {
+            w.put_f64(mark.engine_s)?;
+            w.put_f64(mark.alt_ah)?;
+        }
+        w.put_u32(self.sequence)?;
+        w.finish(Self::MAGIC)
+    }
+
+    pub fn deserialize(buf: &[u8]) -> Result<Self, ConfigError> {
+        let mut r = Reader::new(buf, Self::MAGIC)?;
+        let mut stats = Self::new();
+        r.u32(&mut stats.day);
+        stats.today.deserialize(&mut r);
+        stats.lifetime.deserialize(&mut r);
+        for mark in &mut stats.service {
+            r.f64(&mut mark.engine_s);
+            r.f64(&mut mark.alt_ah);
+        }
+        r.u32(&mut stats.sequence);
+        Ok(stats)
+    }
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Current day in days since 1970-01-01, 0 if the wall clock is not known
+fn today() -> u32 {
+    clock::now().map_or(0, |t| (t.to_unix_ms() / MS_PER_DAY) as u32)
+}
+
+#[embassy_executor::task]
+pub async fn stats_task() -> ! {
+    let mut ticker = Ticker::every(UPDATE_INTERVAL);
+    let mut last_update = Instant::now();
+    let mut last_save = Instant::now();
+    let mut was_running = false;
+    loop {
+        ticker.next().await;
+        let dt = last_update.elapsed().as_millis() as f32 / 1000.;
+        last_update = Instant::now();
+
+        let sample = StatsSample::now();
+        let running = sample.engine_running();
+        STATS.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.set_day(today());
+            s.add(&sample, dt);
+        });
+
+        // save while the engine runs and once it has stopped, nothing changes while it is off
+        if (running && last_save.elapsed() >= SAVE_INTERVAL) || (was_running && !running) {
+            STATS_SAVE.signal(());
+            last_save = Instant::now();
+        }
+        was_running = running;
+        maintenance::check();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charging() -> StatsSample {
+        StatsSample {
+            rpm: 1500.,
+            rpm_min: 500.,
+            alt_current: 100.,
+            bat_current: 80.,
+            bat_voltage: 14.,
+            field_current: 2.,
+            alt_temperature: 80.,
+            pps_temperature: 50.,
+            active_limit: ActiveLimit::Voltage,
+        }
+    }
+
+    #[test]
+    fn test_integration() {
+        let mut t = Totals::new();
+        for _ in 0..3600 {
+            t.add(&charging(), 1.);
+        }
+        assert!((t.alt_ah - 100.).abs() < 1e-6);
+        assert!((t.alt_wh - 1400.).abs() < 1e-6);
+        assert!((t.bat_ah - 80.).abs() < 1e-6);
+        assert_eq!(t.engine_s, 3600.);
+        assert_eq!(t.limit_s[ActiveLimit::Voltage as usize], 3600.);
+        assert_eq!(t.max_alt_current, 100.);
+    }
+
+    #[test]
+    fn test_engine_off_and_missing_values() {
+        let mut t = Totals::new();
+        let sample = StatsSample {
+            rpm: 0.,
+            bat_current: -10.,
+            alt_current: f32::NAN,
+            alt_temperature: f32::NAN,
+            ..charging()
+        };
+        t.add(&sample, 1.);
+        assert_eq!(t.engine_s, 0.);
+        assert_eq!(t.alt_ah, 0.);
+        assert_eq!(t.bat_ah, 0.);
+        assert!(t.max_alt_temperature.is_nan());
+        assert_eq!(t.max_pps_temperature, 50.);
+    }
+
+    #[test]
+    fn test_day_change() {
+        let mut s = Statistics::new();
+        s.add(&charging(), 10.);
+        s.set_day(20000);
+        assert_eq!(s.today.engine_s, 10.);
+        s.set_day(20001);
+        assert_eq!(s.today.engine_s, 0.);
+        assert_eq!(s.lifetime.engine_s, 10.);
+    }
+
+    #[test]
+    fn test_unknown_day_since_boot() {
+        let mut s = Statistics::new();
+        s.add(&charging(), 10.);
+        s.on_boot();
+        assert_eq!(s.today.engine_s, 0.);
+        assert_eq!(s.lifetime.engine_s, 10.);
+
+        s.set_day(20000);
+        s.add(&charging(), 10.);
+        s.on_boot();
+        assert_eq!(s.today.engine_s, 10.);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut s = Statistics::new();
+        s.set_day(20000);
+        s.add(&charging(), 10.);
+        s.service[1] = ServiceMark::at(&s.lifetime);
+        s.sequence = 42;
+        let mut buf = [0xff_u8; Statistics::SERIALIZED_LEN];
+        s.serialize(&mut buf).unwrap();
+        assert_eq!(Statistics::deserialize(&buf).unwrap(), s);
+    }
+}
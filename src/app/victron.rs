@@ -1,18 +1,68 @@
 use bt_hci::event::Vendor;
-use bt_hci::param::{BdAddr, LeAdvReportsIter, LeExtAdvReportsIter};
+use bt_hci::param::{AddrKind, BdAddr, LeAdvReportsIter, LeExtAdvReportsIter};
 use core::sync::atomic::Ordering;
 use embassy_time::Instant;
 use trouble_host::advertise::AdStructure;
 use trouble_host::prelude::EventHandler;
 use victron_ble::DeviceState;
 
+use crate::app::config::CONFIG;
 use crate::app::shared::PROCESS_DATA;
 
-struct VictronDevice {
+/// What a paired device measures
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VictronRole {
+    Battery,
+    Alternator,
+}
+
+pub struct VictronDevice {
+    /// shown in the settings menu
+    pub name: &'static str,
     pub mac: [u8; 6],
     pub key: [u8; 16],
 }
 
+pub const KNOWN_DEVICE_COUNT: usize = 3;
+
+/// Devices whose encryption keys are known, selected by `Config::victron_battery` and `victron_alternator`
+pub static KNOWN_DEVICES: [VictronDevice; KNOWN_DEVICE_COUNT] = [
+    VictronDevice {
+        // just for SW testing
+        name: "AC charger",
+        mac: [0xc0, 0x12, 0x9b, 0x97, 0x7f, 0xb8],
+        key: [
+            0x34, 0xa4, 0x20, 0xf8, 0x6f, 0xa0, 0x37, 0x50, 0x8a, 0x83, 0x47, 0xf6, 0x21, 0x4d, 0xc1, 0xf4,
+        ],
+    },
+    VictronDevice {
+        name: "SmartShunt 300A",
+        mac: [0xf9, 0x3c, 0xeb, 0x5e, 0xf4, 0x75],
+        key: [
+            0xe8, 0xe4, 0xd8, 0x14, 0x4a, 0x72, 0x49, 0x2e, 0x8e, 0x8b, 0x2b, 0x9c, 0x93, 0x78, 0xbd, 0xfb,
+        ],
+    },
+    VictronDevice {
+        name: "SmartShunt 500A",
+        mac: [0xd9, 0xd5, 0x51, 0x59, 0x70, 0x4d],
+        key: [
+            0x13, 0xc6, 0xbf, 0xf8, 0xdb, 0xef, 0xcf, 0x2d, 0xd5, 0xd5, 0x07, 0x79, 0x8d, 0xc1, 0x0f, 0x9e,
+        ],
+    },
+];
+
+/// name of entry `index` of `KNOWN_DEVICES`
+pub fn device_name(index: u8) -> &'static str {
+    KNOWN_DEVICES.get(index as usize).map_or("?", |d| d.name)
+}
+
+struct PairedDevice {
+    role: VictronRole,
+    key: &'static [u8],
+    mac: BdAddr,
+}
+
 impl VictronDevice {
     pub fn bd_addr(&self) -> BdAddr {
         let mut reversed = [0u8; 6];
@@ -23,49 +73,51 @@ impl VictronDevice {
 }
 
 pub struct VictronBLE {
-    paired_key: &'static [u8],
-    pub paired_mac: BdAddr,
+    paired: [PairedDevice; VictronBLE::PAIRED_MAX],
 }
 
 impl VictronBLE {
     const EXP_MA_COEFF: f32 = 0.1;
     const VICTRON_ID: u16 = 0x02e1;
-    const VICTRON_DEVICES: [VictronDevice; 3] = [
-        VictronDevice {
-            // AC Charger - just for SW testing
-            mac: [0xc0, 0x12, 0x9b, 0x97, 0x7f, 0xb8],
-            key: [
-                0x34, 0xa4, 0x20, 0xf8, 0x6f, 0xa0, 0x37, 0x50, 0x8a, 0x83, 0x47, 0xf6, 0x21, 0x4d, 0xc1, 0xf4,
-            ],
-        },
-        VictronDevice {
-            // SmartShunt 300A (alternator)
-            mac: [0xf9, 0x3c, 0xeb, 0x5e, 0xf4, 0x75],
-            key: [
-                0xe8, 0xe4, 0xd8, 0x14, 0x4a, 0x72, 0x49, 0x2e, 0x8e, 0x8b, 0x2b, 0x9c, 0x93, 0x78, 0xbd, 0xfb,
-            ],
-        },
-        VictronDevice {
-            // SmartShunt 500A (battery)
-            mac: [0xd9, 0xd5, 0x51, 0x59, 0x70, 0x4d],
-            key: [
-                0x13, 0xc6, 0xbf, 0xf8, 0xdb, 0xef, 0xcf, 0x2d, 0xd5, 0xd5, 0x07, 0x79, 0x8d, 0xc1, 0x0f, 0x9e,
-            ],
-        },
-    ];
-
+    pub const PAIRED_MAX: usize = 2;
+    /// pairs the devices selected in the config, which is validated, so the indices are in range
     pub fn new() -> Self {
+        let (battery, alternator) = CONFIG.lock(|c| {
+            let c = c.borrow();
+            (&KNOWN_DEVICES[c.victron_battery as usize], &KNOWN_DEVICES[c.victron_alternator as usize])
+        });
         VictronBLE {
-            paired_key: &VictronBLE::VICTRON_DEVICES[0].key,
-            paired_mac: VictronBLE::VICTRON_DEVICES[0].bd_addr(),
+            paired: [
+                PairedDevice {
+                    role: VictronRole::Battery,
+                    key: &battery.key,
+                    mac: battery.bd_addr(),
+                },
+                PairedDevice {
+                    role: VictronRole::Alternator,
+                    key: &alternator.key,
+                    mac: alternator.bd_addr(),
+                },
+            ],
         }
     }
 
-    fn handle_mdata(&self, data: &[u8]) {
-        let device_state_result = victron_ble::parse_manufacturer_data(data, self.paired_key);
+    /// scan filter accepting all paired devices
+    pub fn scan_filter(&self) -> [(AddrKind, &BdAddr); VictronBLE::PAIRED_MAX] {
+        self.paired.each_ref().map(|d| (AddrKind::RANDOM, &d.mac))
+    }
+
+    fn handle_mdata(&self, device: &PairedDevice, data: &[u8]) {
+        let device_state_result = victron_ble::parse_manufacturer_data(data, device.key);
         match device_state_result {
             Ok(device_state) => {
                 match device_state {
+                    DeviceState::BatteryMonitor(bm_state) if device.role == VictronRole::Alternator => {
+                        PROCESS_DATA
+                            .alt_current
+                            .store(bm_state.battery_current_a, core::sync::atomic::Ordering::Relaxed);
+                        Self::update_statistics();
+                    }
                     DeviceState::GridCharger(gc_state) => {
                         // just using this AC charger to bring BLE test data into the system
                         // fake a current reading from normally non-zero voltage
@@ -77,7 +129,7 @@ impl VictronBLE {
                     DeviceState::BatteryMonitor(bm_state) => {
                         PROCESS_DATA
                             .bat_voltage
-                            .store(bm_state.battery_current_a, core::sync::atomic::Ordering::Relaxed);
+                            .store(bm_state.battery_voltage_v, core::sync::atomic::Ordering::Relaxed);
                         PROCESS_DATA
                             .bat_current
                             .store(bm_state.battery_current_a, core::sync::atomic::Ordering::Relaxed);
@@ -119,7 +171,7 @@ impl EventHandler for VictronBLE {
     #[link_section = ".iram1"]
     fn on_adv_reports(&self, mut it: LeAdvReportsIter<'_>) {
         while let Some(Ok(report)) = it.next() {
-            if report.addr != self.paired_mac {
+            let Some(device) = self.paired.iter().find(|d| d.mac == report.addr) else {
                 warn!(
                     "ignoring {:?}, that has unexpectedly passed the scan filter",
                     report.addr
@@ -135,7 +187,7 @@ impl EventHandler for VictronBLE {
                                 payload,
                             } => {
                                 if company_identifier == VictronBLE::VICTRON_ID {
-                                    self.handle_mdata(payload);
+                                    self.handle_mdata(device, payload);
                                     //warn!("Victron ad: {:?}", payload);
                                 } else {
                                     warn!("ignoring non-Victron ad: {:?}", company_identifier);
@@ -0,0 +1,73 @@
+use esp_hal::{
+    gpio::AnyPin,
+    ledc::{
+        channel::{self, ChannelIFace},
+        timer::{self, TimerIFace},
+        LSGlobalClkSource, Ledc, LowSpeed,
+    },
+    peripherals::LEDC,
+    time::Rate,
+};
+use static_cell::StaticCell;
+use thiserror_no_std::Error;
+
+use crate::fmt::Debug2Format;
+
+/// above the audible range, so the backlight does not whine
+const PWM_FREQUENCY: Rate = Rate::from_khz(24);
+
+#[derive(Error, Debug)]
+pub enum BacklightError {
+    #[error("LEDC timer configuration failed: {0:?}")]
+    Timer(timer::Error),
+
+    #[error("LEDC channel configuration failed: {0:?}")]
+    Channel(channel::Error),
+}
+
+/// Display backlight dimmed by PWM from the LEDC peripheral
+pub struct Backlight {
+    channel: channel::Channel<'static, LowSpeed>,
+    /// current duty (%)
+    brightness: u8,
+}
+
+impl Backlight {
+    /// Sets up the PWM with the backlight off
+    pub fn new(ledc: LEDC<'static>, pin: AnyPin<'static>) -> Result<Self, BacklightError> {
+        let mut ledc = Ledc::new(ledc);
+        ledc.set_global_slow_clock(LSGlobalClkSource::APBClk);
+
+        // the channel keeps a reference to its timer
+        static TIMER: StaticCell<timer::Timer<'static, LowSpeed>> = StaticCell::new();
+        let timer = TIMER.init(ledc.timer::<LowSpeed>(timer::Number::Timer0));
+        timer
+            .configure(timer::config::Config {
+                duty: timer::config::Duty::Duty8Bit,
+                clock_source: timer::LSClockSource::APBClk,
+                frequency: PWM_FREQUENCY,
+            })
+            .map_err(BacklightError::Timer)?;
+
+        let mut channel = ledc.channel(channel::Number::Channel0, pin);
+        channel
+            .configure(channel::config::Config {
+                timer,
+                duty_pct: 0,
+                pin_config: channel::config::PinConfig::PushPull,
+            })
+            .map_err(BacklightError::Channel)?;
+        Ok(Self { channel, brightness: 0 })
+    }
+
+    /// Sets the brightness (%), 0 turns the backlight off
+    pub fn set_brightness(&mut self, brightness: u8) {
+        let brightness = brightness.min(100);
+        if brightness != self.brightness {
+            self.channel
+                .set_duty(brightness)
+                .unwrap_or_else(|e| warn!("Failed to set backlight: {:?}", Debug2Format(&e)));
+            self.brightness = brightness;
+        }
+    }
+}
@@ -1,17 +1,20 @@
+use core::cell::RefCell;
 use core::convert::Infallible;
 use embedded_graphics::pixelcolor::Rgb565;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
+use embedded_hal_async::spi::SpiBus;
 use esp_hal::{delay::Delay, gpio::Output};
 use mipidsi::interface::SpiError;
 use mipidsi::{interface::SpiInterface, models::ILI9342CRgb565, Builder};
 use static_cell::StaticCell;
 use thiserror_no_std::Error;
 
-use crate::board::io::spi2::SpiDeviceType;
+use crate::board::driver::backlight::Backlight;
+use crate::board::io::spi2::{DisplayDeviceType, SpiBusType};
 
 
-type DisplayInterface<'a> = SpiInterface<'static, SpiDeviceType<'a>, Output<'a>>;
+type DisplayInterface<'a> = SpiInterface<'static, DisplayDeviceType<'a>, Output<'a>>;
 type D = mipidsi::Display<DisplayInterface<'static>, ILI9342CRgb565, Output<'static>>;
 
 #[derive(Error, Debug)]
@@ -22,29 +25,70 @@ pub enum DisplayError {
     #[error("Display draw failed: {0:?}")]
     //    DisplayError(#[from] mipidsi::interface::SpiError<embedded_hal_bus::spi::DeviceError<esp_hal::spi::Error, core::convert::Infallible>, core::convert::Infallible>),
     DisplayError(#[from] SpiError<embedded_hal_bus::spi::DeviceError<esp_hal::spi::Error, Infallible>, Infallible>),
+
+    #[error("Display transfer failed: {0:?}")]
+    TransferFailed(esp_hal::spi::Error),
 }
 
-#[allow(dead_code)]
+/// The display on the SPI bus shared with the SD card
+///
+/// The chip select is driven here rather than by the SPI device of `display`, so it stays asserted
+/// between the commands sent by `mipidsi` and the pixels sent by DMA.
 pub struct DisplayDriver {
-    bl_pin: Output<'static>,
+    backlight: Backlight,
     pub display: &'static mut D,
+    bus: &'static RefCell<SpiBusType>,
+    cs: Output<'static>,
 }
 
 impl DisplayDriver {
-    pub fn bl_on(&mut self) {
-        self.bl_pin.set_high();
+    /// Sets the backlight brightness (%), 0 turns it off
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.backlight.set_brightness(brightness);
+    }
+
+    /// Sends big-endian RGB565 `pixels` to `area` by DMA
+    ///
+    /// The SPI bus is borrowed until the transfer is complete, the caller holds `spi2::BUS_LOCK` to keep
+    /// the SD card off it.
+    pub async fn write_pixels(&mut self, area: &Rectangle, pixels: &[u8]) -> Result<(), DisplayError> {
+        let Some(bottom_right) = area.bottom_right() else {
+            return Ok(());
+        };
+        self.cs.set_low();
+        let result = async {
+            // address window and memory write command, without pixels
+            self.display.set_pixels(
+                area.top_left.x as u16,
+                area.top_left.y as u16,
+                bottom_right.x as u16,
+                bottom_right.y as u16,
+                core::iter::empty(),
+            )?;
+            let mut bus = self.bus.borrow_mut();
+            SpiBus::write(&mut *bus, pixels).await.map_err(DisplayError::TransferFailed)?;
+            SpiBus::flush(&mut *bus).await.map_err(DisplayError::TransferFailed)
+        }
+        .await;
+        self.cs.set_high();
+        result
     }
 
-    #[allow(dead_code)]
-    pub fn bl_off(&mut self) {
-        self.bl_pin.set_low();
+    /// Runs `f` with the display selected
+    fn selected<R>(&mut self, f: impl FnOnce(&mut D) -> R) -> R {
+        self.cs.set_low();
+        let result = f(&mut *self.display);
+        self.cs.set_high();
+        result
     }
 }
 
 impl DisplayDriver {
     pub fn new(
-        spi_device: SpiDeviceType<'static>,
-        mut bl: Output<'static>,
+        spi_device: DisplayDeviceType<'static>,
+        bus: &'static RefCell<SpiBusType>,
+        mut cs: Output<'static>,
+        backlight: Backlight,
         mut rst: Output<'static>,
         dc: Output<'static>,
     ) -> Result<Self, DisplayError> {
@@ -52,8 +96,8 @@ impl DisplayDriver {
         let di_buf: &'static mut [u8] = BUFFER.init([0_u8; 128]);
         let di = SpiInterface::new(spi_device, dc, di_buf);
 
-        bl.set_low(); // avaoid flickering
         rst.set_high();
+        cs.set_low();
         let mut delay = Delay::new();
         static DISPLAY: StaticCell<D> = StaticCell::new();
         let display = DISPLAY.init(
@@ -66,7 +110,13 @@ impl DisplayDriver {
                 .map_err(|_| DisplayError::InitFailed)?,
         );
         display.clear(Rgb565::BLACK)?;
-        Ok(Self { bl_pin: bl, display })
+        cs.set_high();
+        Ok(Self {
+            backlight,
+            display,
+            bus,
+            cs,
+        })
     }
 }
 
@@ -79,12 +129,12 @@ impl DrawTarget for DisplayDriver {
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
         // Forward the draw_iter call to the `display` implementation
-        self.display.draw_iter(pixels)
+        self.selected(|display| display.draw_iter(pixels))
     }
 
     fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
         // Forward the fill_solid call to the `display` implementation
-        self.display.fill_solid(area, color)
+        self.selected(|display| display.fill_solid(area, color))
     }
 
     fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
@@ -92,7 +142,7 @@ impl DrawTarget for DisplayDriver {
         I: IntoIterator<Item = Self::Color>,
     {
         // Forward the fill_contiguous call to the `display` implementation
-        self.display.fill_contiguous(area, colors)
+        self.selected(|display| display.fill_contiguous(area, colors))
     }
 }
 
@@ -0,0 +1,100 @@
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::{FlashStorage, FlashStorageError};
+use thiserror_no_std::Error;
+
+use crate::app::config::{Config, ConfigError};
+use crate::app::stats::Statistics;
+
+#[derive(Debug, Error)]
+pub enum FlashError {
+    #[error("Flash access failed: {0:?}")]
+    Storage(FlashStorageError),
+
+    #[error("Invalid configuration: {0}")]
+    Config(#[from] ConfigError),
+}
+
+impl From<FlashStorageError> for FlashError {
+    fn from(err: FlashStorageError) -> Self {
+        FlashError::Storage(err)
+    }
+}
+
+/// Stores the persistent configuration and the statistics in dedicated flash sectors
+///
+/// The statistics are saved often and also when the engine stops, when the power may be cut, so they are
+/// written to two sectors in turn: a write cut short leaves the previous copy.
+pub struct ConfigStore {
+    flash: FlashStorage,
+    /// index into `STATS_OFFSETS` of the newest statistics
+    stats_slot: usize,
+    /// `Statistics::sequence` of the newest statistics
+    stats_sequence: u32,
+}
+
+impl ConfigStore {
+    /// first sector of the `nvs` partition of the default partition table, which is not used otherwise
+    const CONFIG_OFFSET: u32 = 0x9000;
+    /// second and third sector of the `nvs` partition
+    const STATS_OFFSETS: [u32; 2] = [0xa000, 0xb000];
+
+    pub fn new() -> Self {
+        Self {
+            flash: FlashStorage::new(),
+            stats_slot: 0,
+            stats_sequence: 0,
+        }
+    }
+
+    pub fn load(&mut self) -> Result<Config, FlashError> {
+        let mut buf = [0_u8; Config::SERIALIZED_LEN];
+        self.flash.read(Self::CONFIG_OFFSET, &mut buf)?;
+        Ok(Config::deserialize(&buf)?)
+    }
+
+    pub fn store(&mut self, config: &Config) -> Result<(), FlashError> {
+        let mut buf = [0xff_u8; Config::SERIALIZED_LEN];
+        let len = config.serialize(&mut buf)?;
+        self.flash.write(Self::CONFIG_OFFSET, &buf[..len])?;
+        Ok(())
+    }
+
+    /// Loads the newest valid copy of the statistics
+    pub fn load_stats(&mut self) -> Result<Statistics, FlashError> {
+        let mut result = Err(FlashError::Config(ConfigError::NotFound));
+        for (slot, &offset) in Self::STATS_OFFSETS.iter().enumerate() {
+            match self.read_stats(offset) {
+                Ok(stats) if result.as_ref().is_ok_and(|newest| newest.sequence >= stats.sequence) => {}
+                Ok(stats) => {
+                    self.stats_slot = slot;
+                    self.stats_sequence = stats.sequence;
+                    result = Ok(stats);
+                }
+                Err(err) if result.is_err() => result = Err(err),
+                Err(_) => {}
+            }
+        }
+        result
+    }
+
+    fn read_stats(&mut self, offset: u32) -> Result<Statistics, FlashError> {
+        let mut buf = [0_u8; Statistics::SERIALIZED_LEN];
+        self.flash.read(offset, &mut buf)?;
+        Ok(Statistics::deserialize(&buf)?)
+    }
+
+    /// Writes the statistics to the sector that does not hold the newest copy
+    pub fn store_stats(&mut self, stats: &Statistics) -> Result<(), FlashError> {
+        let slot = (self.stats_slot + 1) % Self::STATS_OFFSETS.len();
+        let stats = Statistics {
+            sequence: self.stats_sequence + 1,
+            ..stats.clone()
+        };
+        let mut buf = [0xff_u8; Statistics::SERIALIZED_LEN];
+        let len = stats.serialize(&mut buf)?;
+        self.flash.write(Self::STATS_OFFSETS[slot], &buf[..len])?;
+        self.stats_slot = slot;
+        self.stats_sequence = stats.sequence;
+        Ok(())
+    }
+}
@@ -1,5 +1,8 @@
 pub mod analog;
+pub mod backlight;
 pub mod display;
+pub mod flash;
 pub mod pcnt;
 pub mod pps;
 pub mod radio;
+pub mod rtc;
@@ -28,7 +28,7 @@ pub enum PpsError {
     SyncI2cError,
 }
 
-type I2cType = I2c<'static, Async>;
+pub type I2cType = I2c<'static, Async>;
 
 #[allow(dead_code)]
 enum ReadCommand {
@@ -228,6 +228,11 @@ impl PpsDriver {
         }
     }
 
+    /// The I2C bus, for other devices on the same bus
+    pub fn i2c(&mut self) -> &mut I2cType {
+        &mut self.i2c
+    }
+
     pub async fn get_module_id(&mut self) -> Result<u16, PpsError> {
         match ReadCommand::ModuleId.receive_async(&mut self.i2c, self.address).await? {
             ReadResult::ModuleId(id) => Ok(id),
@@ -0,0 +1,94 @@
+use thiserror_no_std::Error;
+
+use crate::app::clock::DateTime;
+use crate::board::driver::pps::I2cType;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Debug, Error)]
+pub enum RtcError {
+    #[error("I2C Master error: {0:?}")]
+    I2cMasterError(#[from] esp_hal::i2c::master::Error),
+
+    #[error("RTC has lost its time")]
+    TimeLost,
+}
+
+/// DS3231 real time clock, shares the I2C bus with the PPS
+///
+/// The RTC keeps UTC in 24 hour mode. As the bus is owned by the `PpsDriver`, every access borrows it.
+pub struct Ds3231 {
+    address: u8,
+}
+
+impl Ds3231 {
+    const REG_SECONDS: u8 = 0x00;
+    const REG_STATUS: u8 = 0x0f;
+    /// oscillator stop flag, set when the time is not valid
+    const STATUS_OSF: u8 = 0x80;
+    const CENTURY: u8 = 0x80;
+    const HOUR_12H: u8 = 0x40;
+
+    pub fn new() -> Self {
+        Self { address: 0x68 }
+    }
+
+    pub async fn read(&self, i2c: &mut I2cType) -> Result<DateTime, RtcError> {
+        let mut status = [0_u8; 1];
+        i2c.write_read_async(self.address, &[Self::REG_STATUS], &mut status)
+            .await?;
+        if status[0] & Self::STATUS_OSF != 0 {
+            return Err(RtcError::TimeLost);
+        }
+
+        let mut regs = [0_u8; 7];
+        i2c.write_read_async(self.address, &[Self::REG_SECONDS], &mut regs)
+            .await?;
+        if regs[2] & Self::HOUR_12H != 0 {
+            return Err(RtcError::TimeLost); // never set by us
+        }
+        let time = DateTime {
+            year: 2000 + 100 * (regs[5] & Self::CENTURY != 0) as u16 + from_bcd(regs[6]) as u16,
+            month: from_bcd(regs[5] & !Self::CENTURY),
+            day: from_bcd(regs[4]),
+            hour: from_bcd(regs[2]),
+            minute: from_bcd(regs[1]),
+            second: from_bcd(regs[0]),
+            millis: 0,
+        };
+        if time.is_valid() {
+            Ok(time)
+        } else {
+            Err(RtcError::TimeLost)
+        }
+    }
+
+    pub async fn write(&self, i2c: &mut I2cType, time: &DateTime) -> Result<(), RtcError> {
+        let century = if time.year >= 2100 { Self::CENTURY } else { 0 };
+        let regs = [
+            Self::REG_SECONDS,
+            to_bcd(time.second),
+            to_bcd(time.minute),
+            to_bcd(time.hour),
+            1, // day of week, not used
+            to_bcd(time.day),
+            to_bcd(time.month) | century,
+            to_bcd((time.year % 100) as u8),
+        ];
+        i2c.write_async(self.address, &regs).await?;
+
+        let mut status = [0_u8; 1];
+        i2c.write_read_async(self.address, &[Self::REG_STATUS], &mut status)
+            .await?;
+        i2c.write_async(self.address, &[Self::REG_STATUS, status[0] & !Self::STATUS_OSF])
+            .await?;
+        Ok(())
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    (value / 10) << 4 | value % 10
+}
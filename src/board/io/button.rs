@@ -1,10 +1,21 @@
 use async_button::{Button, ButtonConfig, ButtonEvent as AsyncButtonEvent};
-use embassy_futures::select::{select3, Either3};
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::gpio::{AnyPin, Input, InputConfig, Pull};
 
+use crate::app::recorder::{self, Trigger};
+use crate::app::screenshot;
 use crate::app::shared::SenderType;
 use crate::app::shared::{ButtonEvent, RegulatorEvent};
 
+/// Dec and Inc pressed together within this time: once triggers the flight recorder, twice (a double
+/// press of both) takes a screenshot
+///
+/// A short press of Dec or Inc is held back for this time, waiting for the other one. The presses of a
+/// combination never reach the state machine, so they neither change the page or a setting nor wake the
+/// display. Other short presses of Dec and Inc reach it delayed by this time.
+const COMBO_WINDOW: Duration = Duration::from_millis(300);
+
 
 #[embassy_executor::task]
 pub async fn button_task(
@@ -12,24 +23,72 @@ pub async fn button_task(
     sender: SenderType,
 ) -> ! {
     let (mut button_left, mut button_center, mut button_right) = button_resources.into_buttons();
+    // short press of Dec or Inc held back, see `COMBO_WINDOW`
+    let mut held: Option<(Instant, ButtonEvent)> = None;
     loop {
-        // decode button events
-        let button_event = match select3(button_left.update(), button_center.update(), button_right.update()).await {
-            Either3::First(event) => match event {
-                AsyncButtonEvent::ShortPress { count } => ButtonEvent::DecShort(count),
-                AsyncButtonEvent::LongPress => ButtonEvent::DecLong,
-            },
-            Either3::Second(event) => match event {
-                AsyncButtonEvent::ShortPress { count } => ButtonEvent::OkShort(count),
-                AsyncButtonEvent::LongPress => ButtonEvent::OkLong,
-            },
-            Either3::Third(event) => match event {
-                AsyncButtonEvent::ShortPress { count } => ButtonEvent::IncShort(count),
-                AsyncButtonEvent::LongPress => ButtonEvent::IncLong,
+        let next = next_event(&mut button_left, &mut button_center, &mut button_right);
+        let button_event = match held {
+            Some((time, event)) => match select(next, Timer::at(time + COMBO_WINDOW)).await {
+                Either::First(button_event) => button_event,
+                Either::Second(()) => {
+                    // not pressed together
+                    held = None;
+                    sender.send(RegulatorEvent::Button(event)).await;
+                    continue;
+                }
             },
+            None => next.await,
+        };
+
+        let combo = match (held, button_event) {
+            (Some((_, ButtonEvent::DecShort(first))), ButtonEvent::IncShort(count))
+            | (Some((_, ButtonEvent::IncShort(first))), ButtonEvent::DecShort(count))
+                if first == count =>
+            {
+                Some(count)
+            }
+            _ => None,
         };
-        sender.send(RegulatorEvent::Button(button_event)).await;
-        // intentionally no timer/ticker here, loop is inhibited by polling the update() method of the buttons
+        if let Some(count) = combo {
+            held = None;
+            match count {
+                1 => recorder::trigger(Trigger::Manual),
+                2 => screenshot::request(),
+                _ => {}
+            }
+            continue;
+        }
+
+        if let Some((_, event)) = held.take() {
+            sender.send(RegulatorEvent::Button(event)).await;
+        }
+        match button_event {
+            ButtonEvent::DecShort(_) | ButtonEvent::IncShort(_) => held = Some((Instant::now(), button_event)),
+            _ => sender.send(RegulatorEvent::Button(button_event)).await,
+        }
+        // no ticker here, the loop is inhibited by polling the update() method of the buttons
+    }
+}
+
+/// Waits for the next press of any button
+async fn next_event(
+    button_left: &mut Button<Input<'_>>,
+    button_center: &mut Button<Input<'_>>,
+    button_right: &mut Button<Input<'_>>,
+) -> ButtonEvent {
+    match select3(button_left.update(), button_center.update(), button_right.update()).await {
+        Either3::First(event) => match event {
+            AsyncButtonEvent::ShortPress { count } => ButtonEvent::DecShort(count),
+            AsyncButtonEvent::LongPress => ButtonEvent::DecLong,
+        },
+        Either3::Second(event) => match event {
+            AsyncButtonEvent::ShortPress { count } => ButtonEvent::OkShort(count),
+            AsyncButtonEvent::LongPress => ButtonEvent::OkLong,
+        },
+        Either3::Third(event) => match event {
+            AsyncButtonEvent::ShortPress { count } => ButtonEvent::IncShort(count),
+            AsyncButtonEvent::LongPress => ButtonEvent::IncLong,
+        },
     }
 }
 
@@ -0,0 +1,30 @@
+use embassy_time::{Duration, Ticker};
+use esp_hal::gpio::{AnyPin, Level, Output, OutputConfig};
+
+use crate::app::alarm;
+
+/// length of one step of the alarm patterns
+const STEP: Duration = Duration::from_millis(100);
+
+/// Drives the alarm output, a buzzer or relay depending on `Config::alarm_output`
+#[embassy_executor::task]
+pub async fn buzzer_task(buzzer_resources: BuzzerResources<'static>) -> ! {
+    let mut output = buzzer_resources.into_output();
+    let mut ticker = Ticker::every(STEP);
+    let mut step: u32 = 0;
+    loop {
+        ticker.next().await;
+        output.set_level(Level::from(alarm::output(step)));
+        step = step.wrapping_add(1);
+    }
+}
+
+pub struct BuzzerResources<'a> {
+    pub pin: AnyPin<'a>,
+}
+
+impl<'a> BuzzerResources<'a> {
+    pub fn into_output(self) -> Output<'a> {
+        Output::new(self.pin, Level::Low, OutputConfig::default())
+    }
+}
@@ -0,0 +1,54 @@
+use embassy_futures::select::{select, Either};
+
+use crate::app::config::{CONFIG, CONFIG_SAVE};
+use crate::app::stats::{STATS, STATS_SAVE};
+use crate::board::driver::flash::ConfigStore;
+use crate::fmt::Debug2Format;
+
+/// Loads the persistent configuration from flash, falls back to defaults if none is stored
+///
+/// Called once from `main` before the tasks are spawned, so every task sees the stored values.
+pub fn load_config(store: &mut ConfigStore) {
+    match store.load() {
+        Ok(config) => {
+            info!("loaded configuration: {:?}", Debug2Format(&config));
+            CONFIG.lock(|c| c.replace(config));
+        }
+        Err(err) => warn!("could not load configuration, using defaults: {:?}", Debug2Format(&err)),
+    }
+}
+
+/// Loads the statistics from flash, starts from zero if none are stored
+pub fn load_stats(store: &mut ConfigStore) {
+    match store.load_stats() {
+        Ok(mut stats) => {
+            info!("loaded statistics: {:?}", Debug2Format(&stats));
+            stats.on_boot();
+            STATS.lock(|s| s.replace(stats));
+        }
+        Err(err) => warn!("could not load statistics, starting from zero: {:?}", Debug2Format(&err)),
+    }
+}
+
+/// Owns the flash, persists configuration and statistics on request
+#[embassy_executor::task]
+pub async fn config_task(mut store: ConfigStore) -> ! {
+    loop {
+        match select(CONFIG_SAVE.wait(), STATS_SAVE.wait()).await {
+            Either::First(()) => {
+                let config = CONFIG.lock(|c| c.borrow().clone());
+                match store.store(&config) {
+                    Ok(()) => info!("configuration saved"),
+                    Err(err) => error!("could not save configuration: {:?}", Debug2Format(&err)),
+                }
+            }
+            Either::Second(()) => {
+                let stats = STATS.lock(|s| s.borrow().clone());
+                match store.store_stats(&stats) {
+                    Ok(()) => debug!("statistics saved"),
+                    Err(err) => error!("could not save statistics: {:?}", Debug2Format(&err)),
+                }
+            }
+        }
+    }
+}
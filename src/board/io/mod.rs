@@ -4,6 +4,8 @@ use crate::app::shared::PROCESS_DATA;
 use crate::board::driver::analog::AdcDriverType;
 
 pub mod button;
+pub mod buzzer;
+pub mod flash;
 pub mod led;
 pub mod pps;
 pub mod radio;
@@ -1,5 +1,8 @@
-use crate::app::shared::{PpsSetMode, PROCESS_DATA, SETPOINT};
+use crate::app::clock::{self, RTC_SET};
+use crate::app::recorder::{self, Trigger};
+use crate::app::shared::{set_fault, Fault, PpsSetMode, PROCESS_DATA, SETPOINT};
 use crate::board::driver::pps::{PpsDriver, PpsError};
+use crate::board::driver::rtc::Ds3231;
 use core::sync::atomic::Ordering;
 use embassy_time::{with_timeout, Duration, Instant, Ticker};
 use esp_hal::gpio::AnyPin;
@@ -9,6 +12,8 @@ use num_traits::FromPrimitive;
 
 
 const PPS_LOOP_TIME_MS: u64 = 500;
+/// the uptime clock is good enough to run between the RTC readings
+const RTC_SYNC_INTERVAL_S: u64 = 3600;
 
 pub async fn read_pps(pps: &mut PpsDriver) {
     pps.get_voltage()
@@ -65,6 +70,24 @@ pub async fn write_pps(pps: &mut PpsDriver) -> Result<(), PpsError> {
     Ok(())
 }
 
+/// Sets the wall clock from the RTC, or the RTC from a new wall clock time
+pub async fn sync_rtc(pps: &mut PpsDriver, rtc: &Ds3231, read: bool) {
+    if let Some(time) = RTC_SET.try_take() {
+        match rtc.write(pps.i2c(), &time).await {
+            Ok(()) => info!("RTC set to {}", time),
+            Err(e) => warn!("RTC write error: {:?}", e),
+        }
+    } else if read {
+        match rtc.read(pps.i2c()).await {
+            Ok(time) => {
+                debug!("RTC time: {}", time);
+                clock::set(&time);
+            }
+            Err(e) => warn!("RTC read error: {:?}", e),
+        }
+    }
+}
+
 #[embassy_executor::task]
 pub async fn pps_task(pps_resources: PpsResources<'static>) -> () {
     let mut pps = match pps_resources.into_pps() {
@@ -75,22 +98,38 @@ pub async fn pps_task(pps_resources: PpsResources<'static>) -> () {
         },
     };
 
+    let rtc = Ds3231::new();
+    let mut rtc_synced: Option<Instant> = None;
+
     let mut ticker = Ticker::every(Duration::from_millis(PPS_LOOP_TIME_MS));
     loop {
         let loop_start = Instant::now();
         trace!("process_data: {:?}", crate::fmt::Debug2Format(&PROCESS_DATA));
         trace!("setpoint: {:?}", crate::fmt::Debug2Format(&SETPOINT));
-        with_timeout(Duration::from_millis(PPS_LOOP_TIME_MS * 3), async {
-            write_pps(&mut pps)
+        let ok = with_timeout(Duration::from_millis(PPS_LOOP_TIME_MS * 3), async {
+            let written = write_pps(&mut pps)
                 .await
-                .unwrap_or_else(|e| warn!("PPS write error: {:?}", e));
+                .inspect_err(|e| warn!("PPS write error: {:?}", e))
+                .is_ok();
             read_pps(&mut pps).await;
+
+            let read_rtc = rtc_synced.is_none_or(|t| t.elapsed() >= Duration::from_secs(RTC_SYNC_INTERVAL_S));
+            sync_rtc(&mut pps, &rtc, read_rtc).await;
+            if read_rtc {
+                rtc_synced = Some(Instant::now());
+            }
+            written
         })
         .await
         .unwrap_or_else(|_| {
             error!("timeout in io i2c loop");
             ticker.reset_at(Instant::now() - Duration::from_millis(PPS_LOOP_TIME_MS));
+            false
         });
+        // only when the fault appears, a persistent one would fill the card with recordings
+        if set_fault(Fault::Pps, !ok) && !ok {
+            recorder::trigger(Trigger::Fault);
+        }
         let loop_time = loop_start.elapsed();
         debug!("io loop time: {:?} ms", loop_time.as_millis());
         ticker.next().await;
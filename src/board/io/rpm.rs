@@ -3,16 +3,16 @@ use embassy_time::{Duration, Ticker};
 use esp_hal::gpio::{AnyPin, Input, InputConfig};
 use thiserror_no_std::Error;
 
+use crate::app::config::CONFIG;
+use crate::app::rpm::RpmClassifier;
 use crate::app::shared::SenderType;
-use crate::app::shared::{ProcessData, RegulatorEvent, RpmEvent, PROCESS_DATA, RPM_MIN};
+use crate::app::shared::{ProcessData, RegulatorEvent, PROCESS_DATA, RPM_MIN};
 use crate::board::driver::pcnt::PcntDriver;
-use crate::util::zc::detect_zero_crossing_with_hysteresis;
 use crate::Debug2Format;
 
 
 const RPM_LOOP_TIME_MS: u64 = 100;
-const POLE_PAIRS: f32 = 6.;
-const PULLEY_RATIO: f32 = 53.7 / 128.2;
+const PULSE_RATE_EXP_MA_COEFF: f32 = 0.05; // ~2s time constant, smooths the rate for calibration
 
 #[derive(Debug, Error)]
 pub enum RpmError {
@@ -23,12 +23,19 @@ pub enum RpmError {
 
 pub async fn read_rpm(pcnt_driver: &mut PcntDriver) -> f32 {
     let pulse_count = pcnt_driver.get_and_reset();
-    let rpm = pulse_count as f32
+    let pulses_per_rev = CONFIG.lock(|c| c.borrow().rpm_pulses_per_rev);
+    let pulse_rate = pulse_count as f32 * (1000. / RPM_LOOP_TIME_MS as f32); // intervals per second
+    let rpm = pulse_rate
         * 60.                              // Hz -> rpm
-        * (1./POLE_PAIRS/2.)               // 6 pole pairs, 2 imp per rev
-        * (1000./RPM_LOOP_TIME_MS as f32)  // intervals per second
-        * PULLEY_RATIO; // belt ratio
+        / pulses_per_rev; // pole pairs and belt ratio, calibrated
     PROCESS_DATA.rpm.store(rpm, Ordering::Relaxed);
+
+    let mut filtered_rate = PROCESS_DATA.pulse_rate.load(Ordering::Relaxed);
+    if !filtered_rate.is_finite() {
+        filtered_rate = pulse_rate;
+    }
+    filtered_rate = (1. - PULSE_RATE_EXP_MA_COEFF) * filtered_rate + PULSE_RATE_EXP_MA_COEFF * pulse_rate;
+    PROCESS_DATA.pulse_rate.store(filtered_rate, Ordering::Relaxed);
     rpm
 }
 
@@ -43,15 +50,16 @@ pub async fn rpm_task(rpm_resoures: RpmResoures<'static>, sender: SenderType) ->
         },
     };
 
-    let mut above = false;
-    let mut crossed;
+    let mut classifier = RpmClassifier::new();
 
     let mut ticker = Ticker::every(Duration::from_millis(RPM_LOOP_TIME_MS));
     loop {
         let rpm = read_rpm(&mut pcnt_driver).await;
-        (above, crossed) = detect_zero_crossing_with_hysteresis(rpm, RPM_MIN as f32, 0.05, above);
-        if crossed {
-            let event = if above { RpmEvent::Normal } else { RpmEvent::Low };
+        let (rpm_min, rpm_normal, hysteresis) = CONFIG.lock(|c| {
+            let c = c.borrow();
+            (c.rpm_min, c.rpm_normal, c.rpm_hysteresis)
+        });
+        if let Some(event) = classifier.update(rpm, rpm_min, rpm_normal, hysteresis) {
             sender.send(RegulatorEvent::Rpm(event)).await;
             debug!("sending rpm event: {:?}", event);
         }
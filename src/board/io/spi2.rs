@@ -2,32 +2,66 @@
 //! This module therefore handles both in a common task to avoid unnecessary low-level/high-frequency synchronization.
 
 use embassy_futures::join::join;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
 use embedded_hal_bus::spi::RefCellDevice;
 use embedded_sdmmc::SdCard;
 use esp_hal::{
     delay::Delay,
     dma::{AnySpiDmaChannel, DmaBufError, DmaError},
     gpio::{AnyPin, Output},
+    peripherals::LEDC,
     spi::master::{AnySpi, SpiDmaBus},
     Async,
 };
 use thiserror_no_std::Error;
 
 use crate::app::logger::logger_loop;
+use crate::board::driver::backlight::{Backlight, BacklightError};
 use crate::board::driver::display::{DisplayDriver, DisplayError};
 use crate::fmt::Debug2Format;
 use crate::ui::ui_loop;
 
-type SpiBusType = SpiDmaBus<'static, Async>;
+pub type SpiBusType = SpiDmaBus<'static, Async>;
 pub type SpiDeviceType<'a> = RefCellDevice<'a, SpiBusType, Output<'static>, Delay>;
 pub type SdCardType = SdCard<SpiDeviceType<'static>, Delay>;
+/// the display driver selects the display itself, see `DisplayDriver::write_pixels`
+pub type DisplayDeviceType<'a> = RefCellDevice<'a, SpiBusType, NoCs, Delay>;
 
+/// Held while the display or the SD card uses the SPI bus
+///
+/// The display keeps the bus borrowed while it awaits a DMA transfer, the SD card driver would panic on
+/// borrowing it then.
+pub static BUS_LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// holds one LVGL draw buffer, so a flush is a single DMA transfer
+const TX_BUFFER_SIZE: usize = 320 * 40 * 2;
+
+/// Chip select that is not driven by the SPI device
+pub struct NoCs;
+
+impl embedded_hal::digital::ErrorType for NoCs {
+    type Error = core::convert::Infallible;
+}
+
+impl embedded_hal::digital::OutputPin for NoCs {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum Spi2Error {
     #[error("Display initialization failed: {0:?}")]
     DisplayInitFailed(#[from] DisplayError),
 
+    #[error("Backlight initialization failed: {0:?}")]
+    BacklightInitFailed(#[from] BacklightError),
+
     #[error("DMA initialization failed: {0:?}")]
     DmaInitFailed(#[from] DmaBufError),
 
@@ -50,6 +84,7 @@ pub struct Spi2Resources<'a> {
     pub display_dma: AnySpiDmaChannel<'a>,
     pub display_cs: AnyPin<'a>,
     pub display_bl: AnyPin<'a>,
+    pub display_ledc: LEDC<'a>,
     pub display_dc: AnyPin<'a>,
     pub display_rst: AnyPin<'a>,
 }
@@ -69,7 +104,7 @@ impl Spi2Resources<'static> {
         use esp_hal::dma::{DmaRxBuf, DmaTxBuf};
         use embedded_sdmmc::SdCard;
 
-        let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(4092);
+        let (rx_buffer, rx_descriptors, tx_buffer, tx_descriptors) = dma_buffers!(4092, TX_BUFFER_SIZE);
         let dma_tx_buf = DmaTxBuf::new(tx_descriptors, tx_buffer)?;
         let dma_rx_buf = DmaRxBuf::new(rx_descriptors, rx_buffer)?;
 
@@ -91,12 +126,12 @@ impl Spi2Resources<'static> {
         let sd_card = SdCard::new(card_spi_device, Delay::new());
 
         let display_cs = Output::new(self.display_cs, Level::High, OutputConfig::default());
-        let display_spi_device = RefCellDevice::new(shared_spi_bus, display_cs, Delay::new())?;
+        let display_spi_device = RefCellDevice::new(shared_spi_bus, NoCs, Delay::new())?;
 
-        let bl = Output::new(self.display_bl, Level::High, OutputConfig::default());
+        let backlight = Backlight::new(self.display_ledc, self.display_bl)?;
         let dc = Output::new(self.display_dc, Level::Low, OutputConfig::default());
         let rst = Output::new(self.display_rst, Level::Low, OutputConfig::default());
-        let display = DisplayDriver::new(display_spi_device, bl, rst, dc)?;
+        let display = DisplayDriver::new(display_spi_device, shared_spi_bus, display_cs, backlight, rst, dc)?;
 
         Ok((sd_card, display,))
     }
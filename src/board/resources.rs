@@ -1,6 +1,7 @@
 //! This module does all the pin- and unit wiring for the board.
 
 use crate::board::io::button::ButtonResources;
+use crate::board::io::buzzer::BuzzerResources;
 use crate::board::io::led::LedResources;
 use crate::board::io::pps::PpsResources;
 use crate::board::io::radio::RadioResources;
@@ -33,7 +34,7 @@ pub fn initialize() -> peripherals::Peripherals {
     peripherals
 }
 
-pub fn collect(peripherals: Peripherals) -> (LedResources<'static>, Spi2Resources<'static>, PpsResources<'static>, ButtonResources<'static>, RadioResources<'static>, RpmResoures<'static>, SystemResources<'static>) {
+pub fn collect(peripherals: Peripherals) -> (LedResources<'static>, Spi2Resources<'static>, PpsResources<'static>, ButtonResources<'static>, BuzzerResources<'static>, RadioResources<'static>, RpmResoures<'static>, SystemResources<'static>) {
     let led_resources = LedResources {
         core0: AnyPin::from(peripherals.GPIO12),
         core1: AnyPin::from(peripherals.GPIO15),
@@ -49,6 +50,7 @@ pub fn collect(peripherals: Peripherals) -> (LedResources<'static>, Spi2Resource
         card_cs: AnyPin::from(peripherals.GPIO4),
         display_cs: AnyPin::from(peripherals.GPIO14),
         display_bl: AnyPin::from(peripherals.GPIO32),
+        display_ledc: peripherals.LEDC,
         display_dc: AnyPin::from(peripherals.GPIO27),
         display_rst: AnyPin::from(peripherals.GPIO33),
     };
@@ -73,6 +75,10 @@ pub fn collect(peripherals: Peripherals) -> (LedResources<'static>, Spi2Resource
         button_center: AnyPin::from(peripherals.GPIO38),
         button_right: AnyPin::from(peripherals.GPIO37),
     };
+    // optional buzzer or relay for alarms, on port B
+    let buzzer_resources = BuzzerResources {
+        pin: AnyPin::from(peripherals.GPIO26),
+    };
 
     let tg1 = TimerGroup::new(peripherals.TIMG1);
     let system_resources = SystemResources {
@@ -88,6 +94,7 @@ pub fn collect(peripherals: Peripherals) -> (LedResources<'static>, Spi2Resource
         spi2_resources,
         pps_resources,
         button_resources,
+        buzzer_resources,
         radio_resources,
         rpm_resources,
         system_resources,
@@ -23,7 +23,10 @@ use esp_backtrace as _;
 use esp_println as _;
 use static_cell::make_static;
 
+use board::driver::flash::ConfigStore;
 use board::io::button::button_task;
+use board::io::buzzer::buzzer_task;
+use board::io::flash::{config_task, load_config, load_stats};
 use board::io::{pps::pps_task, radio::radio_task, rpm::rpm_task};
 use board::resources;
 use embassy_time::{Duration, Ticker, Timer};
@@ -32,14 +35,19 @@ use esp_alloc::HeapStats;
 use esp_hal::{
     gpio::Output,
     main,
-    system::{CpuControl, Stack},
+    rtc_cntl::reset_reason,
+    system::{Cpu, CpuControl, Stack},
 };
 use esp_hal::interrupt::Priority;
 use esp_hal_embassy::{Callbacks, InterruptExecutor};
 
 use crate::board::io::spi2::{spi2_task};
+use app::alarm::alarm_task;
 use app::control::controller_task;
+use app::journal::{self, EntryKind};
 use app::mode::regulator_mode_task;
+use app::history::history_task;
+use app::stats::stats_task;
 use app::shared::{RegulatorEvent, SenderType};
 use fmt::Debug2Format;
 use util::led_debug::LedDebug;
@@ -71,15 +79,20 @@ fn main() -> ! {
     }
 
     let peripherals = resources::initialize();
-    let (led_resources, spi2_resources, pps_resources, button_resources, radio_resources, rpm_resources, system_resources) = resources::collect(peripherals);
+    let (led_resources, spi2_resources, pps_resources, button_resources, buzzer_resources, radio_resources, rpm_resources, system_resources) = resources::collect(peripherals);
 
     esp_hal_embassy::init([system_resources.timer1_0, system_resources.timer1_1]);
     let mut cpu_ctrl = CpuControl::new(system_resources.cpu_ctrl);
     info!("Embassy initialized!");
+    journal::record(EntryKind::Boot, format_args!("{:?}", reset_reason(Cpu::ProCpu)));
 
     let leds = led_resources.into_leds();
     LedDebug::create(leds.user);
 
+    let mut config_store = ConfigStore::new();
+    load_config(&mut config_store);
+    load_stats(&mut config_store);
+
     let channel = app::shared::prepare_channel();
     let button_sender = channel.sender();
     let rpm_sender = channel.sender();
@@ -102,6 +115,9 @@ fn main() -> ! {
             spawner_app.must_spawn(app_main(ready_sender));
             spawner_app.must_spawn(pps_task(pps_resources));
             spawner_app.must_spawn(regulator_mode_task(receiver));
+            spawner_app.must_spawn(stats_task());
+            spawner_app.must_spawn(history_task());
+            spawner_app.must_spawn(alarm_task());
             loop {
                 // leds.core1.set_low();
                 unsafe { core::arch::asm!("waiti 0"); };
@@ -115,6 +131,8 @@ fn main() -> ! {
     spawner_pro.must_spawn(spi2_task(spi2_resources));
     spawner_pro.must_spawn(pro_main());
     spawner_pro.must_spawn(radio_task(radio_resources));
+    spawner_pro.must_spawn(config_task(config_store));
+    spawner_pro.must_spawn(buzzer_task(buzzer_resources));
 
     loop {
         unsafe { core::arch::asm!("waiti 0"); };
@@ -0,0 +1,83 @@
+use lvgl_rust_sys::{
+    lv_align_t, lv_color_hex, lv_disp_get_default, lv_disp_get_layer_top, lv_font_montserrat_40, lv_obj_add_flag,
+    lv_obj_clear_flag, lv_obj_create, lv_obj_flag_t, lv_obj_set_size, lv_obj_set_style_bg_color,
+    lv_obj_set_style_bg_opa, lv_obj_t, lv_opa_t, lv_text_align_t, LV_ALIGN_BOTTOM_MID, LV_ALIGN_CENTER,
+    LV_ALIGN_TOP_MID, LV_OBJ_FLAG_HIDDEN, LV_OPA_COVER, LV_TEXT_ALIGN_CENTER,
+};
+
+use super::lvgl::{Label, Widget, WidgetError};
+use crate::app::alarm::{AlarmSource, Severity};
+
+/// Full screen alert shown on top of all pages, see `app::alarm`
+#[derive(Debug)]
+pub struct AlarmOverlay<'a> {
+    handle: *mut lv_obj_t,
+    severity_label: Label<'a>,
+    reason_label: Label<'a>,
+    /// reason currently shown, to skip redundant updates
+    shown: Option<&'static str>,
+}
+
+impl<'a> AlarmOverlay<'a> {
+    pub fn new() -> Result<Self, WidgetError> {
+        let handle = unsafe { lv_obj_create(lv_disp_get_layer_top(lv_disp_get_default())) };
+        if handle.is_null() {
+            return Err(WidgetError::LvglNullPointer);
+        }
+        unsafe {
+            lv_obj_set_size(handle, 320, 240);
+            lv_obj_set_style_bg_opa(handle, LV_OPA_COVER as lv_opa_t, 0);
+            lv_obj_add_flag(handle, LV_OBJ_FLAG_HIDDEN as lv_obj_flag_t);
+        }
+
+        let severity_label = Label::new(handle, "")?;
+        severity_label
+            .align(LV_ALIGN_TOP_MID as lv_align_t, 0, 20)
+            .color(0xffffff)
+            .font(unsafe { &lv_font_montserrat_40 });
+
+        let reason_label = Label::new(handle, "")?;
+        reason_label
+            .width(280)
+            .align(LV_ALIGN_CENTER as lv_align_t, 0, 0)
+            .color(0xffffff)
+            .text_align(LV_TEXT_ALIGN_CENTER as lv_text_align_t);
+
+        Label::new(handle, "")?
+            .text("Hold Inc to ack")?
+            .align(LV_ALIGN_BOTTOM_MID as lv_align_t, 0, -10)
+            .color(0xffffff);
+
+        Ok(Self {
+            handle,
+            severity_label,
+            reason_label,
+            shown: None,
+        })
+    }
+
+    /// Shows `alarm`, or hides the overlay if there is none
+    pub fn update(&mut self, alarm: Option<&'static AlarmSource>) -> Result<(), WidgetError> {
+        if self.shown == alarm.map(|a| a.name) {
+            return Ok(());
+        }
+        match alarm {
+            Some(alarm) => {
+                let color = match alarm.severity {
+                    Severity::Info => 0x0050a0,
+                    Severity::Warning => 0xc06000,
+                    Severity::Critical => 0xc00000,
+                };
+                self.severity_label.text(alarm.severity.name())?;
+                self.reason_label.text(alarm.name)?;
+                unsafe {
+                    lv_obj_set_style_bg_color(self.handle, lv_color_hex(color), 0);
+                    lv_obj_clear_flag(self.handle, LV_OBJ_FLAG_HIDDEN as lv_obj_flag_t);
+                }
+            }
+            None => unsafe { lv_obj_add_flag(self.handle, LV_OBJ_FLAG_HIDDEN as lv_obj_flag_t) },
+        }
+        self.shown = alarm.map(|a| a.name);
+        Ok(())
+    }
+}
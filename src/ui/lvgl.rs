@@ -1,4 +1,6 @@
-use core::ffi::c_char;
+use core::ffi::{c_char, CStr};
+use core::mem::MaybeUninit;
+use core::ptr::addr_of_mut;
 use heapless::{format, CString, String};
 use heapless::c_string::ExtendError;
 use lvgl_rust_sys::*;
@@ -28,6 +30,64 @@ impl defmt::Format for WidgetError {
     }
 }
 
+/// text color of the night theme
+const NIGHT_COLOR: u32 = 0xc00000;
+
+/// style shared by all screens, carries the colors of the night theme
+static mut SCREEN_STYLE: MaybeUninit<lv_style_t> = MaybeUninit::uninit();
+
+/// Initializes the screen style, must be called before the first screen is created
+pub fn init_screen_style() {
+    unsafe { lv_style_init(addr_of_mut!(SCREEN_STYLE).cast::<lv_style_t>()) };
+}
+
+/// Switches all widgets between the default theme and a red-on-black night theme
+pub fn set_night_theme(night: bool) {
+    unsafe {
+        let disp = lv_disp_get_default();
+        let (primary, secondary) = if night {
+            (LV_PALETTE_RED, LV_PALETTE_RED)
+        } else {
+            (LV_PALETTE_BLUE, LV_PALETTE_RED)
+        };
+        // re-initializing the default theme updates the existing widgets
+        let theme = lv_theme_default_init(
+            disp,
+            lv_palette_main(primary as lv_palette_t),
+            lv_palette_main(secondary as lv_palette_t),
+            night,
+            lv_theme_get_font_normal(core::ptr::null_mut()),
+        );
+        lv_disp_set_theme(disp, theme);
+
+        let style = addr_of_mut!(SCREEN_STYLE).cast::<lv_style_t>();
+        if night {
+            lv_style_set_bg_color(style, lv_color_hex(0x000000));
+            lv_style_set_text_color(style, lv_color_hex(NIGHT_COLOR));
+        } else {
+            lv_style_remove_prop(style, LV_STYLE_BG_COLOR as lv_style_prop_t);
+            lv_style_remove_prop(style, LV_STYLE_TEXT_COLOR as lv_style_prop_t);
+        }
+        lv_obj_report_style_change(style);
+    }
+}
+
+/// Creates a screen with the common padding and theme, shown with `lv_scr_load`
+pub fn new_screen() -> Result<*mut lv_obj_t, WidgetError> {
+    let screen = unsafe { lv_obj_create(core::ptr::null_mut()) };
+    if screen.is_null() {
+        return Err(WidgetError::LvglNullPointer);
+    }
+    unsafe {
+        lv_obj_add_style(screen, addr_of_mut!(SCREEN_STYLE).cast::<lv_style_t>(), 0);
+        lv_obj_set_style_pad_top(screen, 6, 0);
+        lv_obj_set_style_pad_bottom(screen, 6, 0);
+        lv_obj_set_style_pad_left(screen, 12, 0);
+        lv_obj_set_style_pad_right(screen, 12, 0);
+    };
+    Ok(screen)
+}
+
 #[allow(unused)]
 pub trait Widget {
     fn get_handle(&self) -> *mut lv_obj_t;
@@ -50,7 +110,7 @@ pub trait Widget {
     }
 
     fn y(&self, y: i32) -> &Self {
-        unsafe { lv_obj_set_x(self.get_handle(), y as lv_coord_t) };
+        unsafe { lv_obj_set_y(self.get_handle(), y as lv_coord_t) };
         self
     }
 
@@ -67,13 +127,36 @@ pub trait Widget {
     fn set_value(&mut self, value: f32) -> Result<(), WidgetError>;
 }
 
+/// Ranges of the meter, see `overview::meter_scale`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MeterScale {
+    /// full scale of the current (A)
+    pub max_current: i32,
+    /// start of the yellow band (A)
+    pub warning_current: i32,
+    /// start of the red band (A)
+    pub limit_current: i32,
+    /// full scale of the RPM scale (100 rpm)
+    pub max_rpm: i32,
+    /// end of the grey no-charge band of the RPM scale (100 rpm)
+    pub min_rpm: i32,
+}
+
 #[derive(Debug, Default)]
 pub struct Meter<'a> {
     handle: *mut lv_obj_t,
+    current_scale: *mut lv_meter_scale_t,
+    current_ticks: *mut lv_meter_scale_t,
+    rpm_scale: *mut lv_meter_scale_t,
     current_needle: *mut lv_meter_indicator_t,
     rpm_needle: *mut lv_meter_indicator_t,
+    green_arc: *mut lv_meter_indicator_t,
+    yellow_arc: *mut lv_meter_indicator_t,
+    red_arc: *mut lv_meter_indicator_t,
+    idle_arc: *mut lv_meter_indicator_t,
     current_label: Label<'a>,
     state_label: Label<'a>,
+    limit_label: Label<'a>,
 }
 
 impl<'a> Widget for Meter<'a> {
@@ -82,70 +165,71 @@ impl<'a> Widget for Meter<'a> {
     }
 
     fn set_value(&mut self, value: f32) -> Result<(), WidgetError> {
-        unsafe {
-            lv_meter_set_indicator_value(self.handle, self.current_needle, value as i32);
-        }
+        self.set_needle(self.current_needle, value as i32);
         self.current_label.set_value(value)?;
         Ok(())
     }
 }
 
 impl<'a> Meter<'a> {
-    pub fn new(parent: *mut lv_obj_t) -> Result<Self, WidgetError> {
+    pub fn new(parent: *mut lv_obj_t, ranges: &MeterScale) -> Result<Self, WidgetError> {
         unsafe {
             let meter = lv_meter_create(parent);
             lv_obj_align(meter, LV_ALIGN_CENTER as lv_align_t, 0, 0);
             lv_obj_set_width(meter, 228);
             lv_obj_set_height(meter, 228);
 
-            let scale = lv_meter_add_scale(meter).as_mut().ok_or(WidgetError::LvglNullPointer)?;
-            scale.min = 0;
-            scale.max = 150;
-            scale.angle_range = 240;
-            scale.rotation = 150;
+            let current_scale = lv_meter_add_scale(meter).as_mut().ok_or(WidgetError::LvglNullPointer)?;
+            current_scale.angle_range = 240;
+            current_scale.rotation = 150;
 
-            let current_needle = lv_meter_add_needle_line(meter, scale, 5, lv_color_hex(0xff0000), -4)
+            let current_needle = lv_meter_add_needle_line(meter, current_scale, 5, lv_color_hex(0xff0000), -4)
                 .as_mut()
                 .ok_or(WidgetError::LvglNullPointer)?;
-            let rpm_needle = lv_meter_add_needle_line(meter, scale, 3, lv_color_hex(0xaaaaaa), -30)
+
+            let green_arc = lv_meter_add_arc(meter, current_scale, 10, lv_color_hex(0x009f00), 10)
                 .as_mut()
                 .ok_or(WidgetError::LvglNullPointer)?;
-
-            let green_arc = lv_meter_add_arc(meter, scale, 10, lv_color_hex(0x009f00), 10)
+            let yellow_arc = lv_meter_add_arc(meter, current_scale, 10, lv_color_hex(0xffff00), 10)
                 .as_mut()
                 .ok_or(WidgetError::LvglNullPointer)?;
-            green_arc.start_value = 0;
-            green_arc.end_value = 99;
-
-            let yellow_arc = lv_meter_add_arc(meter, scale, 10, lv_color_hex(0xffff00), 10)
+            let red_arc = lv_meter_add_arc(meter, current_scale, 10, lv_color_hex(0xff0000), 10)
                 .as_mut()
                 .ok_or(WidgetError::LvglNullPointer)?;
-            yellow_arc.start_value = 100;
-            yellow_arc.end_value = 119;
 
-            let red_arc = lv_meter_add_arc(meter, scale, 10, lv_color_hex(0xff0000), 10)
+            let current_ticks = lv_meter_add_scale(meter).as_mut().ok_or(WidgetError::LvglNullPointer)?;
+            current_ticks.angle_range = 240;
+            current_ticks.rotation = 150;
+            current_ticks.tick_width = 1;
+            current_ticks.tick_cnt = 51;
+            current_ticks.tick_length = 10;
+            current_ticks.tick_major_nth = 5;
+            current_ticks.tick_major_width = 2;
+            current_ticks.tick_major_length = 10;
+            current_ticks.label_gap = 10;
+
+            // engine RPM on a separate inner scale, labeled in 100 rpm
+            let rpm_scale = lv_meter_add_scale(meter).as_mut().ok_or(WidgetError::LvglNullPointer)?;
+            rpm_scale.angle_range = 240;
+            rpm_scale.rotation = 150;
+            rpm_scale.r_mod = -48;
+            rpm_scale.tick_width = 1;
+            rpm_scale.tick_length = 5;
+            rpm_scale.tick_color = lv_color_hex(0x808080);
+            rpm_scale.tick_major_nth = 5;
+            rpm_scale.tick_major_width = 2;
+            rpm_scale.tick_major_length = 8;
+            rpm_scale.tick_major_color = lv_color_hex(0x808080);
+            rpm_scale.label_gap = 6;
+
+            let rpm_needle = lv_meter_add_needle_line(meter, rpm_scale, 3, lv_color_hex(0xaaaaaa), -4)
+                .as_mut()
+                .ok_or(WidgetError::LvglNullPointer)?;
+            let idle_arc = lv_meter_add_arc(meter, rpm_scale, 3, lv_color_hex(0xaaaaaa), 0)
                 .as_mut()
                 .ok_or(WidgetError::LvglNullPointer)?;
-            red_arc.start_value = 120;
-            red_arc.end_value = 150;
-
-            let scale2 = lv_meter_add_scale(meter).as_mut().ok_or(WidgetError::LvglNullPointer)?;
-            scale2.min = 0;
-            scale2.max = 150;
-            scale2.angle_range = 240;
-            scale2.rotation = 150;
-            scale2.tick_width = 1;
-            scale2.tick_cnt = 51;
-            scale2.tick_length = 10;
-            scale2.tick_color = lv_color_hex(0x000000);
-            scale2.tick_major_nth = 5;
-            scale2.tick_major_width = 2;
-            scale2.tick_major_length = 10;
-            scale2.tick_major_color = lv_color_hex(0x404040);
-            scale2.label_gap = 10;
 
             lv_obj_set_style_text_font(meter, &lv_font_montserrat_14, 0);
-            lv_meter_set_indicator_value(meter, current_needle, 42);
 
             // Create labels for the meter
             let current_label = Label::new(meter, "")?;
@@ -164,13 +248,69 @@ impl<'a> Meter<'a> {
                 .text("<unknown>")?
                 .align(LV_ALIGN_CENTER as lv_align_t, 0, -35);
 
-            Ok(Meter {
+            let limit_label = Label::new(meter, "")?;
+            limit_label
+                .text("")?
+                .align(LV_ALIGN_CENTER as lv_align_t, 0, -55);
+
+            let mut meter = Meter {
                 handle: meter,
-                current_label,
+                current_scale,
+                current_ticks,
+                rpm_scale,
                 current_needle,
                 rpm_needle,
+                green_arc,
+                yellow_arc,
+                red_arc,
+                idle_arc,
+                current_label,
                 state_label,
-            })
+                limit_label,
+            };
+            meter.set_scale(ranges);
+            meter.update_tick_color();
+            Ok(meter)
+        }
+    }
+
+    /// Takes the colour of the current ticks from the text colour of their labels, which the screen style sets
+    pub fn update_tick_color(&mut self) {
+        unsafe {
+            let color =
+                lv_obj_get_style_prop(self.handle, LV_PART_TICKS as lv_part_t, LV_STYLE_TEXT_COLOR as lv_style_prop_t)
+                    .color;
+            let ticks = &mut *self.current_ticks;
+            if ticks.tick_color.full != color.full {
+                ticks.tick_color = color;
+                ticks.tick_major_color = color;
+                lv_obj_invalidate(self.handle);
+            }
+        }
+    }
+
+    /// Applies new ranges to the scales and colour bands
+    pub fn set_scale(&mut self, ranges: &MeterScale) {
+        unsafe {
+            for scale in [self.current_scale, self.current_ticks] {
+                (*scale).min = 0;
+                (*scale).max = ranges.max_current;
+            }
+            (*self.rpm_scale).min = 0;
+            (*self.rpm_scale).max = ranges.max_rpm;
+            // one tick per 100 rpm, labels every 500 rpm
+            (*self.rpm_scale).tick_cnt = ranges.max_rpm as u16 + 1;
+
+            for (arc, start, end) in [
+                (self.green_arc, 0, ranges.warning_current - 1),
+                (self.yellow_arc, ranges.warning_current, ranges.limit_current - 1),
+                (self.red_arc, ranges.limit_current, ranges.max_current),
+                (self.idle_arc, 0, ranges.min_rpm),
+            ] {
+                (*arc).start_value = start;
+                (*arc).end_value = end;
+            }
+            lv_obj_invalidate(self.handle);
         }
     }
 
@@ -179,11 +319,25 @@ impl<'a> Meter<'a> {
         Ok(self)
     }
 
+    /// shows the limit that currently determines the field current
+    pub fn set_limit(&mut self, limit: &str) -> Result<&Self, WidgetError> {
+        self.limit_label.text(limit)?;
+        Ok(self)
+    }
+
+    /// moves the RPM needle on the inner scale
     pub fn set_rpm(&mut self, rpm: f32) -> Result<&Self, WidgetError> {
+        self.set_needle(self.rpm_needle, (rpm / 100.) as i32);
+        Ok(self)
+    }
+
+    /// LVGL redraws a needle on every update, so it is only updated when it moves by a scale unit
+    fn set_needle(&mut self, needle: *mut lv_meter_indicator_t, value: i32) {
         unsafe {
-            lv_meter_set_indicator_value(self.handle, self.rpm_needle, (rpm / 100.) as i32);
+            if (*needle).end_value != value {
+                lv_meter_set_indicator_value(self.handle, needle, value);
+            }
         }
-        Ok(self)
     }
 }
 
@@ -217,8 +371,12 @@ impl<'a> Label<'a> {
         }
     }
 
-    /// Sets the text of the label.
+    /// Sets the text of the label, the label is only redrawn if the text changed.
     pub fn text(&self, text: &str) -> Result<&Self, WidgetError> {
+        let current = unsafe { lv_label_get_text(self.handle) };
+        if !current.is_null() && unsafe { CStr::from_ptr(current) }.to_bytes() == text.as_bytes() {
+            return Ok(self);
+        }
         let c_str = CString::<20>::from_bytes_with_nul(text.as_bytes())?;
         let c_ptr = c_str.as_ptr() as *mut c_char;
         unsafe { lv_label_set_text(self.handle, c_ptr) };
@@ -236,6 +394,12 @@ impl<'a> Label<'a> {
         unsafe { lv_obj_set_style_text_font(self.handle, font, 0) };
         self
     }
+
+    /// Sets the text color of the label, e.g. to match a chart series.
+    pub fn color(&self, color: u32) -> &Self {
+        unsafe { lv_obj_set_style_text_color(self.handle, lv_color_hex(color), 0) };
+        self
+    }
 }
 
 #[derive(Debug, Default)]
@@ -248,6 +412,7 @@ impl Widget for Bar {
         self.handle
     }
 
+    /// resolution 0.1, LVGL skips the redraw if the value did not change
     fn set_value(&mut self, value: f32) -> Result<(), WidgetError> {
         unsafe {
             lv_bar_set_value(self.handle, (value * 10.) as i32, lv_anim_enable_t_LV_ANIM_OFF);
@@ -289,3 +454,125 @@ impl Bar {
         self
     }
 }
+
+/// Line chart with up to two series, one on the primary and one on the secondary Y axis
+///
+/// LVGL charts use integer coordinates, so the values of each series are multiplied by its `scale`.
+#[derive(Debug, Default)]
+pub struct Chart {
+    handle: *mut lv_obj_t,
+    series: [*mut lv_chart_series_t; 2],
+    scale: [f32; 2],
+}
+
+impl Widget for Chart {
+    fn get_handle(&self) -> *mut lv_obj_t {
+        self.handle
+    }
+
+    /// appends a value to the primary series, shifting out the oldest one
+    fn set_value(&mut self, value: f32) -> Result<(), WidgetError> {
+        if self.series[0].is_null() {
+            return Err(WidgetError::LvglNullPointer);
+        }
+        unsafe { lv_chart_set_next_value(self.handle, self.series[0], self.coord(0, value)) };
+        Ok(())
+    }
+}
+
+impl Chart {
+    /// not drawn, used for missing values
+    const POINT_NONE: lv_coord_t = lv_coord_t::MAX;
+
+    pub fn new(parent: *mut lv_obj_t, points: u16) -> Result<Self, WidgetError> {
+        let handle = unsafe { lv_chart_create(parent) };
+        if handle.is_null() {
+            return Err(WidgetError::LvglNullPointer);
+        }
+        unsafe {
+            lv_chart_set_type(handle, LV_CHART_TYPE_LINE as lv_chart_type_t);
+            lv_chart_set_point_count(handle, points);
+            lv_chart_set_div_line_count(handle, 3, 0);
+            // lines only, no dots at the points
+            lv_obj_set_style_width(handle, 0, LV_PART_INDICATOR);
+            lv_obj_set_style_height(handle, 0, LV_PART_INDICATOR);
+        }
+        Ok(Chart {
+            handle,
+            ..Default::default()
+        })
+    }
+
+    pub fn width(self, width: i32) -> Self {
+        unsafe { lv_obj_set_width(self.handle, width as lv_coord_t) };
+        self
+    }
+
+    pub fn height(self, height: i32) -> Self {
+        unsafe { lv_obj_set_height(self.handle, height as lv_coord_t) };
+        self
+    }
+
+    pub fn align(self, alignment: lv_align_t, x_offset: i32, y_offset: i32) -> Self {
+        unsafe {
+            lv_obj_align(self.handle, alignment, x_offset as lv_coord_t, y_offset as lv_coord_t);
+        };
+        self
+    }
+
+    /// Adds the series of `axis` (0 primary, 1 secondary) showing values from `min` to `max`
+    pub fn series(mut self, axis: usize, color: u32, min: f32, max: f32, scale: f32) -> Result<Self, WidgetError> {
+        let lv_axis = if axis == 0 {
+            LV_CHART_AXIS_PRIMARY_Y
+        } else {
+            LV_CHART_AXIS_SECONDARY_Y
+        } as lv_chart_axis_t;
+        let series = unsafe { lv_chart_add_series(self.handle, lv_color_hex(color), lv_axis) };
+        if series.is_null() {
+            return Err(WidgetError::LvglNullPointer);
+        }
+        self.series[axis] = series;
+        self.scale[axis] = scale;
+        self.set_range(axis, min, max);
+        Ok(self)
+    }
+
+    /// Shows the values from `min` to `max` on `axis`
+    pub fn set_range(&mut self, axis: usize, min: f32, max: f32) {
+        let lv_axis = if axis == 0 {
+            LV_CHART_AXIS_PRIMARY_Y
+        } else {
+            LV_CHART_AXIS_SECONDARY_Y
+        } as lv_chart_axis_t;
+        let scale = self.scale[axis];
+        unsafe {
+            lv_chart_set_range(
+                self.handle,
+                lv_axis,
+                (min * scale) as lv_coord_t,
+                (max * scale) as lv_coord_t,
+            )
+        };
+    }
+
+    /// Replaces all points of the series of `axis`, NaN values are not drawn
+    pub fn set_points(&mut self, axis: usize, values: &[f32]) -> Result<(), WidgetError> {
+        let series = self.series[axis];
+        if series.is_null() {
+            return Err(WidgetError::LvglNullPointer);
+        }
+        for (id, value) in values.iter().enumerate() {
+            unsafe { lv_chart_set_value_by_id(self.handle, series, id as u16, self.coord(axis, *value)) };
+        }
+        unsafe { lv_chart_refresh(self.handle) };
+        Ok(())
+    }
+
+    fn coord(&self, axis: usize, value: f32) -> lv_coord_t {
+        if value.is_finite() {
+            (value * self.scale[axis]) as lv_coord_t
+        } else {
+            Self::POINT_NONE
+        }
+    }
+}
@@ -1,36 +1,98 @@
-use core::ffi::c_void;
+//! LVGL display driver: double-buffered rendering with DMA transfers to the display
+//!
+//! LVGL renders into one draw buffer while the other one is sent to the display. `flush_cb` only records
+//! the rendered area; [`refresh`] sends it by DMA while LVGL renders the next one, then awaits the end of
+//! the transfer, so other tasks run in the meantime. A buffer is only rendered into again after its
+//! transfer has been awaited, so `wait_cb` never lets LVGL wait. The SPI bus stays borrowed during a
+//! transfer, `BUS_LOCK` keeps the SD card logger off it until then.
+
+use core::future::Future;
 use core::mem::MaybeUninit;
-use core::ptr::addr_of_mut;
-use embedded_graphics::pixelcolor::Rgb565;
+use core::pin::{pin, Pin};
+use core::ptr::{addr_of_mut, null_mut};
+use core::task::{Context, Waker};
+use embassy_futures::yield_now;
 use embedded_graphics::prelude::*;
 use embedded_graphics::primitives::Rectangle;
 use lvgl_rust_sys::{
-    lv_area_t, lv_color_t, lv_disp_draw_buf_init, lv_disp_draw_buf_t, lv_disp_drv_init, lv_disp_drv_register,
-    lv_disp_drv_t, lv_disp_flush_ready,
+    _lv_area_is_on, _lv_area_join, _lv_disp_refr_timer, lv_area_get_size, lv_area_t, lv_color_t, lv_coord_t,
+    lv_disp_draw_buf_init, lv_disp_draw_buf_t, lv_disp_drv_init, lv_disp_drv_register, lv_disp_drv_t,
+    lv_disp_flush_ready, lv_disp_get_default, lv_disp_t, lv_obj_invalidate, lv_obj_update_layout, lv_timer_del,
 };
 use static_cell::make_static;
 
+use crate::app::screenshot::{self, Band};
 use crate::board::driver::display::DisplayDriver;
+use crate::board::io::spi2::BUS_LOCK;
+use crate::fmt::Debug2Format;
 
 const SCREEN_WIDTH: usize = 320;
 const SCREEN_HEIGHT: usize = 240;
 const COLOR_BUF_LINES: usize = 40;
+const COLOR_BUF_PIXELS: usize = SCREEN_WIDTH * COLOR_BUF_LINES;
 
 // buffers and driver struct placed in .dram2_uninit section for resource reasons
 #[link_section = ".dram2_uninit"]
 static mut DRAW_BUF: MaybeUninit<lv_disp_draw_buf_t> = MaybeUninit::uninit();
 
 #[link_section = ".dram2_uninit"]
-static mut COLOR_BUF: MaybeUninit<[lv_color_t; SCREEN_WIDTH * COLOR_BUF_LINES]> = MaybeUninit::uninit();
+static mut COLOR_BUF_1: MaybeUninit<[lv_color_t; COLOR_BUF_PIXELS]> = MaybeUninit::uninit();
+
+#[link_section = ".dram2_uninit"]
+static mut COLOR_BUF_2: MaybeUninit<[lv_color_t; COLOR_BUF_PIXELS]> = MaybeUninit::uninit();
+
+/// area rendered by LVGL and not yet sent to the display
+static mut PENDING: Option<Flush> = None;
+
+/// transfer being sent while LVGL renders, see `send_while_rendering`
+static mut IN_FLIGHT: *mut Transfer = null_mut();
+
+/// A rendered area, the pixels stay valid until `lv_disp_flush_ready` is called
+struct Flush {
+    disp_drv: *mut lv_disp_drv_t,
+    area: Rectangle,
+    pixels: *const u8,
+    len: usize,
+}
+
+/// A type-erased transfer future pinned on the stack of `send_while_rendering`, so that `wait_cb` can
+/// drive it without knowing its type
+struct Transfer {
+    future: *mut (),
+    poll: unsafe fn(*mut ()) -> bool,
+    done: bool,
+}
+
+impl Transfer {
+    fn new<F: Future<Output = ()>>(future: Pin<&mut F>) -> Self {
+        unsafe fn poll<F: Future<Output = ()>>(future: *mut ()) -> bool {
+            let future = unsafe { Pin::new_unchecked(&mut *future.cast::<F>()) };
+            future.poll(&mut Context::from_waker(Waker::noop())).is_ready()
+        }
+        Self {
+            future: unsafe { future.get_unchecked_mut() as *mut F }.cast::<()>(),
+            poll: poll::<F>,
+            done: false,
+        }
+    }
+
+    /// Polls the transfer unless it is complete, returns `true` once it is
+    fn poll(&mut self) -> bool {
+        if !self.done {
+            self.done = unsafe { (self.poll)(self.future) };
+        }
+        self.done
+    }
+}
 
-pub fn lvgl_disp_init(user_data: *mut core::ffi::c_void) {
-    // Initialize the draw buffer
+pub fn lvgl_disp_init() {
+    // Initialize the draw buffers
     unsafe {
         lv_disp_draw_buf_init(
             addr_of_mut!(DRAW_BUF).cast::<lv_disp_draw_buf_t>(),
-            addr_of_mut!(COLOR_BUF).cast::<c_void>(),
-            core::ptr::null_mut(),
-            (SCREEN_WIDTH * COLOR_BUF_LINES) as u32,
+            addr_of_mut!(COLOR_BUF_1).cast(),
+            addr_of_mut!(COLOR_BUF_2).cast(),
+            COLOR_BUF_PIXELS as u32,
         );
     }
 
@@ -41,43 +103,201 @@ pub fn lvgl_disp_init(user_data: *mut core::ffi::c_void) {
         disp_drv.hor_res = SCREEN_WIDTH as i16;
         disp_drv.ver_res = SCREEN_HEIGHT as i16;
         disp_drv.flush_cb = Some(flush_cb);
+        disp_drv.wait_cb = Some(wait_cb);
         disp_drv.draw_buf = addr_of_mut!(DRAW_BUF).cast::<lv_disp_draw_buf_t>();
-        disp_drv.user_data = user_data;
-        lv_disp_drv_register(disp_drv);
+        let disp = lv_disp_drv_register(disp_drv);
+        // rendering is done by `refresh` instead
+        if let Some(disp) = disp.as_mut() {
+            lv_timer_del(disp.refr_timer);
+            disp.refr_timer = null_mut();
+        }
+    }
+}
+
+/// Renders the invalidated areas of the display band by band, yielding in between
+///
+/// This replaces LVGL's refresh timer `_lv_disp_refr_timer()`, which renders and flushes all areas at
+/// once. Each band fits into one draw buffer and is sent while the next one is rendered. Yielding frees
+/// the shared SPI bus for the SD card logger between two bands.
+pub async fn refresh(display: &mut DisplayDriver) {
+    render_invalidated(display, false).await;
+}
+
+/// Renders the whole screen and hands each band to the data logger, see `app::screenshot`
+pub async fn take_screenshot(display: &mut DisplayDriver) {
+    unsafe {
+        let Some(disp) = lv_disp_get_default().as_mut() else {
+            return;
+        };
+        lv_obj_invalidate(disp.act_scr);
+    }
+    if render_invalidated(display, true).await {
+        info!("screenshot saved");
+    } else {
+        warn!("screenshot failed");
+    }
+}
+
+/// Renders the invalidated areas, with `capture` each band is saved before it is sent to the display
+///
+/// Returns `false` if saving a band failed.
+async fn render_invalidated(display: &mut DisplayDriver, mut capture: bool) -> bool {
+    unsafe {
+        let Some(disp) = lv_disp_get_default().as_mut() else {
+            return false;
+        };
+        // layout changes invalidate further areas
+        lv_obj_update_layout(disp.act_scr);
+        lv_obj_update_layout(disp.top_layer);
+        lv_obj_update_layout(disp.sys_layer);
+
+        let count = disp.inv_p as usize;
+        let mut areas = disp.inv_areas;
+        let mut joined = disp.inv_area_joined;
+        join_areas(&mut areas[..count], &mut joined[..count]);
+        for (area, _) in areas[..count].iter().zip(joined).filter(|(_, joined)| *joined == 0) {
+            for band in bands(area) {
+                send_while_rendering(display, Some((&mut *disp, band))).await;
+                if capture && !save_pending().await {
+                    capture = false;
+                }
+                yield_now().await;
+            }
+        }
+        // the last band
+        send_while_rendering(display, None).await;
+        capture
+    }
+}
+
+/// Saves the pending area as a band of the screenshot, the whole screen is rendered in full-width bands
+async unsafe fn save_pending() -> bool {
+    let band = (*addr_of_mut!(PENDING))
+        .as_ref()
+        .filter(|flush| flush.area.size.width as usize == screenshot::WIDTH)
+        .map(|flush| {
+            let y = flush.area.top_left.y as usize;
+            Band::new(y, flush.area.size.height as usize, flush.pixels)
+        });
+    match band {
+        Some(band) => screenshot::save(band).await,
+        None => false,
+    }
+}
+
+/// Sends the pending area to the display while LVGL renders `band`
+///
+/// Returns when both are done, with the rendered band pending. The transfer is awaited once rendering is
+/// done, with the SPI bus locked from its start.
+async unsafe fn send_while_rendering(display: &mut DisplayDriver, band: Option<(&mut lv_disp_t, lv_area_t)>) {
+    let Some(flush) = (*addr_of_mut!(PENDING)).take() else {
+        if let Some((disp, band)) = band {
+            render(disp, band);
+        }
+        return;
+    };
+    let _bus = BUS_LOCK.lock().await;
+    let mut future = pin!(async {
+        let pixels = core::slice::from_raw_parts(flush.pixels, flush.len);
+        if let Err(e) = display.write_pixels(&flush.area, pixels).await {
+            warn!("Failed to flush display: {:?}", Debug2Format(&e));
+        }
+        lv_disp_flush_ready(flush.disp_drv);
+    });
+    let mut transfer = Transfer::new(future.as_mut());
+    // the first poll starts the DMA transfer
+    transfer.poll();
+    if let Some((disp, band)) = band {
+        IN_FLIGHT = &mut transfer;
+        render(disp, band);
+        IN_FLIGHT = null_mut();
+    }
+    if !transfer.poll() {
+        future.await;
+    }
+}
+
+/// Renders `band` into the free draw buffer, `flush_cb` is called once
+unsafe fn render(disp: &mut lv_disp_t, band: lv_area_t) {
+    disp.inv_areas[0] = band;
+    disp.inv_area_joined[0] = 0;
+    disp.inv_p = 1;
+    _lv_disp_refr_timer(null_mut());
+}
+
+/// Merges areas that are cheaper to draw together, like `lv_refr_join_area()` of LVGL
+unsafe fn join_areas(areas: &mut [lv_area_t], joined: &mut [u8]) {
+    for into in 0..areas.len() {
+        if joined[into] != 0 {
+            continue;
+        }
+        for from in 0..areas.len() {
+            if from == into || joined[from] != 0 || !_lv_area_is_on(&areas[into], &areas[from]) {
+                continue;
+            }
+            let mut union = lv_area_t::default();
+            _lv_area_join(&mut union, &areas[into], &areas[from]);
+            // only if fewer pixels are drawn
+            if lv_area_get_size(&union) < lv_area_get_size(&areas[into]) + lv_area_get_size(&areas[from]) {
+                areas[into] = union;
+                joined[from] = 1;
+            }
+        }
     }
 }
 
+/// Splits `area` into bands that fit into one draw buffer, the same way LVGL does
+fn bands(area: &lv_area_t) -> impl Iterator<Item = lv_area_t> + '_ {
+    let width = (area.x2 - area.x1 + 1) as usize;
+    let rows = (COLOR_BUF_PIXELS / width).max(1) as lv_coord_t;
+    (area.y1..=area.y2).step_by(rows as usize).map(move |y1| lv_area_t {
+        y1,
+        y2: (y1 + rows - 1).min(area.y2),
+        ..*area
+    })
+}
+
 // void my_disp_flush( lv_disp_drv_t *disp_drv, const lv_area_t *area, lv_color_t *color_p )
 #[inline(always)]
 #[link_section = ".iram1"]
 unsafe extern "C" fn flush_cb(disp_drv_p: *mut lv_disp_drv_t, area_p: *const lv_area_t, color_p: *mut lv_color_t) {
-    if let Some(disp_drv) = disp_drv_p.as_mut() {
-        if let Some(area) = area_p.as_ref() {
-            let p1 = Point::new(area.x1 as i32, area.y1 as i32);
-            let p2 = Point::new(area.x2 as i32, area.y2 as i32);
-            let r = Rectangle::with_corners(p1, p2);
-            let w = r.size.width;
-            let h = r.size.height;
-            debug!("Flushing {} x {}", w, h);
-            if let Some(display) = (disp_drv.user_data as *mut DisplayDriver).as_mut() {
-                if !color_p.is_null() {
-                    let color_p = color_p as *const Rgb565;
-                    let colors_it = core::slice::from_raw_parts(color_p, (w * h) as usize).iter().cloned();
-                    display
-                        .fill_contiguous(&r, colors_it)
-                        .expect("Failed to fill contiguous color");
-                } else {
-                    warn!("color_p is null");
-                }
-            } else {
-                warn!("user_data is null");
-            }
-        } else {
-            warn!("area_p is null");
-        }
-    } else {
-        warn!("disp_drv_p is null");
+    let Some(area) = area_p.as_ref() else {
+        warn!("area_p is null");
+        lv_disp_flush_ready(disp_drv_p);
+        return;
+    };
+    if color_p.is_null() {
+        warn!("color_p is null");
+        lv_disp_flush_ready(disp_drv_p);
+        return;
+    }
+    let p1 = Point::new(area.x1 as i32, area.y1 as i32);
+    let p2 = Point::new(area.x2 as i32, area.y2 as i32);
+    let r = Rectangle::with_corners(p1, p2);
+    let len = (r.size.width * r.size.height) as usize;
+    debug!("Flushing {} x {}", r.size.width, r.size.height);
+
+    // the display expects the pixels big-endian
+    let colors = core::slice::from_raw_parts_mut(color_p.cast::<u16>(), len);
+    for color in colors.iter_mut() {
+        *color = color.swap_bytes();
+    }
+    *addr_of_mut!(PENDING) = Some(Flush {
+        disp_drv: disp_drv_p,
+        area: r,
+        pixels: color_p.cast::<u8>(),
+        len: len * 2,
+    });
+}
+
+/// Called by LVGL at the end of rendering a band while the other draw buffer is still being sent
+///
+/// LVGL may go on: the buffer is only rendered into again after `send_while_rendering` has awaited its
+/// transfer. A poll completes the transfer if the DMA is done already.
+#[link_section = ".iram1"]
+unsafe extern "C" fn wait_cb(disp_drv_p: *mut lv_disp_drv_t) {
+    if let Some(transfer) = IN_FLIGHT.as_mut() {
+        transfer.poll();
     }
     lv_disp_flush_ready(disp_drv_p);
-    debug!("Flush done");
 }
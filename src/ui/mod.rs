@@ -1,29 +1,140 @@
-use core::ffi::{c_char, c_void, CStr};
+use core::ffi::{c_char, CStr};
+use core::sync::atomic::Ordering;
 use embassy_time::{Duration, Instant, Timer};
-use heapless::String;
-use lvgl_rust_sys::{
-    lv_align_t, lv_disp_get_default, lv_init, lv_log_register_print_cb, lv_obj_set_style_pad_bottom,
-    lv_obj_set_style_pad_left, lv_obj_set_style_pad_right, lv_obj_set_style_pad_top, lv_scr_act, lv_text_align_t,
-    lv_timer_handler, LV_ALIGN_RIGHT_MID, LV_TEXT_ALIGN_RIGHT,
-};
-
-use self::lvgl::{Bar, Label, Meter, Widget};
-use self::lvgl_buffers::lvgl_disp_init;
-use crate::app::shared::{MAX_FIELD_CURRENT, MAX_FIELD_VOLTAGE, PROCESS_DATA, REGULATOR_MODE, RM_LEN};
+use heapless::{format, String};
+use lvgl_rust_sys::{lv_init, lv_log_register_print_cb, lv_timer_handler};
+
+use self::alarm::AlarmOverlay;
+use self::lvgl::{init_screen_style, set_night_theme, WidgetError};
+pub use self::lvgl_buffers::refresh;
+use self::lvgl_buffers::{lvgl_disp_init, take_screenshot};
+use self::overview::Overview;
+use self::settings::SettingsPage;
+use self::stats::StatsScreen;
+use self::table::TablePage;
+use self::trends::TrendsPage;
+use crate::app::alarm::{self as alarms, AlarmSource, Severity};
+use crate::app::backlight::{self, BacklightState};
+use crate::app::config::CONFIG;
+use crate::app::screenshot;
+use crate::app::settings::MENU;
+use crate::app::shared::{page, Fault, Page, PROCESS_DATA, SETPOINT};
+use crate::app::stats::STATS;
 use crate::board::driver::display::DisplayDriver;
-use crate::ui::lvgl::WidgetError;
 
+mod alarm;
 mod lvgl;
 mod lvgl_buffers;
+mod overview;
+mod settings;
+mod stats;
+mod table;
+mod trends;
 
-#[allow(unused)]
-#[derive(Debug, Default)]
-struct Widgets<'a> {
-    meter: Meter<'a>,
-    field_voltage_bar: Bar,
-    field_current_bar: Bar,
-    field_voltage_label: Label<'a>,
-    field_current_label: Label<'a>,
+const FAULT_COUNT: usize = Fault::COUNT as usize;
+
+/// interval of reading the process data into the widgets, rendering is driven by LVGL's timers
+const UPDATE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Title of `page` with its position, e.g. "Battery 2/6"
+fn page_title(page: Page) -> Result<String<20>, WidgetError> {
+    Ok(format!(20; "{} {}/{}", page.title(), page as u8 + 1, Page::COUNT)?)
+}
+
+/// All pages, only the one shown is updated
+struct Pages<'a> {
+    overview: Overview<'a>,
+    battery: TablePage<'a, 4>,
+    alternator: TablePage<'a, 8>,
+    trends: TrendsPage<'a>,
+    statistics: StatsScreen<'a>,
+    faults: TablePage<'a, FAULT_COUNT>,
+    settings: SettingsPage<'a>,
+}
+
+impl<'a> Pages<'a> {
+    fn create() -> Result<Self, WidgetError> {
+        Ok(Self {
+            overview: Overview::create()?,
+            battery: TablePage::new(
+                Page::Battery,
+                ["Voltage V", "Current A", "SoC %", "Charge limit A"],
+            )?,
+            alternator: TablePage::new(
+                Page::Alternator,
+                [
+                    "Speed rpm",
+                    "Current A",
+                    "Field A",
+                    "Field V",
+                    "Temp C",
+                    "PPS temp C",
+                    "Limited by",
+                    "Target %",
+                ],
+            )?,
+            trends: TrendsPage::new()?,
+            statistics: StatsScreen::new()?,
+            faults: TablePage::new(Page::Faults, core::array::from_fn(|i| Self::fault(i).name()))?,
+            settings: SettingsPage::new()?,
+        })
+    }
+
+    fn fault(index: usize) -> Fault {
+        num_traits::FromPrimitive::from_usize(index).unwrap_or(Fault::Pps)
+    }
+
+    fn show(&self, page: Page) {
+        match page {
+            Page::Overview => self.overview.show(),
+            Page::Battery => self.battery.show(),
+            Page::Alternator => self.alternator.show(),
+            Page::Trends => self.trends.show(),
+            Page::Statistics => self.statistics.show(),
+            Page::Faults => self.faults.show(),
+            Page::Settings => self.settings.show(),
+        }
+    }
+
+    fn update(&mut self, page: Page) -> Result<(), WidgetError> {
+        let pd = &PROCESS_DATA;
+        match page {
+            Page::Overview => self.overview.update()?,
+            Page::Battery => {
+                let charge_limit = CONFIG.lock(|c| c.borrow().bat_current_limit());
+                let t = &mut self.battery;
+                t.set_value(0, pd.bat_voltage.load(Ordering::Relaxed), 2)?;
+                t.set_value(1, pd.bat_current.load(Ordering::Relaxed), 1)?;
+                t.set_value(2, pd.bat_soc.load(Ordering::Relaxed), 0)?;
+                t.set_value(3, charge_limit, 0)?;
+            }
+            Page::Alternator => {
+                let t = &mut self.alternator;
+                t.set_value(0, pd.rpm.load(Ordering::Relaxed), 0)?;
+                t.set_value(1, pd.alt_current.load(Ordering::Relaxed), 1)?;
+                t.set_value(2, pd.field_current.load(Ordering::Relaxed), 2)?;
+                t.set_value(3, pd.field_voltage.load(Ordering::Relaxed), 1)?;
+                t.set_value(4, pd.temperature.load(Ordering::Relaxed), 0)?;
+                t.set_value(5, pd.pps_temperature.load(Ordering::Relaxed), 0)?;
+                t.set_text(6, SETPOINT.active_limit().name())?;
+                t.set_value(7, pd.target_factor.load(Ordering::Relaxed) * 100., 0)?;
+            }
+            Page::Trends => self.trends.update()?,
+            // copied, so the critical section does not last for the whole update
+            Page::Statistics => self.statistics.update(&STATS.lock(|s| s.borrow().clone()))?,
+            Page::Faults => {
+                for row in 0..FAULT_COUNT {
+                    let active = Self::fault(row).is_active();
+                    self.faults.set_text(row, if active { "ACTIVE" } else { "ok" })?;
+                }
+            }
+            Page::Settings => {
+                let config = CONFIG.lock(|c| c.borrow().clone());
+                self.settings.update(&MENU.lock(|m| m.borrow().clone()), &config)?
+            }
+        }
+        Ok(())
+    }
 }
 
 #[no_mangle]
@@ -39,127 +150,105 @@ pub extern "C" fn get_tick_ms() -> u32 {
     ms
 }
 
-impl<'a> Widgets<'a> {
-    fn create() -> Result<Self, WidgetError> {
+/// LVGL with all pages and the alarm overlay
+///
+/// The host simulator in `tools/ui-sim` renders the same widgets, fed by fake process data.
+pub struct Ui<'a> {
+    pages: Pages<'a>,
+    alarm_overlay: AlarmOverlay<'a>,
+    shown: Page,
+    night: bool,
+}
+
+impl<'a> Ui<'a> {
+    /// Initializes LVGL and creates the widgets of all pages
+    pub fn create() -> Result<Self, WidgetError> {
         unsafe {
-            lv_obj_set_style_pad_top(lv_scr_act(), 6, 0);
-            lv_obj_set_style_pad_bottom(lv_scr_act(), 6, 0);
-            lv_obj_set_style_pad_left(lv_scr_act(), 12, 0);
-            lv_obj_set_style_pad_right(lv_scr_act(), 12, 0);
-        };
-        let screen = unsafe { lv_scr_act() };
-        assert!(!screen.is_null());
-
-        // Create and configure the meter
-        let mut meter = Meter::new(screen)?;
-        meter.set_value(0.)?;
-
-        // Create bars for field voltage and current
-        let field_voltage_bar = Bar::new(screen)?.width(12).height(228).range(0., MAX_FIELD_VOLTAGE);
-
-        let field_current_bar =
-            Bar::new(screen)?
-                .width(12)
-                .height(228)
-                .range(0., MAX_FIELD_CURRENT)
-                .align(LV_ALIGN_RIGHT_MID as lv_align_t, 0, 0);
-
-        // Create labels for field voltage and current
-        let field_voltage_label = Label::new(screen, "V")?;
-        field_voltage_label.x(18).text("1.3V")?;
-
-        let field_current_label = Label::new(screen, "A")?;
-        field_current_label
-            .x(228)
-            .width(50)
-            .text("-0.0A")?
-            .text_align(LV_TEXT_ALIGN_RIGHT as lv_text_align_t);
-
-        Ok(Widgets {
-            meter,
-            field_voltage_bar,
-            field_current_bar,
-            field_voltage_label,
-            field_current_label,
+            lv_init();
+            lv_log_register_print_cb(Some(lvgl_log_print)); /* register print function for debugging */
+            lvgl_disp_init();
+        }
+        init_screen_style();
+        let night = CONFIG.lock(|c| c.borrow().night_theme);
+        set_night_theme(night);
+        let pages = Pages::create()?;
+        pages.show(Page::Overview);
+        Ok(Self {
+            pages,
+            alarm_overlay: AlarmOverlay::new()?,
+            shown: Page::Overview,
+            night,
         })
     }
 
-    pub fn update(&mut self) -> Result<(), lvgl::WidgetError> {
-        let current = PROCESS_DATA.bat_current.load(core::sync::atomic::Ordering::Relaxed);
-        let field_voltage = PROCESS_DATA.field_voltage.load(core::sync::atomic::Ordering::Relaxed);
-        let field_current = PROCESS_DATA.field_current.load(core::sync::atomic::Ordering::Relaxed);
-        let rpm = PROCESS_DATA.rpm.load(core::sync::atomic::Ordering::Relaxed);
-
-        if current.is_finite() {
-            self.meter.set_value(current)?;
-        }
-        if field_voltage.is_finite() {
-            self.field_voltage_bar.set_value(field_voltage)?;
-            self.field_voltage_label.set_value(field_voltage)?;
+    /// Shows the selected page and reads the process data into its widgets
+    ///
+    /// Widgets are only invalidated if their values changed.
+    pub fn update(&mut self, alarm: Option<&'static AlarmSource>) {
+        let night_theme = CONFIG.lock(|c| c.borrow().night_theme);
+        if night_theme != self.night {
+            set_night_theme(night_theme);
+            self.night = night_theme;
         }
-        if field_current.is_finite() {
-            self.field_current_bar.set_value(field_current)?;
-            self.field_current_label.set_value(field_current)?;
+        let selected = page();
+        if selected != self.shown {
+            self.pages.show(selected);
+            self.shown = selected;
         }
-        if rpm.is_finite() {
-            self.meter.set_rpm(rpm)?;
-        }
-
-        REGULATOR_MODE.lock(|rm| {
-            let rm: &String<RM_LEN> = &rm.borrow();
-            self.meter.set_state(rm).ok();
-        });
-
-        Ok(())
+        self.pages
+            .update(self.shown)
+            .unwrap_or_else(|e| warn!("Failed to update widgets: {:?}", e));
+        self.alarm_overlay
+            .update(alarm)
+            .unwrap_or_else(|e| warn!("Failed to update widgets: {:?}", e));
     }
 }
 
-// async fn lvgl_refresh_task(disp_refr: *mut lv_disp_t) {
-//     // this async fn replaces LVGL's central refresh routine `_lv_disp_refr_timer()`;
-//     unsafe {
-//         let disp_refr = disp_refr.as_mut().unwrap();
-//         lv_obj_update_layout(disp_refr.act_scr);
-//         yield_now().await;
-//         lv_obj_update_layout(disp_refr.top_layer);
-//         yield_now().await;
-//         lv_obj_update_layout(disp_refr.sys_layer);
-//         yield_now().await;
-//
-//         lv_refr_join_area();
-//         yield_now().await;
-//         refr_sync_areas();
-//         yield_now().await;
-//         refr_invalid_areas();
-//     }
-//
-// }
-
 pub async fn ui_loop(mut display_driver: DisplayDriver) {
-    unsafe {
-        // initialize LVGL
-        lv_init();
-        lv_log_register_print_cb(Some(lvgl_log_print)); /* register print function for debugging */
-        lvgl_disp_init(&display_driver as *const DisplayDriver as *mut c_void);
-
-        // Create the widgets
-        let Ok(mut widgets) = Widgets::create() else
-        {
-            warn!("Could not create LVGL widgets, disabling UI");
-            return;
-        };
-
-        // UI loop
-        lv_timer_handler(); // first rendering takes a long time, so do it once befor turing on the backlight
-        display_driver.bl_on();
-        let _disp = lv_disp_get_default();
-        loop {
-            widgets
-                .update()
-                .unwrap_or_else(|e| warn!("Failed to update widgets: {:?}", e));
-            lv_timer_handler();
-            //            lv_refr_now(disp);
-            //            lvgl_refresh_task(disp).await;
-            Timer::after(Duration::from_millis(100)).await;
+    let Ok(mut ui) = Ui::create() else {
+        warn!("Could not create LVGL widgets, disabling UI");
+        return;
+    };
+
+    // first rendering takes a long time, so do it once befor turing on the backlight
+    refresh(&mut display_driver).await;
+    let mut next_update = Instant::now();
+    let mut display_on = true;
+    loop {
+        if Instant::now() >= next_update {
+            next_update = Instant::now() + UPDATE_INTERVAL;
+            let current_alarm = alarms::current();
+            if current_alarm.is_some() {
+                backlight::wake();
+            }
+            let (state, brightness) = CONFIG.lock(|c| {
+                let config = c.borrow();
+                let state = backlight::state(&config);
+                (state, state.brightness(&config))
+            });
+            // flash the backlight at 1Hz while a warning or critical alarm is not acknowledged
+            let flash = current_alarm.is_some_and(|a| a.severity >= Severity::Warning);
+            if flash && Instant::now().as_millis() % 1000 >= 500 {
+                display_driver.set_brightness(0);
+            } else {
+                display_driver.set_brightness(brightness);
+            }
+            display_on = state != BacklightState::Off;
+            if display_on {
+                ui.update(current_alarm);
+            }
+        }
+
+        // nothing to see while the display is off, the pending changes are rendered after waking up
+        let mut next_due = next_update;
+        if display_on {
+            let lvgl_due = Instant::now() + Duration::from_millis(unsafe { lv_timer_handler() } as u64);
+            refresh(&mut display_driver).await;
+            if screenshot::take_request() {
+                take_screenshot(&mut display_driver).await;
+            }
+            next_due = next_due.min(lvgl_due);
         }
+        Timer::at(next_due).await;
     }
 }
@@ -0,0 +1,146 @@
+use heapless::{format, String};
+use libm::ceilf;
+use lvgl_rust_sys::{
+    lv_align_t, lv_obj_t, lv_scr_load, lv_text_align_t, LV_ALIGN_BOTTOM_LEFT, LV_ALIGN_BOTTOM_RIGHT,
+    LV_ALIGN_RIGHT_MID, LV_TEXT_ALIGN_RIGHT,
+};
+
+use super::lvgl::{new_screen, Bar, Label, Meter, MeterScale, Widget, WidgetError};
+use crate::app::config::{Config, CONFIG};
+use crate::app::maintenance;
+use crate::app::shared::{
+    MAX_FIELD_CURRENT, MAX_FIELD_VOLTAGE, PROCESS_DATA, REGULATOR_MODE, RM_LEN, RPM_MAX, SETPOINT,
+};
+
+/// Meter ranges for the configured alternator
+///
+/// The current scale ends at the rated current, rounded up to 10A so the labels are whole numbers. The
+/// red band starts at the alternator current limit, the yellow band at 80% of it. The grey band of the
+/// RPM scale ends at `rpm_min`, below which there is no charging.
+fn meter_scale(config: &Config) -> MeterScale {
+    let max_current = (ceilf(config.alt_rated_current / 10.) * 10.) as i32;
+    let limit_current = (config.effective_alt_current_limit() as i32).min(max_current);
+    MeterScale {
+        max_current,
+        warning_current: limit_current * 4 / 5,
+        limit_current,
+        max_rpm: (RPM_MAX / 100) as i32,
+        min_rpm: (config.rpm_min / 100.) as i32,
+    }
+}
+
+/// Main page with the current meter and the field bars
+#[allow(unused)]
+#[derive(Debug, Default)]
+pub struct Overview<'a> {
+    screen: *mut lv_obj_t,
+    meter: Meter<'a>,
+    /// ranges the meter currently shows
+    scale: MeterScale,
+    field_voltage_bar: Bar,
+    field_current_bar: Bar,
+    field_voltage_label: Label<'a>,
+    field_current_label: Label<'a>,
+    card_label: Label<'a>,
+    maintenance_label: Label<'a>,
+}
+
+impl<'a> Overview<'a> {
+    pub fn create() -> Result<Self, WidgetError> {
+        let screen = new_screen()?;
+
+        // Create and configure the meter
+        let scale = CONFIG.lock(|c| meter_scale(&c.borrow()));
+        let mut meter = Meter::new(screen, &scale)?;
+        meter.set_value(0.)?;
+
+        // Create bars for field voltage and current
+        let field_voltage_bar = Bar::new(screen)?.width(12).height(228).range(0., MAX_FIELD_VOLTAGE);
+
+        let field_current_bar =
+            Bar::new(screen)?
+                .width(12)
+                .height(228)
+                .range(0., MAX_FIELD_CURRENT)
+                .align(LV_ALIGN_RIGHT_MID as lv_align_t, 0, 0);
+
+        // Create labels for field voltage and current
+        let field_voltage_label = Label::new(screen, "V")?;
+        field_voltage_label.x(18).text("1.3V")?;
+
+        let field_current_label = Label::new(screen, "A")?;
+        field_current_label
+            .x(228)
+            .width(50)
+            .text("-0.0A")?
+            .text_align(LV_TEXT_ALIGN_RIGHT as lv_text_align_t);
+
+        // SD card state of the data logger
+        let card_label = Label::new(screen, "")?;
+        card_label.align(LV_ALIGN_BOTTOM_LEFT as lv_align_t, 18, 0);
+
+        // maintenance reminder
+        let maintenance_label = Label::new(screen, "")?;
+        maintenance_label.align(LV_ALIGN_BOTTOM_RIGHT as lv_align_t, -18, 0);
+
+        Ok(Overview {
+            screen,
+            meter,
+            scale,
+            field_voltage_bar,
+            field_current_bar,
+            field_voltage_label,
+            field_current_label,
+            card_label,
+            maintenance_label,
+        })
+    }
+
+    pub fn update(&mut self) -> Result<(), WidgetError> {
+        let current = PROCESS_DATA.bat_current.load(core::sync::atomic::Ordering::Relaxed);
+        let field_voltage = PROCESS_DATA.field_voltage.load(core::sync::atomic::Ordering::Relaxed);
+        let field_current = PROCESS_DATA.field_current.load(core::sync::atomic::Ordering::Relaxed);
+        let rpm = PROCESS_DATA.rpm.load(core::sync::atomic::Ordering::Relaxed);
+
+        // rebuilt when the alternator settings have been changed
+        let scale = CONFIG.lock(|c| meter_scale(&c.borrow()));
+        if scale != self.scale {
+            self.meter.set_scale(&scale);
+            self.scale = scale;
+        }
+        // follows the night theme
+        self.meter.update_tick_color();
+
+        if current.is_finite() {
+            self.meter.set_value(current)?;
+        }
+        if field_voltage.is_finite() {
+            self.field_voltage_bar.set_value(field_voltage)?;
+            self.field_voltage_label.set_value(field_voltage)?;
+        }
+        if field_current.is_finite() {
+            self.field_current_bar.set_value(field_current)?;
+            self.field_current_label.set_value(field_current)?;
+        }
+        if rpm.is_finite() {
+            self.meter.set_rpm(rpm)?;
+        }
+
+        REGULATOR_MODE.lock(|rm| {
+            let rm: &String<RM_LEN> = &rm.borrow();
+            self.meter.set_state(rm).ok();
+        });
+        self.meter.set_limit(SETPOINT.active_limit().name())?;
+        self.card_label.text(PROCESS_DATA.card_state().name())?;
+        match maintenance::first_due() {
+            Some(item) => self.maintenance_label.text(&format!(20; "{} due", item.name())?)?,
+            None => self.maintenance_label.text("")?,
+        };
+
+        Ok(())
+    }
+
+    pub fn show(&self) {
+        unsafe { lv_scr_load(self.screen) };
+    }
+}
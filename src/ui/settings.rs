@@ -0,0 +1,58 @@
+use heapless::{format, String};
+use lvgl_rust_sys::{lv_align_t, LV_ALIGN_BOTTOM_LEFT};
+
+use super::lvgl::{Label, Widget, WidgetError};
+use super::table::TablePage;
+use crate::app::config::Config;
+use crate::app::settings::{SettingsMenu, SETTINGS};
+use crate::app::shared::Page;
+
+/// settings shown at once, the list is scrolled page by page
+const VISIBLE: usize = 8;
+
+/// Settings menu, see `app::settings`
+#[derive(Debug)]
+pub struct SettingsPage<'a> {
+    table: TablePage<'a, VISIBLE>,
+    hint: Label<'a>,
+}
+
+impl<'a> SettingsPage<'a> {
+    pub fn new() -> Result<Self, WidgetError> {
+        let table = TablePage::new(Page::Settings, [""; VISIBLE])?;
+        let hint = Label::new(table.screen(), "")?;
+        hint.align(LV_ALIGN_BOTTOM_LEFT as lv_align_t, 0, 0);
+        Ok(Self { table, hint })
+    }
+
+    pub fn show(&self) {
+        self.table.show();
+    }
+
+    pub fn update(&mut self, menu: &SettingsMenu, config: &Config) -> Result<(), WidgetError> {
+        let first = menu.cursor / VISIBLE * VISIBLE;
+        for row in 0..VISIBLE {
+            let Some(setting) = SETTINGS.get(first + row) else {
+                self.table.set_name(row, "")?;
+                self.table.set_text(row, "")?;
+                continue;
+            };
+            let selected = first + row == menu.cursor;
+            let marker = if selected { ">" } else { " " };
+            self.table.set_name(row, &format!(20; "{}{}", marker, setting.name)?)?;
+            let value: String<20> = match menu.edit {
+                Some(edited) if selected => format!(20; "[{}]", setting.display(edited).as_str())
+                    .unwrap_or_else(|_| setting.display(edited)),
+                _ => setting.display((setting.get)(config)),
+            };
+            self.table.set_text(row, &value)?;
+        }
+        let hint = match (menu.edit, menu.message) {
+            (Some(_), _) => "OkLong save",
+            (None, "") => "Off: OkLong edit",
+            (None, message) => message,
+        };
+        self.hint.text(hint)?;
+        Ok(())
+    }
+}
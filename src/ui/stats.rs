@@ -0,0 +1,105 @@
+use lvgl_rust_sys::{lv_align_t, lv_obj_t, lv_scr_load, lv_text_align_t, LV_ALIGN_TOP_LEFT, LV_TEXT_ALIGN_RIGHT};
+
+use super::lvgl::{new_screen, Label, Widget, WidgetError};
+use super::page_title;
+use crate::app::shared::{ActiveLimit, Page};
+use crate::app::stats::{Statistics, Totals};
+
+const ROWS: usize = 10;
+const ROW_HEIGHT: i32 = 20;
+const COLUMN_WIDTH: i32 = 90;
+
+const ROW_NAMES: [&str; ROWS] = [
+    "Alt Ah",
+    "Alt kWh",
+    "Bat Ah",
+    "Bat kWh",
+    "Engine h",
+    "Bulk h",
+    "Absorb h",
+    "Max Alt C",
+    "Max Alt A",
+    "Max Bat A",
+];
+
+/// Values of the rows in `ROW_NAMES`
+fn row_values(t: &Totals) -> [f32; ROWS] {
+    let hours = |s: f64| (s / 3600.) as f32;
+    let bulk_s = t.limit_s[ActiveLimit::BatCurrent as usize] + t.limit_s[ActiveLimit::AltCurrent as usize];
+    [
+        t.alt_ah as f32,
+        (t.alt_wh / 1000.) as f32,
+        t.bat_ah as f32,
+        (t.bat_wh / 1000.) as f32,
+        hours(t.engine_s),
+        hours(bulk_s),
+        hours(t.limit_s[ActiveLimit::Voltage as usize]),
+        t.max_alt_temperature,
+        t.max_alt_current,
+        t.max_bat_current,
+    ]
+}
+
+/// Daily and lifetime statistics, a table with one row per value
+#[derive(Debug)]
+pub struct StatsScreen<'a> {
+    screen: *mut lv_obj_t,
+    /// "Since boot" while the day is not known
+    today_header: Label<'a>,
+    today: [Label<'a>; ROWS],
+    lifetime: [Label<'a>; ROWS],
+}
+
+impl<'a> StatsScreen<'a> {
+    pub fn new() -> Result<Self, WidgetError> {
+        let screen = new_screen()?;
+
+        let top_left = LV_ALIGN_TOP_LEFT as lv_align_t;
+        Label::new(screen, "")?.text(&page_title(Page::Statistics)?)?;
+        let today_header = Self::value_label(screen, 0)?;
+        Self::value_label(screen, 1)?.text("Total")?;
+
+        let mut today: [Label<'a>; ROWS] = Default::default();
+        let mut lifetime: [Label<'a>; ROWS] = Default::default();
+        for (row, name) in ROW_NAMES.iter().enumerate() {
+            let y = (row as i32 + 1) * ROW_HEIGHT;
+            Label::new(screen, "")?.text(name)?.align(top_left, 0, y);
+            today[row] = Self::value_label(screen, 0)?;
+            today[row].align(top_left, 110, y);
+            lifetime[row] = Self::value_label(screen, 1)?;
+            lifetime[row].align(top_left, 110 + COLUMN_WIDTH + 10, y);
+        }
+
+        Ok(Self {
+            screen,
+            today_header,
+            today,
+            lifetime,
+        })
+    }
+
+    /// right aligned label of value column `column`, in the header row until aligned otherwise
+    fn value_label(screen: *mut lv_obj_t, column: i32) -> Result<Label<'a>, WidgetError> {
+        let label = Label::new(screen, "")?;
+        label
+            .width(COLUMN_WIDTH)
+            .align(LV_ALIGN_TOP_LEFT as lv_align_t, 110 + column * (COLUMN_WIDTH + 10), 0);
+        label.text_align(LV_TEXT_ALIGN_RIGHT as lv_text_align_t);
+        Ok(label)
+    }
+
+    pub fn show(&self) {
+        unsafe { lv_scr_load(self.screen) };
+    }
+
+    pub fn update(&mut self, stats: &Statistics) -> Result<(), WidgetError> {
+        self.today_header.text(if stats.day == 0 { "Since boot" } else { "Today" })?;
+        for (label, value) in self.today.iter_mut().zip(row_values(&stats.today)) {
+            label.set_value(value)?;
+        }
+        for (label, value) in self.lifetime.iter_mut().zip(row_values(&stats.lifetime)) {
+            label.set_value(value)?;
+        }
+        Ok(())
+    }
+}
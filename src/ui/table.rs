@@ -0,0 +1,72 @@
+use lvgl_rust_sys::{lv_align_t, lv_obj_t, lv_scr_load, lv_text_align_t, LV_ALIGN_TOP_LEFT, LV_TEXT_ALIGN_RIGHT};
+
+use super::lvgl::{new_screen, Label, Widget, WidgetError};
+use super::page_title;
+use crate::app::shared::Page;
+
+const ROW_HEIGHT: i32 = 22;
+const VALUE_X: i32 = 140;
+const VALUE_WIDTH: i32 = 140;
+
+/// Page with a title and `N` rows of a name and a right aligned value
+#[derive(Debug)]
+pub struct TablePage<'a, const N: usize> {
+    screen: *mut lv_obj_t,
+    names: [Label<'a>; N],
+    values: [Label<'a>; N],
+}
+
+impl<'a, const N: usize> TablePage<'a, N> {
+    pub fn new(page: Page, names: [&str; N]) -> Result<Self, WidgetError> {
+        let screen = new_screen()?;
+
+        let top_left = LV_ALIGN_TOP_LEFT as lv_align_t;
+        Label::new(screen, "")?.text(&page_title(page)?)?;
+
+        let mut labels: [Label<'a>; N] = core::array::from_fn(|_| Label::default());
+        let mut values: [Label<'a>; N] = core::array::from_fn(|_| Label::default());
+        for (row, name) in names.iter().enumerate() {
+            let y = (row as i32 + 1) * ROW_HEIGHT;
+            labels[row] = Label::new(screen, "")?;
+            labels[row].text(name)?.align(top_left, 0, y);
+            values[row] = Label::new(screen, "")?;
+            values[row]
+                .width(VALUE_WIDTH)
+                .align(top_left, VALUE_X, y)
+                .text_align(LV_TEXT_ALIGN_RIGHT as lv_text_align_t);
+        }
+
+        Ok(Self {
+            screen,
+            names: labels,
+            values,
+        })
+    }
+
+    pub fn screen(&self) -> *mut lv_obj_t {
+        self.screen
+    }
+
+    pub fn show(&self) {
+        unsafe { lv_scr_load(self.screen) };
+    }
+
+    /// Sets the value of `row`, NaN values are shown as `-`
+    pub fn set_value(&mut self, row: usize, value: f32, precision: usize) -> Result<(), WidgetError> {
+        if value.is_finite() {
+            self.set_text(row, &heapless::format!(20; "{:.*}", precision, value)?)
+        } else {
+            self.set_text(row, "-")
+        }
+    }
+
+    pub fn set_text(&mut self, row: usize, text: &str) -> Result<(), WidgetError> {
+        self.values[row].text(text)?;
+        Ok(())
+    }
+
+    pub fn set_name(&mut self, row: usize, name: &str) -> Result<(), WidgetError> {
+        self.names[row].text(name)?;
+        Ok(())
+    }
+}
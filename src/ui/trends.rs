@@ -0,0 +1,128 @@
+use lvgl_rust_sys::{
+    lv_align_t, lv_obj_t, lv_scr_load, lv_text_align_t, LV_ALIGN_TOP_LEFT, LV_ALIGN_TOP_RIGHT, LV_TEXT_ALIGN_RIGHT,
+};
+
+use super::lvgl::{new_screen, Chart, Label, Widget, WidgetError};
+use super::page_title;
+use crate::app::config::CONFIG;
+use crate::app::history::{self, Trend, TrendWindow, HISTORY, POINTS};
+use crate::app::shared::{Page, MAX_FIELD_CURRENT, RPM_MAX};
+
+const CHART_WIDTH: i32 = 296;
+const CHART_HEIGHT: i32 = 80;
+/// vertical distance of the two charts, including the legend
+const CHART_PITCH: i32 = 102;
+
+const PRIMARY_COLOR: u32 = 0xd00000;
+const SECONDARY_COLOR: u32 = 0x0050d0;
+
+/// Trends of battery voltage and current, field current and RPM
+#[derive(Debug)]
+pub struct TrendsPage<'a> {
+    screen: *mut lv_obj_t,
+    window_label: Label<'a>,
+    battery: Chart,
+    alternator: Chart,
+    /// rated alternator current of the battery current range
+    rated_current: f32,
+    /// window and history revision shown, the charts are only redrawn when they change
+    shown: Option<(TrendWindow, u32)>,
+}
+
+impl<'a> TrendsPage<'a> {
+    pub fn new() -> Result<Self, WidgetError> {
+        let screen = new_screen()?;
+        let top_left = LV_ALIGN_TOP_LEFT as lv_align_t;
+
+        Label::new(screen, "")?.text(&page_title(Page::Trends)?)?;
+        let window_label = Label::new(screen, "")?;
+        window_label
+            .width(100)
+            .align(LV_ALIGN_TOP_RIGHT as lv_align_t, 0, 0)
+            .text_align(LV_TEXT_ALIGN_RIGHT as lv_text_align_t);
+
+        let rated_current = CONFIG.lock(|c| c.borrow().alt_rated_current);
+        let (min_current, max_current) = Self::current_range(rated_current);
+        let battery = Self::chart(screen, 0)?
+            .series(0, PRIMARY_COLOR, 10., 16., 100.)?
+            .series(1, SECONDARY_COLOR, min_current, max_current, 10.)?;
+        Self::legend(screen, 0, "Bat V 10-16", "Bat A")?;
+
+        let alternator = Self::chart(screen, 1)?
+            .series(0, PRIMARY_COLOR, 0., MAX_FIELD_CURRENT, 100.)?
+            .series(1, SECONDARY_COLOR, 0., RPM_MAX as f32, 1.)?;
+        Self::legend(screen, 1, "Field A", "RPM")?;
+
+        Ok(Self {
+            screen,
+            window_label,
+            battery,
+            alternator,
+            rated_current,
+            shown: None,
+        })
+    }
+
+    /// range of the battery current, discharge currents are shown down to a quarter of the charge range
+    fn current_range(rated_current: f32) -> (f32, f32) {
+        (-rated_current / 4., rated_current)
+    }
+
+    fn chart(screen: *mut lv_obj_t, index: i32) -> Result<Chart, WidgetError> {
+        Ok(Chart::new(screen, POINTS as u16)?.width(CHART_WIDTH).height(CHART_HEIGHT).align(
+            LV_ALIGN_TOP_LEFT as lv_align_t,
+            0,
+            20 + index * CHART_PITCH,
+        ))
+    }
+
+    /// names of the series below chart `index`, in the colors of the series
+    fn legend(screen: *mut lv_obj_t, index: i32, primary: &str, secondary: &str) -> Result<(), WidgetError> {
+        let y = 20 + index * CHART_PITCH + CHART_HEIGHT + 2;
+        Label::new(screen, "")?
+            .text(primary)?
+            .color(PRIMARY_COLOR)
+            .align(LV_ALIGN_TOP_LEFT as lv_align_t, 0, y);
+        Label::new(screen, "")?
+            .text(secondary)?
+            .color(SECONDARY_COLOR)
+            .width(100)
+            .align(LV_ALIGN_TOP_RIGHT as lv_align_t, 0, y)
+            .text_align(LV_TEXT_ALIGN_RIGHT as lv_text_align_t);
+        Ok(())
+    }
+
+    pub fn show(&self) {
+        unsafe { lv_scr_load(self.screen) };
+    }
+
+    pub fn update(&mut self) -> Result<(), WidgetError> {
+        // follows a change of the alternator settings
+        let rated_current = CONFIG.lock(|c| c.borrow().alt_rated_current);
+        if rated_current != self.rated_current {
+            let (min_current, max_current) = Self::current_range(rated_current);
+            self.battery.set_range(1, min_current, max_current);
+            self.rated_current = rated_current;
+        }
+
+        let window = history::window();
+        let revision = HISTORY.lock(|h| h.borrow().revision());
+        if self.shown == Some((window, revision)) {
+            return Ok(());
+        }
+        self.window_label.text(window.name())?;
+
+        let mut points = [0.; POINTS];
+        for (chart, trends) in [
+            (&mut self.battery, [Trend::BatVoltage, Trend::BatCurrent]),
+            (&mut self.alternator, [Trend::FieldCurrent, Trend::Rpm]),
+        ] {
+            for (axis, trend) in trends.into_iter().enumerate() {
+                HISTORY.lock(|h| h.borrow().series(window, trend, &mut points));
+                chart.set_points(axis, &points)?;
+            }
+        }
+        self.shown = Some((window, revision));
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//! Checks binary data logs of the alternator regulator and converts them to CSV or Parquet
+//!
+//! Damaged records are skipped and reported on stderr, the conversion continues with the next valid
+//! record. The CSV output has the same layout as the CSV logs written by the regulator.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use binlog::{Corrupt, Header, Record};
+use clap::{Parser, Subcommand};
+
+#[cfg(feature = "parquet")]
+mod parquet;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Validates the header and record checksums
+    Check {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Converts a log file to CSV
+    Csv {
+        file: PathBuf,
+        /// output file, stdout if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Converts a log file to Parquet
+    #[cfg(feature = "parquet")]
+    Parquet {
+        file: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+/// Wall clock time of a record in Unix ms, if the regulator knew the time when the file was started
+fn record_time(header: &Header, record: &Record) -> Option<i64> {
+    header.boot_unix_ms.map(|boot| (boot + record.uptime_ms as u64) as i64)
+}
+
+fn report(path: &Path, corrupt: &Corrupt) {
+    eprintln!(
+        "{}: skipped {} bytes at offset {}: {}",
+        path.display(),
+        corrupt.len,
+        corrupt.offset,
+        corrupt.error
+    );
+}
+
+/// Returns the number of valid records and the number of damaged regions
+fn check(path: &Path) -> Result<(usize, usize), Box<dyn Error>> {
+    let data = std::fs::read(path)?;
+    let header = Header::parse(&data)?;
+    let (mut valid, mut corrupt) = (0, 0);
+    for record in header.records(&data) {
+        match record {
+            Ok(_) => valid += 1,
+            Err(c) => {
+                report(path, &c);
+                corrupt += 1;
+            }
+        }
+    }
+    Ok((valid, corrupt))
+}
+
+fn csv(path: &Path, out: impl Write) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(path)?;
+    let header = Header::parse(&data)?;
+
+    let mut out = BufWriter::new(out);
+    write!(out, "Timestamp;Uptime")?;
+    for name in header.names() {
+        write!(out, ";{}", name)?;
+    }
+    writeln!(out)?;
+
+    for record in header.records(&data) {
+        let record = match record {
+            Ok(record) => record,
+            Err(corrupt) => {
+                report(path, &corrupt);
+                continue;
+            }
+        };
+        if let Some(time) = record_time(&header, &record).and_then(chrono::DateTime::from_timestamp_millis) {
+            write!(out, "{}", time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))?;
+        }
+        write!(out, ";{}", record.uptime_ms)?;
+        for value in record.values() {
+            write!(out, ";{}", value)?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<bool, Box<dyn Error>> {
+    match cli.command {
+        Command::Check { files } => {
+            let mut ok = true;
+            for path in files {
+                match check(&path) {
+                    Ok((valid, 0)) => println!("{}: ok, {} records", path.display(), valid),
+                    Ok((valid, corrupt)) => {
+                        println!("{}: {} records, {} damaged regions", path.display(), valid, corrupt);
+                        ok = false;
+                    }
+                    Err(err) => {
+                        println!("{}: {}", path.display(), err);
+                        ok = false;
+                    }
+                }
+            }
+            Ok(ok)
+        }
+        Command::Csv { file, output } => {
+            match output {
+                Some(output) => csv(&file, File::create(output)?)?,
+                None => csv(&file, io::stdout().lock())?,
+            }
+            Ok(true)
+        }
+        #[cfg(feature = "parquet")]
+        Command::Parquet { file, output } => {
+            parquet::convert(&file, File::create(output)?)?;
+            Ok(true)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use binlog::Header;
+use parquet::basic::{LogicalType, Repetition, TimeUnit, Type as PhysicalType};
+use parquet::data_type::{FloatType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::MilliSeconds;
+use parquet::schema::types::Type;
+
+use crate::{record_time, report};
+
+/// Converts a log file to a Parquet file with a single row group
+///
+/// The columns are `Timestamp` (only if the wall clock was known), `Uptime` and one float column per
+/// logged field.
+pub fn convert(path: &Path, out: File) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(path)?;
+    let header = Header::parse(&data)?;
+
+    let mut timestamps = Vec::new();
+    let mut uptimes = Vec::new();
+    let mut columns = vec![Vec::new(); header.field_count];
+    for record in header.records(&data) {
+        let record = match record {
+            Ok(record) => record,
+            Err(corrupt) => {
+                report(path, &corrupt);
+                continue;
+            }
+        };
+        timestamps.extend(record_time(&header, &record));
+        uptimes.push(record.uptime_ms as i64);
+        for (column, value) in columns.iter_mut().zip(record.values()) {
+            column.push(value);
+        }
+    }
+
+    let mut fields = Vec::new();
+    if header.boot_unix_ms.is_some() {
+        fields.push(Arc::new(
+            Type::primitive_type_builder("Timestamp", PhysicalType::INT64)
+                .with_repetition(Repetition::REQUIRED)
+                .with_logical_type(Some(LogicalType::Timestamp {
+                    is_adjusted_to_u_t_c: true,
+                    unit: TimeUnit::MILLIS(MilliSeconds {}),
+                }))
+                .build()?,
+        ));
+    }
+    fields.push(Arc::new(
+        Type::primitive_type_builder("Uptime", PhysicalType::INT64)
+            .with_repetition(Repetition::REQUIRED)
+            .build()?,
+    ));
+    for name in header.names() {
+        fields.push(Arc::new(
+            Type::primitive_type_builder(name, PhysicalType::FLOAT)
+                .with_repetition(Repetition::REQUIRED)
+                .build()?,
+        ));
+    }
+    let schema = Type::group_type_builder("binlog").with_fields(fields).build()?;
+
+    let mut writer = SerializedFileWriter::new(out, Arc::new(schema), Arc::new(WriterProperties::builder().build()))?;
+    let mut row_group = writer.next_row_group()?;
+    let mut int_columns = header.boot_unix_ms.map(|_| &timestamps).into_iter().chain([&uptimes]);
+    let mut float_columns = columns.iter();
+    while let Some(mut column) = row_group.next_column()? {
+        match int_columns.next() {
+            Some(values) => column.typed::<Int64Type>().write_batch(values, None, None)?,
+            None => column.typed::<FloatType>().write_batch(
+                float_columns.next().ok_or("column count mismatch")?,
+                None,
+                None,
+            )?,
+        };
+        column.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
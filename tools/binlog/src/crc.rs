@@ -0,0 +1,38 @@
+/// CRC-32 (IEEE 802.3, reflected, polynomial 0xEDB88320) as used by zlib, PNG, ...
+///
+/// Bitwise implementation without lookup table, as it is only used for small amounts of data
+/// (persistent configuration, log records).
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0xffff_ffff, data) ^ 0xffff_ffff
+}
+
+/// Feeds `data` into a running CRC-32 register, allowing to checksum non-contiguous data.
+///
+/// Start with `0xffff_ffff` and XOR the final result with `0xffff_ffff`.
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_crc32_incremental() {
+        let crc = crc32_update(0xffff_ffff, b"12345");
+        let crc = crc32_update(crc, b"6789") ^ 0xffff_ffff;
+        assert_eq!(crc, crc32(b"123456789"));
+    }
+}
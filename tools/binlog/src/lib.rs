@@ -0,0 +1,396 @@
+//! Binary data log format of the alternator regulator
+//!
+//! A log file starts with a header describing the schema, followed by fixed size records:
+//!
+//! ```text
+//! header: magic "ALTB" | version u8 | field count u8 | header length u16 | boot time u64 (Unix ms, 0 = unknown)
+//!         | field count x (name length u8 | name) | CRC-32 u32
+//! record: sync 0xa5 0x5a | uptime u32 (ms) | field count x value f32 | CRC-32 u32
+//! ```
+//!
+//! All integers and floats are little endian. Every record carries its own CRC, so a reader can skip
+//! damaged records and resynchronize on the next sync marker, e.g. after a power loss while writing.
+//!
+//! The crate is `no_std` and shared between the firmware (writing) and the host tools (reading).
+
+#![no_std]
+
+pub mod crc;
+
+use core::fmt;
+
+use crate::crc::crc32;
+
+pub const MAGIC: [u8; 4] = *b"ALTB";
+pub const VERSION: u8 = 1;
+pub const SYNC: [u8; 2] = [0xa5, 0x5a];
+
+/// Maximum number of fields, limited by the field count byte
+pub const FIELDS_MAX: usize = u8::MAX as usize;
+
+const HEADER_FIXED_LEN: usize = 4 + 1 + 1 + 2 + 8;
+const RECORD_FIXED_LEN: usize = SYNC.len() + 4;
+const CRC_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// the output buffer is too small
+    BufferTooSmall,
+    /// too many fields or a name longer than 255 bytes
+    SchemaTooLarge,
+    /// not a binary log file
+    Magic,
+    /// binary log of an unsupported version
+    Version(u8),
+    /// the data ends within a header or record
+    Truncated,
+    /// header or record damaged
+    Checksum,
+    /// field name is not valid UTF-8
+    Utf8,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BufferTooSmall => write!(f, "buffer too small"),
+            Error::SchemaTooLarge => write!(f, "schema too large"),
+            Error::Magic => write!(f, "not a binary log file"),
+            Error::Version(v) => write!(f, "unsupported version {}", v),
+            Error::Truncated => write!(f, "data truncated"),
+            Error::Checksum => write!(f, "checksum mismatch"),
+            Error::Utf8 => write!(f, "field name is not valid UTF-8"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Length of a record with `fields` values
+pub const fn record_len(fields: usize) -> usize {
+    RECORD_FIXED_LEN + 4 * fields + CRC_LEN
+}
+
+/// Writes the file header to `buf`, returns the number of bytes written
+///
+/// `boot_unix_ms` is the wall clock time at boot, so the record uptimes can be converted to wall clock
+/// times. Use `None` if the wall clock is not known.
+pub fn write_header<'a>(
+    buf: &mut [u8],
+    boot_unix_ms: Option<u64>,
+    names: impl IntoIterator<Item = &'a str>,
+) -> Result<usize, Error> {
+    let mut pos = HEADER_FIXED_LEN;
+    let mut count = 0_usize;
+    for name in names {
+        let len = u8::try_from(name.len()).map_err(|_| Error::SchemaTooLarge)?;
+        let end = pos + 1 + name.len();
+        let field = buf.get_mut(pos..end).ok_or(Error::BufferTooSmall)?;
+        field[0] = len;
+        field[1..].copy_from_slice(name.as_bytes());
+        pos = end;
+        count += 1;
+    }
+    let count = u8::try_from(count).map_err(|_| Error::SchemaTooLarge)?;
+    let header_len = u16::try_from(pos + CRC_LEN).map_err(|_| Error::SchemaTooLarge)?;
+    if buf.len() < pos + CRC_LEN {
+        return Err(Error::BufferTooSmall);
+    }
+
+    buf[0..4].copy_from_slice(&MAGIC);
+    buf[4] = VERSION;
+    buf[5] = count;
+    buf[6..8].copy_from_slice(&header_len.to_le_bytes());
+    buf[8..16].copy_from_slice(&boot_unix_ms.unwrap_or(0).to_le_bytes());
+    let crc = crc32(&buf[..pos]);
+    buf[pos..pos + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+    Ok(pos + CRC_LEN)
+}
+
+/// Writes a record to `buf`, returns the number of bytes written
+pub fn write_record(buf: &mut [u8], uptime_ms: u32, values: &[f32]) -> Result<usize, Error> {
+    let len = record_len(values.len());
+    let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+    buf[0..2].copy_from_slice(&SYNC);
+    buf[2..6].copy_from_slice(&uptime_ms.to_le_bytes());
+    for (chunk, value) in buf[RECORD_FIXED_LEN..].chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    let crc_pos = len - CRC_LEN;
+    let crc = crc32(&buf[..crc_pos]);
+    buf[crc_pos..].copy_from_slice(&crc.to_le_bytes());
+    Ok(len)
+}
+
+/// Parsed file header
+#[derive(Debug, Clone, Copy)]
+pub struct Header<'a> {
+    pub version: u8,
+    pub field_count: usize,
+    /// wall clock time at boot (Unix ms), if known when the file was started
+    pub boot_unix_ms: Option<u64>,
+    /// length of the header in bytes, the records start here
+    pub len: usize,
+    names: &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    /// Parses and checks the header at the start of `data`
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let fixed = data.get(..HEADER_FIXED_LEN).ok_or(Error::Truncated)?;
+        if fixed[0..4] != MAGIC {
+            return Err(Error::Magic);
+        }
+        if fixed[4] != VERSION {
+            return Err(Error::Version(fixed[4]));
+        }
+        let field_count = fixed[5] as usize;
+        let len = u16::from_le_bytes([fixed[6], fixed[7]]) as usize;
+        let boot_unix_ms = u64::from_le_bytes(fixed[8..16].try_into().unwrap());
+
+        if len < HEADER_FIXED_LEN + CRC_LEN {
+            return Err(Error::Checksum);
+        }
+        let header = data.get(..len).ok_or(Error::Truncated)?;
+        let crc_pos = len - CRC_LEN;
+        if crc32(&header[..crc_pos]) != u32::from_le_bytes(header[crc_pos..].try_into().unwrap()) {
+            return Err(Error::Checksum);
+        }
+
+        let header = Self {
+            version: fixed[4],
+            field_count,
+            boot_unix_ms: (boot_unix_ms != 0).then_some(boot_unix_ms),
+            len,
+            names: &header[HEADER_FIXED_LEN..crc_pos],
+        };
+        // check the names once, so `names()` does not need to report errors
+        let mut count = 0;
+        for name in header.raw_names() {
+            core::str::from_utf8(name.ok_or(Error::Checksum)?).map_err(|_| Error::Utf8)?;
+            count += 1;
+        }
+        if count != field_count {
+            return Err(Error::Checksum);
+        }
+        Ok(header)
+    }
+
+    /// Field names, in the order of the record values
+    pub fn names(&self) -> impl Iterator<Item = &'a str> + 'a {
+        self.raw_names()
+            .map(|name| core::str::from_utf8(name.unwrap_or_default()).unwrap_or_default())
+    }
+
+    /// Length of each record in bytes
+    pub fn record_len(&self) -> usize {
+        record_len(self.field_count)
+    }
+
+    /// Iterates over the records following the header in `data`
+    pub fn records(&self, data: &'a [u8]) -> Records<'a> {
+        Records {
+            data,
+            pos: self.len.min(data.len()),
+            field_count: self.field_count,
+        }
+    }
+
+    fn raw_names(&self) -> impl Iterator<Item = Option<&'a [u8]>> + 'a {
+        let mut rest = self.names;
+        core::iter::from_fn(move || {
+            let (&len, tail) = rest.split_first()?;
+            match tail.get(..len as usize) {
+                Some(name) => {
+                    rest = &tail[len as usize..];
+                    Some(Some(name))
+                }
+                None => {
+                    rest = &[];
+                    Some(None)
+                }
+            }
+        })
+    }
+}
+
+/// A single valid record
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub uptime_ms: u32,
+    values: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    pub fn values(&self) -> impl Iterator<Item = f32> + 'a {
+        self.values
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+    }
+}
+
+/// Damaged data found between records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Corrupt {
+    /// file offset of the damaged data
+    pub offset: usize,
+    /// number of bytes skipped
+    pub len: usize,
+    pub error: Error,
+}
+
+/// Iterator over the records of a file
+///
+/// Damaged data is reported once as `Err(Corrupt)`, then reading continues at the next valid record.
+pub struct Records<'a> {
+    data: &'a [u8],
+    pos: usize,
+    field_count: usize,
+}
+
+impl<'a> Records<'a> {
+    fn record_at(&self, pos: usize) -> Result<Record<'a>, Error> {
+        let record = self
+            .data
+            .get(pos..pos + record_len(self.field_count))
+            .ok_or(Error::Truncated)?;
+        if record[0..2] != SYNC {
+            return Err(Error::Checksum);
+        }
+        let crc_pos = record.len() - CRC_LEN;
+        if crc32(&record[..crc_pos]) != u32::from_le_bytes(record[crc_pos..].try_into().unwrap()) {
+            return Err(Error::Checksum);
+        }
+        Ok(Record {
+            uptime_ms: u32::from_le_bytes(record[2..6].try_into().unwrap()),
+            values: &record[RECORD_FIXED_LEN..crc_pos],
+        })
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, Corrupt>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let error = match self.record_at(self.pos) {
+            Ok(record) => {
+                self.pos += record_len(self.field_count);
+                return Some(Ok(record));
+            }
+            Err(error) => error,
+        };
+
+        // skip to the next valid record
+        let offset = self.pos;
+        let mut pos = offset + 1;
+        while pos < self.data.len() && self.record_at(pos).is_err() {
+            pos += 1;
+        }
+        self.pos = pos;
+        Some(Err(Corrupt {
+            offset,
+            len: pos - offset,
+            error,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 3] = ["RPM", "Field Current", "Bat Voltage"];
+
+    fn write_file(buf: &mut [u8], records: &[(u32, [f32; 3])]) -> usize {
+        let mut pos = write_header(buf, Some(1_700_000_000_000), NAMES).unwrap();
+        for (uptime, values) in records {
+            pos += write_record(&mut buf[pos..], *uptime, values).unwrap();
+        }
+        pos
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut buf = [0_u8; 256];
+        let len = write_file(&mut buf, &[(1000, [1500., 2.5, 13.8]), (1100, [1510., f32::NAN, 13.9])]);
+
+        let header = Header::parse(&buf[..len]).unwrap();
+        assert_eq!(header.field_count, 3);
+        assert_eq!(header.boot_unix_ms, Some(1_700_000_000_000));
+        assert!(header.names().eq(NAMES));
+
+        let records: [Record; 2] = {
+            let mut it = header.records(&buf[..len]).map(Result::unwrap);
+            [it.next().unwrap(), it.next().unwrap()]
+        };
+        assert_eq!(records[0].uptime_ms, 1000);
+        assert!(records[0].values().eq([1500., 2.5, 13.8]));
+        assert_eq!(records[1].uptime_ms, 1100);
+        assert!(records[1].values().nth(1).unwrap().is_nan());
+        assert_eq!(header.records(&buf[..len]).count(), 2);
+    }
+
+    #[test]
+    fn test_unknown_boot_time() {
+        let mut buf = [0_u8; 64];
+        let len = write_header(&mut buf, None, NAMES).unwrap();
+        assert_eq!(Header::parse(&buf[..len]).unwrap().boot_unix_ms, None);
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let mut buf = [0_u8; 64];
+        let len = write_header(&mut buf, None, NAMES).unwrap();
+        assert_eq!(Header::parse(&buf[..len - 1]).unwrap_err(), Error::Truncated);
+        buf[20] ^= 1;
+        assert_eq!(Header::parse(&buf[..len]).unwrap_err(), Error::Checksum);
+        buf[0] = b'X';
+        assert_eq!(Header::parse(&buf[..len]).unwrap_err(), Error::Magic);
+    }
+
+    #[test]
+    fn test_buffer_too_small() {
+        let mut buf = [0_u8; 20];
+        assert_eq!(write_header(&mut buf, None, NAMES).unwrap_err(), Error::BufferTooSmall);
+        assert_eq!(write_record(&mut buf, 0, &[0.; 3]).unwrap_err(), Error::BufferTooSmall);
+    }
+
+    #[test]
+    fn test_resync_after_damage() {
+        let mut buf = [0_u8; 256];
+        let values = [1., 2., 3.];
+        let len = write_file(&mut buf, &[(1, values), (2, values), (3, values)]);
+        let record_len = record_len(3);
+
+        // damage the second record
+        let second = Header::parse(&buf[..len]).unwrap().len + record_len;
+        buf[second + 8] ^= 0xff;
+
+        let header = Header::parse(&buf[..len]).unwrap();
+        let mut records = header.records(&buf[..len]);
+        assert_eq!(records.next().unwrap().unwrap().uptime_ms, 1);
+        let corrupt = records.next().unwrap().unwrap_err();
+        assert_eq!(corrupt.offset, second);
+        assert_eq!(corrupt.len, record_len);
+        assert_eq!(corrupt.error, Error::Checksum);
+        assert_eq!(records.next().unwrap().unwrap().uptime_ms, 3);
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let mut buf = [0_u8; 256];
+        let len = write_file(&mut buf, &[(1, [1., 2., 3.]), (2, [1., 2., 3.])]);
+        let header = Header::parse(&buf[..len]).unwrap();
+
+        let mut records = header.records(&buf[..len - 3]);
+        assert!(records.next().unwrap().is_ok());
+        let corrupt = records.next().unwrap().unwrap_err();
+        assert_eq!(corrupt.error, Error::Truncated);
+        assert_eq!(corrupt.len, header.record_len() - 3);
+        assert!(records.next().is_none());
+    }
+}
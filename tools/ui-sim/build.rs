@@ -0,0 +1,55 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use cc::Build;
+use cmake::Config;
+
+const LVGL_DIR: &str = "../../lvgl_rust_sys/lvgl";
+
+/// Builds LVGL like the firmware build script, with the host compiler
+fn main() {
+    cmake_lvgl();
+    compile_lvgl_inline_wrappers();
+}
+
+/// Generates the C wrappers of the inline functions of LVGL into `OUT_DIR` and compiles them
+///
+/// The bindings of `lvgl_rust_sys` call these wrappers, but its build script leaves them in a temporary
+/// directory shared by all builds.
+fn compile_lvgl_inline_wrappers() {
+    let lvgl = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join(LVGL_DIR);
+    let wrappers = PathBuf::from(env::var("OUT_DIR").unwrap()).join("extern");
+    bindgen::Builder::default()
+        .header(lvgl.join("lvgl.h").to_string_lossy())
+        .clang_arg(format!("-I{}", lvgl.display()))
+        .wrap_static_fns(true)
+        .wrap_static_fns_path(&wrappers)
+        .generate()
+        .expect("Failed to generate the LVGL inline wrappers");
+
+    Build::new()
+        .include(&lvgl)
+        .file(wrappers.with_extension("c"))
+        .flag("-O2")
+        .flag("-fno-strict-aliasing")
+        .compile("lvgl-inline-wrappers");
+}
+
+fn cmake_lvgl() {
+    let dst = Config::new(LVGL_DIR)
+        .define("CMAKE_BUILD_TYPE", "Release")
+        .define("BUILD_SHARED_LIBS", "OFF")
+        .define("LV_LVGL_H_INCLUDE_SIMPLE", "ON")
+        .define("LV_CONF_INCLUDE_SIMPLE", "ON")
+        .define("LV_CONF_PATH", "lv_conf.h")
+        .define("LV_CONF_ERROR_STR", "NULL")
+        .define("LV_CONF_ERROR_INCLUDE_SIMPLE", "ON")
+        .define("LV_CONF_ERROR_THROW", "ON")
+        .cflag("-O2")
+        .cflag("-fno-strict-aliasing")
+        .profile("Release")
+        .build();
+
+    println!("cargo:rustc-link-search=native={}/lib", dst.display());
+    println!("cargo:rustc-link-lib=static=lvgl");
+}
@@ -0,0 +1,79 @@
+//! Stand-ins for the board drivers named by the firmware modules
+
+pub mod driver {
+    pub mod display {
+        use embedded_graphics::primitives::Rectangle;
+
+        pub const WIDTH: usize = 320;
+        pub const HEIGHT: usize = 240;
+
+        #[derive(Debug)]
+        pub enum DisplayError {
+            OutOfBounds,
+        }
+
+        /// Framebuffer in place of the display
+        pub struct DisplayDriver {
+            /// RGB565, row by row
+            pub pixels: Vec<u16>,
+        }
+
+        impl DisplayDriver {
+            pub fn new() -> Self {
+                Self {
+                    pixels: vec![0; WIDTH * HEIGHT],
+                }
+            }
+
+            pub fn set_brightness(&mut self, _brightness: u8) {}
+
+            /// Copies big-endian RGB565 `pixels` to `area`, like the firmware sends them to the display
+            pub async fn write_pixels(&mut self, area: &Rectangle, pixels: &[u8]) -> Result<(), DisplayError> {
+                let width = area.size.width as usize;
+                for (i, color) in pixels.chunks_exact(2).enumerate() {
+                    let x = area.top_left.x as usize + i % width;
+                    let y = area.top_left.y as usize + i / width;
+                    if x >= WIDTH || y >= HEIGHT {
+                        return Err(DisplayError::OutOfBounds);
+                    }
+                    self.pixels[y * WIDTH + x] = u16::from_be_bytes([color[0], color[1]]);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub mod io {
+    pub mod spi2 {
+        use core::convert::Infallible;
+        use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+        use embassy_sync::mutex::Mutex;
+        use embedded_hal::delay::DelayNs;
+        use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+
+        /// taken by the UI like on the board, nothing else uses it
+        pub static BUS_LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+        /// only named by the data logger, the simulator has no SD card
+        pub type SdCardType = embedded_sdmmc::SdCard<NoSpi, NoDelay>;
+
+        pub struct NoSpi;
+
+        impl ErrorType for NoSpi {
+            type Error = Infallible;
+        }
+
+        impl SpiDevice for NoSpi {
+            fn transaction(&mut self, _operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+                Ok(())
+            }
+        }
+
+        pub struct NoDelay;
+
+        impl DelayNs for NoDelay {
+            fn delay_ns(&mut self, _ns: u32) {}
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Host simulator of the regulator UI
+//!
+//! The UI and application modules of the firmware are compiled for the host, with stand-ins for the board
+//! drivers. [`Simulator`] renders the pages with the same code as the firmware, fed by fake process data,
+//! into a framebuffer instead of the display.
+
+#![feature(int_from_ascii)]
+#![feature(type_alias_impl_trait)]
+#![feature(impl_trait_in_assoc_type)]
+// most of the application is not used by the UI
+#![allow(dead_code)]
+
+#[path = "../../../src/fmt.rs"]
+mod fmt;
+
+#[path = "../../../src/app/mod.rs"]
+mod app;
+mod board;
+#[path = "../../../src/ui/mod.rs"]
+mod ui;
+mod util;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use embassy_futures::block_on;
+use lvgl_rust_sys::lv_timer_handler;
+
+use app::history::HISTORY;
+use app::shared::{set_page, CardState, PROCESS_DATA, REGULATOR_MODE};
+use board::driver::display::DisplayDriver;
+use ui::{refresh, Ui};
+
+pub use app::shared::Page;
+pub use board::driver::display::{HEIGHT, WIDTH};
+
+/// LVGL has global state, so there is only one simulator per process
+static CREATED: AtomicBool = AtomicBool::new(false);
+
+/// All pages in the order of navigation
+pub fn pages() -> impl Iterator<Item = Page> {
+    core::iter::successors(Some(Page::Overview), |page| Some(page.next())).take(Page::COUNT as usize)
+}
+
+/// Renders the firmware UI into a framebuffer
+pub struct Simulator {
+    ui: Ui<'static>,
+    display: DisplayDriver,
+}
+
+impl Simulator {
+    /// Initializes LVGL and creates the widgets, panics if called twice
+    pub fn new() -> Self {
+        assert!(!CREATED.swap(true, Ordering::Relaxed), "only one simulator per process");
+        fake_process_data();
+        let ui = Ui::create().expect("Failed to create the widgets");
+        Self {
+            ui,
+            display: DisplayDriver::new(),
+        }
+    }
+
+    /// Shows `page` and renders it, returns the RGB565 pixels of the screen row by row
+    pub fn render(&mut self, page: Page) -> &[u16] {
+        set_page(page);
+        self.ui.update(None);
+        unsafe { lv_timer_handler() };
+        block_on(refresh(&mut self.display));
+        &self.display.pixels
+    }
+}
+
+/// Process data of a charging alternator, always the same so the rendered images can be compared
+fn fake_process_data() {
+    let pd = &PROCESS_DATA;
+    pd.rpm.store(2450., Ordering::Relaxed);
+    pd.pulse_rate.store(490., Ordering::Relaxed);
+    pd.temperature.store(62., Ordering::Relaxed);
+    pd.alt_current.store(48.5, Ordering::Relaxed);
+    pd.bat_current.store(42.3, Ordering::Relaxed);
+    pd.bat_soc.store(71., Ordering::Relaxed);
+    pd.bat_voltage.store(13.92, Ordering::Relaxed);
+    pd.input_voltage.store(14.1, Ordering::Relaxed);
+    pd.field_voltage.store(6.8, Ordering::Relaxed);
+    pd.field_current.store(2.35, Ordering::Relaxed);
+    pd.pps_temperature.store(41., Ordering::Relaxed);
+    pd.ble_rate.store(1., Ordering::Relaxed);
+    pd.target_factor.store(0.85, Ordering::Relaxed);
+    pd.card_state.store(CardState::Ok as u8, Ordering::Relaxed);
+    REGULATOR_MODE.lock(|rm| {
+        let mut rm = rm.borrow_mut();
+        rm.clear();
+        rm.push_str("Charging").ok();
+    });
+
+    // ten minutes of rising voltage and falling current
+    for s in 0..600 {
+        let t = s as f32 / 600.;
+        let sample = [13.2 + 0.7 * t, 60. - 18. * t, 2.8 - 0.5 * t, 2450.];
+        HISTORY.lock(|h| h.borrow_mut().add(&sample));
+    }
+}
+
+/// Writes RGB565 `pixels` of the screen to a PNG file
+pub fn write_png(path: &Path, pixels: &[u16]) -> std::io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = pixels.iter().flat_map(|&p| to_rgb(p)).collect();
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+/// Reads a PNG file written by [`write_png`], returns the RGB pixels
+pub fn read_png(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut reader = png::Decoder::new(BufReader::new(File::open(path)?)).read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    data.truncate(info.buffer_size());
+    Ok(data)
+}
+
+/// RGB888 of an RGB565 pixel, as in PNG files
+pub fn to_rgb(pixel: u16) -> [u8; 3] {
+    let r = (pixel >> 11) as u8 & 0x1f;
+    let g = (pixel >> 5) as u8 & 0x3f;
+    let b = pixel as u8 & 0x1f;
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
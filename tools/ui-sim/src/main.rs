@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use ui_sim::{pages, write_png, Simulator};
+
+/// Renders all pages of the regulator UI to PNG files
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// directory of the PNG files, one per page
+    #[arg(default_value = ".")]
+    out_dir: PathBuf,
+}
+
+fn main() -> std::io::Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+    std::fs::create_dir_all(&args.out_dir)?;
+
+    let mut simulator = Simulator::new();
+    for page in pages() {
+        let path = args.out_dir.join(format!("{}.png", page.title().to_lowercase()));
+        write_png(&path, simulator.render(page))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
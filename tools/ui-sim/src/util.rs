@@ -0,0 +1,4 @@
+//! The parts of the firmware utilities used by the application modules
+
+#[path = "../../../src/util/zc.rs"]
+pub mod zc;
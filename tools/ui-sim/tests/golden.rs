@@ -0,0 +1,63 @@
+//! Compares the rendered pages to the images in `golden/`
+//!
+//! After an intended change of the UI, `UPDATE_GOLDEN=1 cargo test` replaces the images. A page that
+//! differs is written to `target/golden/` for inspection.
+
+use std::path::{Path, PathBuf};
+
+use ui_sim::{pages, read_png, to_rgb, write_png, Simulator};
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("golden")
+}
+
+fn actual_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden")
+}
+
+/// LVGL is global, so all pages are rendered in one test
+#[test]
+fn test_pages_match_golden_images() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut simulator = Simulator::new();
+    let mut failed = Vec::new();
+    let mut missing = Vec::new();
+    for page in pages() {
+        let name = format!("{}.png", page.title().to_lowercase());
+        let pixels = simulator.render(page);
+        let golden = golden_dir().join(&name);
+        if update {
+            std::fs::create_dir_all(golden_dir()).unwrap();
+            write_png(&golden, pixels).unwrap();
+            continue;
+        }
+
+        if !golden.exists() {
+            missing.push(golden.display().to_string());
+            continue;
+        }
+        let rgb: Vec<u8> = pixels.iter().flat_map(|&p| to_rgb(p)).collect();
+        match read_png(&golden) {
+            Ok(expected) if expected == rgb => {}
+            result => {
+                if let Err(e) = result {
+                    eprintln!("{}: {}", golden.display(), e);
+                }
+                std::fs::create_dir_all(actual_dir()).unwrap();
+                write_png(&actual_dir().join(&name), pixels).unwrap();
+                failed.push(name);
+            }
+        }
+    }
+    assert!(
+        missing.is_empty(),
+        "golden images missing: {:?}, create them with `UPDATE_GOLDEN=1 cargo test` in tools/ui-sim and commit them",
+        missing
+    );
+    assert!(
+        failed.is_empty(),
+        "pages differ from the golden images, see {}: {:?}",
+        actual_dir().display(),
+        failed
+    );
+}